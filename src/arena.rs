@@ -0,0 +1,241 @@
+use crate::{RefIndex, Shelf, Storage};
+
+/// Type holding a shelf of `T` values.
+///
+/// This trait is implemented by the arenas defined with the
+/// [`arena!`](crate::arena!) macro for each of their shelves, so that values
+/// can be accessed through a `Ref<T>` without naming the shelf holding them.
+pub trait HasShelf<T> {
+	type Storage: Storage<Value = T>;
+
+	type Index: RefIndex + 'static;
+
+	fn shelf(&self) -> &Shelf<Self::Storage, Self::Index>;
+
+	fn shelf_mut(&mut self) -> &mut Shelf<Self::Storage, Self::Index>;
+}
+
+/// Visitor of the shelves of an arena.
+///
+/// The `for_each_shelf` method of the arenas defined with the
+/// [`arena!`](crate::arena!) macro calls [`Self::visit`] on each of their
+/// shelves, in declaration order. Since shelves store values of different
+/// types, a visitor usually implements this trait for any storage providing
+/// the operations it needs.
+pub trait VisitShelf<S, I: RefIndex> {
+	/// Visits the shelf stored in the field `name` of the arena.
+	fn visit(&mut self, name: &'static str, shelf: &Shelf<S, I>);
+}
+
+/// Defines an arena grouping several shelves, keyed by value type.
+///
+/// Each field of the arena is a `Shelf<S>` storing values of a different
+/// type `T`, declared as `field: Shelf<S> => T`, or `field: Shelf<S, I> => T`
+/// to reference its values with a `Ref<T, I>` index type other than `usize`.
+/// The arena implements [`HasShelf<T>`] for each of them, and provides
+/// the `shelf`, `shelf_mut`, `get`, `get_mut`, `insert`, `remove` and `iter`
+/// methods, dispatching to the right shelf according to the type of the
+/// value or reference.
+/// Every shelf can be visited at once with the `for_each_shelf` method,
+/// given a [`VisitShelf`] implementation for each of them.
+///
+/// Attributes are passed through to the struct and its fields, so the arena
+/// can derive `Default`, or `Serialize` and `Deserialize` to serialize all
+/// its shelves at once.
+///
+/// ```
+/// use shelves::{FreeList, Ref, Shelf, VisitShelf};
+///
+/// struct Type(&'static str);
+///
+/// struct Function {
+///     name: &'static str,
+///     result: Ref<Type>,
+/// }
+///
+/// shelves::arena! {
+///     #[derive(Default)]
+///     pub struct Program {
+///         pub types: Shelf<Vec<Type>> => Type,
+///         pub functions: Shelf<FreeList<Function>, u32> => Function,
+///     }
+/// }
+///
+/// let mut program = Program::default();
+/// let result = program.insert(Type("i32"));
+/// let f: Ref<Function, u32> = program.insert(Function { name: "main", result });
+///
+/// let function = program.get(f).unwrap();
+/// assert_eq!(function.name, "main");
+/// assert_eq!(program.get(function.result).unwrap().0, "i32");
+/// assert_eq!(program.len(), 2);
+///
+/// struct Count(Vec<(&'static str, usize)>);
+///
+/// impl<S: shelves::Storage, I: shelves::RefIndex> VisitShelf<S, I> for Count {
+///     fn visit(&mut self, name: &'static str, shelf: &Shelf<S, I>) {
+///         self.0.push((name, shelf.len()))
+///     }
+/// }
+///
+/// let mut count = Count(Vec::new());
+/// program.for_each_shelf(&mut count);
+/// assert_eq!(count.0, [("types", 1), ("functions", 1)]);
+/// ```
+///
+/// Two shelves of the same arena cannot store the same value type.
+#[macro_export]
+macro_rules! arena {
+	(
+		$(#[$meta:meta])*
+		$vis:vis struct $name:ident {
+			$(
+				$(#[$field_meta:meta])*
+				$field_vis:vis $field:ident : Shelf<$storage:ty $(, $index:ty)?> => $value:ty
+			),* $(,)?
+		}
+	) => {
+		$(#[$meta])*
+		$vis struct $name {
+			$(
+				$(#[$field_meta])*
+				$field_vis $field: $crate::Shelf<$storage $(, $index)?>,
+			)*
+		}
+
+		$(
+			impl $crate::arena::HasShelf<$value> for $name {
+				type Storage = $storage;
+
+				type Index = $crate::arena!(@index $($index)?);
+
+				fn shelf(&self) -> &$crate::Shelf<$storage, Self::Index> {
+					&self.$field
+				}
+
+				fn shelf_mut(&mut self) -> &mut $crate::Shelf<$storage, Self::Index> {
+					&mut self.$field
+				}
+			}
+		)*
+
+		#[allow(dead_code)]
+		impl $name {
+			/// Returns the total number of values stored in the arena.
+			pub fn len(&self) -> usize {
+				0 $(+ self.$field.len())*
+			}
+
+			/// Checks if the arena stores no value.
+			pub fn is_empty(&self) -> bool {
+				true $(&& self.$field.is_empty())*
+			}
+
+			/// Returns the shelf storing `T` values.
+			pub fn shelf<T>(
+				&self,
+			) -> &$crate::Shelf<
+				<Self as $crate::arena::HasShelf<T>>::Storage,
+				<Self as $crate::arena::HasShelf<T>>::Index,
+			>
+			where
+				Self: $crate::arena::HasShelf<T>,
+			{
+				$crate::arena::HasShelf::<T>::shelf(self)
+			}
+
+			/// Returns the shelf storing `T` values, mutably.
+			pub fn shelf_mut<T>(
+				&mut self,
+			) -> &mut $crate::Shelf<
+				<Self as $crate::arena::HasShelf<T>>::Storage,
+				<Self as $crate::arena::HasShelf<T>>::Index,
+			>
+			where
+				Self: $crate::arena::HasShelf<T>,
+			{
+				$crate::arena::HasShelf::<T>::shelf_mut(self)
+			}
+
+			/// Returns a reference to the value associated to the given reference.
+			pub fn get<T>(
+				&self,
+				r: $crate::Ref<T, <Self as $crate::arena::HasShelf<T>>::Index>,
+			) -> Option<&T>
+			where
+				Self: $crate::arena::HasShelf<T>,
+			{
+				self.shelf::<T>().get(r)
+			}
+
+			/// Returns a mutable reference to the value associated to the given
+			/// reference.
+			pub fn get_mut<T>(
+				&mut self,
+				r: $crate::Ref<T, <Self as $crate::arena::HasShelf<T>>::Index>,
+			) -> Option<&mut T>
+			where
+				Self: $crate::arena::HasShelf<T>,
+				<Self as $crate::arena::HasShelf<T>>::Storage: $crate::StorageMut,
+			{
+				self.shelf_mut::<T>().get_mut(r)
+			}
+
+			/// Inserts a new value in its shelf and returns its reference.
+			pub fn insert<T>(
+				&mut self,
+				value: T,
+			) -> $crate::Ref<T, <Self as $crate::arena::HasShelf<T>>::Index>
+			where
+				Self: $crate::arena::HasShelf<T>,
+				<Self as $crate::arena::HasShelf<T>>::Storage: $crate::StorageAllocate,
+			{
+				self.shelf_mut::<T>().insert(value)
+			}
+
+			/// Removes the value associated to the given reference, and returns it.
+			pub fn remove<T>(
+				&mut self,
+				r: $crate::Ref<T, <Self as $crate::arena::HasShelf<T>>::Index>,
+			) -> Option<T>
+			where
+				Self: $crate::arena::HasShelf<T>,
+				<Self as $crate::arena::HasShelf<T>>::Storage: $crate::StorageRemove,
+			{
+				self.shelf_mut::<T>().remove(r)
+			}
+
+			/// Returns an iterator over the references and values of the shelf
+			/// storing `T` values.
+			pub fn iter<T>(
+				&self,
+			) -> $crate::shelf::Iter<
+				'_,
+				<Self as $crate::arena::HasShelf<T>>::Storage,
+				<Self as $crate::arena::HasShelf<T>>::Index,
+			>
+			where
+				Self: $crate::arena::HasShelf<T>,
+				<Self as $crate::arena::HasShelf<T>>::Storage: $crate::StorageIter,
+			{
+				self.shelf::<T>().iter()
+			}
+
+			/// Visits every shelf of the arena, in declaration order.
+			pub fn for_each_shelf<V>(&self, visitor: &mut V)
+			where
+				$(V: $crate::arena::VisitShelf<$storage, $crate::arena!(@index $($index)?)>,)*
+			{
+				$(
+					$crate::arena::VisitShelf::<$storage, $crate::arena!(@index $($index)?)>::visit(
+						visitor,
+						stringify!($field),
+						&self.$field,
+					);
+				)*
+			}
+		}
+	};
+	(@index) => { usize };
+	(@index $index:ty) => { $index };
+}
@@ -1,58 +1,71 @@
-use crate::{Ref, Shelf, Storage, StorageAllocateConst, StorageMut, StorageRemove};
+use crate::dictionary::{self, RefTree, Slot};
+use crate::shelf::{IntoIter, Iter};
+use crate::{
+	Collision, Comparator, DefaultComparator, DictionaryReport, InsertError, Ref, RefIndex, Remap,
+	Shelf, Storage, StorageAllocateConst, StorageCompact, StorageIntoIter, StorageIter, StorageMut,
+	StorageRemove,
+};
 use std::borrow::{Borrow, BorrowMut};
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 
-/// Bidirectional mapping between `T` and `Ref<T>`.
-pub struct BTreeConstDictionary<S: Storage> {
-	map: RefCell<BTreeMap<S::Value, Ref<S::Value>>>,
-	values: Shelf<S>,
+/// Bidirectional mapping between `T` and `Ref<T, I>`.
+///
+/// Each value is stored only once, in the shelf of the dictionary.
+/// Values are mapped to their reference by a balanced search tree of
+/// references, ordered by comparing the values through the shelf.
+/// Looking up, inserting or removing a value takes a logarithmic time.
+/// The tree is never borrowed while comparing values, so that the comparator
+/// may itself look up or insert values.
+pub struct BTreeConstDictionary<S: Storage, C = DefaultComparator, I: RefIndex = usize> {
+	refs: RefCell<RefTree<S::Value, I>>,
+	comparator: C,
+	values: Shelf<S, I>,
 }
 
-impl<S: Storage + Default> Default for BTreeConstDictionary<S> {
+impl<S: Storage + Default, C: Default, I: RefIndex> Default for BTreeConstDictionary<S, C, I> {
 	fn default() -> Self {
-		Self {
-			map: RefCell::new(BTreeMap::new()),
-			values: Shelf::default(),
-		}
+		Self::with_comparator(S::default(), C::default())
 	}
 }
 
 impl<S: Storage> BTreeConstDictionary<S> {
 	/// Creates a new empty dictionary.
 	pub fn new(storage: S) -> Self {
+		Self::with_comparator(storage, DefaultComparator)
+	}
+}
+
+impl<S: Storage, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	/// Creates a new empty dictionary using the given comparator to order
+	/// the values.
+	pub fn with_comparator(storage: S, comparator: C) -> Self {
 		Self {
-			map: RefCell::new(BTreeMap::new()),
+			refs: RefCell::default(),
+			comparator,
 			values: Shelf::new(storage),
 		}
 	}
 
-	pub fn len(&self) -> usize {
-		self.map.borrow().len()
-	}
-
-	pub fn is_empty(&self) -> bool {
-		self.map.borrow().is_empty()
-	}
-
-	#[allow(clippy::type_complexity)]
-	pub fn into_parts(self) -> (RefCell<BTreeMap<S::Value, Ref<S::Value>>>, Shelf<S>) {
-		(self.map, self.values)
+	/// Returns the comparator of the dictionary.
+	pub fn comparator(&self) -> &C {
+		&self.comparator
 	}
 
-	pub fn into_map(self) -> BTreeMap<S::Value, Ref<S::Value>> {
-		self.map.into_inner()
+	pub fn len(&self) -> usize {
+		self.refs.borrow().len()
 	}
 
-	pub fn as_map(&self) -> &RefCell<BTreeMap<S::Value, Ref<S::Value>>> {
-		&self.map
+	pub fn is_empty(&self) -> bool {
+		self.refs.borrow().is_empty()
 	}
 
-	pub fn into_shelf(self) -> Shelf<S> {
+	pub fn into_shelf(self) -> Shelf<S, I> {
 		self.values
 	}
 
-	pub fn as_shelf(&self) -> &Shelf<S> {
+	pub fn as_shelf(&self) -> &Shelf<S, I> {
 		&self.values
 	}
 
@@ -64,84 +77,702 @@ impl<S: Storage> BTreeConstDictionary<S> {
 		self.values.as_storage()
 	}
 
-	/// Borrows the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow<T>(&self, r: Ref<T>) -> Option<&T>
+	/// Borrows the definition (`T`) associated to the given term (`Ref<T, I>`).
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.borrow(r)
 	}
 
-	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get<T>(&self, r: Ref<T>) -> Option<&S::Value>
+	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.get(r)
 	}
+
+	/// Returns the reference (`Ref<T, I>`) associated to the given definition, if any.
+	///
+	/// The definition may be any borrowed form of the stored value type,
+	/// which allows querying the dictionary without inserting or even
+	/// allocating the value.
+	pub fn get_ref<Q>(&self, value: &Q) -> Option<Ref<S::Value, I>>
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.search(value).ok()
+	}
+
+	/// Searches the given value, walking down the tree one node at a time so
+	/// that values are compared once the tree is released. The search starts
+	/// over if the tree was modified in the meantime.
+	///
+	/// Returns the reference of the value if found, or the slot where it
+	/// should be inserted and the version of the tree it is missing from
+	/// otherwise.
+	fn search<Q>(&self, value: &Q) -> Result<Ref<S::Value, I>, (Slot, usize)>
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		let mut last: Option<(usize, usize, Ordering)> = None;
+		loop {
+			let (version, node, r) = {
+				let refs = self.refs.borrow();
+				let version = refs.version();
+				let node = match last {
+					Some((v, node, ordering)) if v == version => refs.child(node, ordering),
+					_ => refs.root(),
+				};
+
+				match node {
+					Ok(node) => (version, node, refs.get(node)),
+					Err(slot) => return Err((slot, version)),
+				}
+			};
+
+			match dictionary::compare(&self.values, &self.comparator, r, value) {
+				Ordering::Equal => return Ok(r),
+				ordering => last = Some((version, node, ordering)),
+			}
+		}
+	}
+
+	/// Returns the first node of the tree whose value is greater than the
+	/// given value, walking down the tree as [`Self::search`] does.
+	fn upper_bound<Q>(&self, value: &Q) -> Option<usize>
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		let mut last: Option<(usize, usize, Ordering, Option<usize>)> = None;
+		loop {
+			let (version, node, r, bound) = {
+				let refs = self.refs.borrow();
+				let version = refs.version();
+				let (node, bound) = match last {
+					Some((v, node, ordering, bound)) if v == version => {
+						(refs.child(node, ordering).ok(), bound)
+					}
+					_ => (refs.root().ok(), None),
+				};
+
+				match node {
+					Some(node) => (version, node, refs.get(node), bound),
+					None => return bound,
+				}
+			};
+
+			let ordering = dictionary::compare(&self.values, &self.comparator, r, value);
+			let bound = match ordering {
+				Ordering::Greater => Some(node),
+				_ => bound,
+			};
+
+			last = Some((version, node, ordering, bound))
+		}
+	}
+
+	/// Checks if the given definition is registered in the dictionary.
+	pub fn contains<Q>(&self, value: &Q) -> bool
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.get_ref(value).is_some()
+	}
+}
+
+impl<S: Storage, I: RefIndex> BTreeConstDictionary<S, DefaultComparator, I> {
+	/// Returns the map from the values of the dictionary to their reference,
+	/// borrowing the values.
+	///
+	/// The map is built in `O(n)` time. It is only available with the default
+	/// comparator, so that it orders the values as the dictionary does.
+	pub fn as_map(&self) -> BTreeMap<&S::Value, Ref<S::Value, I>>
+	where
+		S: StorageIter,
+		S::Value: Ord,
+	{
+		self.values.iter().map(|(r, value)| (value, r)).collect()
+	}
+
+	/// Turns the dictionary into the map from its values to their reference.
+	///
+	/// The map is built in `O(n)` time.
+	pub fn into_map(self) -> BTreeMap<S::Value, Ref<S::Value, I>>
+	where
+		S: StorageIntoIter,
+		S::Value: Ord,
+	{
+		self.values
+			.into_iter()
+			.map(|(r, value)| (value, r))
+			.collect()
+	}
+
+	/// Splits the dictionary into the map from its values to their reference,
+	/// and its shelf.
+	///
+	/// Every value is cloned into the map, in `O(n)` time, so that it is
+	/// stored twice. Use [`Self::as_map`] to only borrow the values.
+	#[allow(clippy::type_complexity)]
+	pub fn into_parts(self) -> (BTreeMap<S::Value, Ref<S::Value, I>>, Shelf<S, I>)
+	where
+		S: StorageIter,
+		S::Value: Ord + Clone,
+	{
+		let map = self
+			.values
+			.iter()
+			.map(|(r, value)| (value.clone(), r))
+			.collect();
+		(map, self.values)
+	}
+}
+
+impl<S: Storage, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, ordered by definition.
+	///
+	/// The index is only borrowed while looking for the next definition, so
+	/// values can be inserted in the dictionary during the iteration.
+	pub fn ordered_iter(&self) -> OrderedIter<'_, S, C, I>
+	where
+		C: Comparator<S::Value>,
+	{
+		OrderedIter {
+			dictionary: self,
+			last: None,
+		}
+	}
+}
+
+impl<S: StorageIter, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, in storage order.
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		self.values.iter()
+	}
+
+	/// Checks that the value-to-reference index of the dictionary is
+	/// consistent with its shelf.
+	pub fn validate(&self) -> DictionaryReport<S::Value, I>
+	where
+		C: Comparator<S::Value>,
+	{
+		DictionaryReport::new(
+			&self.values,
+			self.refs.borrow().iter(),
+			|value| self.get_ref(value),
+			|a, b| self.comparator.compare(a, b).is_eq(),
+		)
+	}
+
+	/// Rebuilds the value-to-reference index of the dictionary from its
+	/// shelf.
+	///
+	/// Only the first of several equal values, in storage order, is
+	/// registered. The others stay in the shelf, and are returned as
+	/// collisions.
+	pub fn rebuild_map(&mut self) -> Vec<Collision<S::Value, I>>
+	where
+		C: Comparator<S::Value>,
+	{
+		let (refs, collisions) = RefTree::build(&self.values, &self.comparator);
+		*self.refs.get_mut() = refs;
+		collisions
+	}
 }
 
-impl<S: StorageMut> BTreeConstDictionary<S> {
-	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow_mut<T>(&mut self, r: Ref<T>) -> Option<&mut T>
+impl<S: StorageMut, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// ordering, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn borrow_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.borrow_mut(r)
 	}
 
-	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get_mut<T>(&mut self, r: Ref<T>) -> Option<&mut S::Value>
+	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// ordering, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn get_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.get_mut(r)
 	}
+
+	/// Replaces the definition associated to the given term, and returns the
+	/// old definition.
+	///
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the new definition is already associated to another term, the
+	/// dictionary is left unchanged and a [`Collision`] is returned.
+	#[allow(clippy::type_complexity)]
+	pub fn replace(
+		&mut self,
+		r: Ref<S::Value, I>,
+		value: S::Value,
+	) -> Option<Result<S::Value, Collision<S::Value, I>>>
+	where
+		C: Comparator<S::Value>,
+	{
+		let old = self.values.get::<S::Value>(r)?;
+		let refs = self.refs.get_mut();
+		if let Ok(node) = refs.search(&self.values, &self.comparator, &value) {
+			let existing = refs.get(node);
+			if existing != r {
+				return Some(Err(Collision::new(r, existing)));
+			}
+		}
+
+		refs.remove_ref(&self.values, &self.comparator, old, r);
+		let old = std::mem::replace(self.values.get_mut::<S::Value>(r).unwrap(), value);
+		if let Err(slot) = refs.search::<S, C, S::Value>(
+			&self.values,
+			&self.comparator,
+			self.values.get(r).unwrap(),
+		) {
+			refs.insert(slot, r);
+		}
+
+		Some(Ok(old))
+	}
+
+	/// Modifies the definition associated to the given term, keeping the
+	/// dictionary consistent.
+	///
+	/// The given function is applied to a copy of the definition, which then
+	/// replaces it as with [`Self::replace`].
+	/// The definition cannot be modified in place since it must be left
+	/// unchanged on collision, hence the `S::Value: Clone` bound. Use
+	/// [`Self::replace`] for definitions that cannot be cloned.
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the modified definition is equal to the definition of another term,
+	/// the dictionary is left unchanged and a [`Collision`] is returned.
+	pub fn modify(
+		&mut self,
+		r: Ref<S::Value, I>,
+		f: impl FnOnce(&mut S::Value),
+	) -> Option<Result<(), Collision<S::Value, I>>>
+	where
+		S::Value: Clone,
+		C: Comparator<S::Value>,
+	{
+		let mut value = self.values.get::<S::Value>(r)?.clone();
+		f(&mut value);
+		self.replace(r, value).map(|result| result.map(|_| ()))
+	}
 }
 
-impl<S: StorageAllocateConst> BTreeConstDictionary<S> {
+impl<S: StorageAllocateConst, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
 	/// Inserts a new value and returns its unique reference.
 	///
 	/// If the value is already registered, its old reference is returned
 	/// without overriding the old value.
-	pub fn insert(&self, value: S::Value) -> Ref<S::Value>
+	///
+	/// # Panics
+	///
+	/// Panics if the index of the dictionary is already being modified, or if
+	/// the reference of a new value does not fit in the index type `I`. The
+	/// dictionary is left unchanged in both cases. See [`Self::try_insert`].
+	pub fn insert(&self, value: S::Value) -> Ref<S::Value, I>
+	where
+		C: Comparator<S::Value>,
+	{
+		self.try_insert(value).unwrap_or_else(|e| panic!("{}", e))
+	}
+
+	/// Inserts a new value and returns its unique reference, or returns the
+	/// value back in an [`InsertError`] if the index of the dictionary is
+	/// borrowed or if the reference of the value would not fit in the index
+	/// type `I`.
+	///
+	/// The value is compared to the registered values while the index is not
+	/// borrowed, and the lookup starts over if the index was modified in the
+	/// meantime. The index is only borrowed mutably to register the new
+	/// reference, without comparing values.
+	/// It is borrowed by [`Self::validate`], so inserting from the comparator
+	/// during a validation fails.
+	pub fn try_insert(&self, value: S::Value) -> Result<Ref<S::Value, I>, InsertError<S::Value>>
 	where
-		S::Value: Clone + Ord,
+		C: Comparator<S::Value>,
 	{
-		use std::collections::btree_map::Entry;
-		let mut map = self.map.borrow_mut();
-		match map.entry(value) {
-			Entry::Vacant(entry) => {
-				let r = self.values.insert_const(entry.key().clone());
-				entry.insert(r);
-				r
+		loop {
+			let (slot, version) = match self.search(&value) {
+				Ok(r) => return Ok(r),
+				Err(missing) => missing,
+			};
+
+			let mut refs = match self.refs.try_borrow_mut() {
+				Ok(refs) => refs,
+				Err(_) => return Err(InsertError::borrowed(value)),
+			};
+
+			if refs.version() == version {
+				let r = self
+					.values
+					.try_insert_const(value)
+					.map_err(InsertError::overflow)?;
+				refs.insert(slot, r);
+				return Ok(r);
 			}
-			Entry::Occupied(entry) => *entry.get(),
 		}
 	}
+
+	/// Inserts all the given values and returns their unique references.
+	///
+	/// The returned vector contains the reference of each input value,
+	/// in order. Equal values are given the same reference.
+	pub fn insert_all<J>(&self, values: J) -> Vec<Ref<S::Value, I>>
+	where
+		J: IntoIterator<Item = S::Value>,
+		C: Comparator<S::Value>,
+	{
+		values.into_iter().map(|value| self.insert(value)).collect()
+	}
 }
 
-impl<S: StorageRemove> BTreeConstDictionary<S> {
-	pub fn remove<T>(&mut self, r: Ref<T>) -> Option<S::Value>
+impl<S: StorageRemove, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
 	where
-		S::Value: Borrow<T> + Ord,
+		S::Value: Borrow<T>,
+		C: Comparator<S::Value>,
 	{
-		match self.values.remove(r) {
-			Some(value) => {
-				let mut map = self.map.borrow_mut();
-				map.remove::<S::Value>(&value);
-				Some(value)
-			}
-			None => None,
-		}
+		let value = self.values.get::<S::Value>(r.cast())?;
+		self.refs
+			.get_mut()
+			.remove_ref(&self.values, &self.comparator, value, r.cast());
+
+		self.values.remove(r)
 	}
 
-	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value>, S::Value)>
+	#[allow(clippy::type_complexity)]
+	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value, I>, S::Value)>
 	where
-		S::Value: Ord,
+		C: Comparator<S::Value>,
 	{
-		let mut map = self.map.borrow_mut();
-		map.remove(value)
-			.map(|r| (r, self.values.remove(r).unwrap()))
+		let refs = self.refs.get_mut();
+		let node = refs.search(&self.values, &self.comparator, value).ok()?;
+		let r = refs.remove(node);
+		Some((r, self.values.remove(r).unwrap()))
+	}
+}
+
+impl<S: StorageCompact, C, I: RefIndex> BTreeConstDictionary<S, C, I> {
+	/// Moves the values of the dictionary so that they are densely packed,
+	/// updating the references of the dictionary accordingly.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references, to be applied to the references held outside of
+	/// the dictionary.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let remap = self.values.compact();
+		self.refs.get_mut().remap(&remap);
+		remap
+	}
+}
+
+impl<'a, S: 'a + StorageIter, C, I: RefIndex> IntoIterator for &'a BTreeConstDictionary<S, C, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<S: StorageIntoIter, C, I: RefIndex> IntoIterator for BTreeConstDictionary<S, C, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.values.into_iter()
+	}
+}
+
+impl<S: StorageAllocateConst, C, I: RefIndex> Extend<S::Value> for BTreeConstDictionary<S, C, I>
+where
+	C: Comparator<S::Value>,
+{
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
+	}
+}
+
+impl<S: StorageAllocateConst + Default, C: Default, I: RefIndex> FromIterator<S::Value>
+	for BTreeConstDictionary<S, C, I>
+where
+	C: Comparator<S::Value>,
+{
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<S: Storage + serde::Serialize, C, I: RefIndex> serde::Serialize
+	for BTreeConstDictionary<S, C, I>
+{
+	/// Serializes the underlying storage only.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		self.values.as_storage().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, C, I> serde::Deserialize<'de> for BTreeConstDictionary<S, C, I>
+where
+	S: StorageIter + serde::Deserialize<'de>,
+	C: Default,
+	I: RefIndex,
+	C: Comparator<S::Value>,
+{
+	/// Deserializes the underlying storage and rebuilds the dictionary from it.
+	///
+	/// Fails if the same value is stored at two different indices.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let values = Shelf::new(S::deserialize(deserializer)?);
+		let comparator = C::default();
+		let (refs, collisions) = RefTree::build(&values, &comparator);
+		if let Some(collision) = collisions.first() {
+			return Err(serde::de::Error::custom(format_args!(
+				"duplicate dictionary value at index {}",
+				collision.modified().index()
+			)));
+		}
+
+		Ok(Self {
+			refs: RefCell::new(refs),
+			comparator,
+			values,
+		})
+	}
+}
+
+/// Iterator over the terms and definitions of a dictionary, ordered by
+/// definition.
+///
+/// References of the index that do not resolve in the shelf are skipped.
+pub struct OrderedIter<'a, S: Storage, C = DefaultComparator, I: RefIndex = usize> {
+	dictionary: &'a BTreeConstDictionary<S, C, I>,
+	last: Option<&'a S::Value>,
+}
+
+impl<'a, S: Storage, C, I: RefIndex> Iterator for OrderedIter<'a, S, C, I>
+where
+	C: Comparator<S::Value>,
+{
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let values = &self.dictionary.values;
+		let mut node = match self.last {
+			Some(last) => self.dictionary.upper_bound(last),
+			None => self.dictionary.refs.borrow().first(),
+		};
+
+		let refs = self.dictionary.refs.borrow();
+		while let Some(n) = node {
+			let r = refs.get(n);
+			if let Some(value) = values.get(r) {
+				self.last = Some(value);
+				return Some((r, value));
+			}
+
+			node = refs.next(n)
+		}
+
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{AppendVec, InsertErrorKind};
+	use std::cell::OnceCell;
+	use std::rc::{Rc, Weak};
+
+	type ReentrantDictionary = BTreeConstDictionary<AppendVec<String>, Reentrant>;
+
+	/// Comparator inserting a pending value in its own dictionary the next
+	/// time it compares values.
+	#[derive(Default)]
+	struct Reentrant {
+		dictionary: OnceCell<Weak<ReentrantDictionary>>,
+		pending: RefCell<Option<String>>,
+		errors: RefCell<Vec<InsertErrorKind>>,
+	}
+
+	impl Comparator<str> for Reentrant {
+		fn compare(&self, a: &str, b: &str) -> Ordering {
+			if let Some(value) = self.pending.take() {
+				let dictionary = self.dictionary.get().unwrap().upgrade().unwrap();
+				if let Err(e) = dictionary.try_insert(value) {
+					self.errors.borrow_mut().push(e.kind())
+				}
+			}
+
+			a.cmp(b)
+		}
+	}
+
+	impl Comparator<String> for Reentrant {
+		fn compare(&self, a: &String, b: &String) -> Ordering {
+			Comparator::<str>::compare(self, a, b)
+		}
+	}
+
+	#[test]
+	fn insert_all_deduplicates() {
+		let mut dictionary: BTreeConstDictionary<AppendVec<String>> =
+			BTreeConstDictionary::default();
+		let refs = dictionary.insert_all(["c", "b", "c", "a", "b"].map(String::from));
+		let indices: Vec<_> = refs.as_slice().iter().map(Ref::index).collect();
+		assert_eq!(indices, [0, 1, 0, 2, 1]);
+		assert_eq!(dictionary.len(), 3);
+
+		dictionary.extend(["a", "d", "c", "d"].map(String::from));
+		assert_eq!(dictionary.len(), 4);
+		assert_eq!(dictionary.get_ref("d").map(|r| r.index()), Some(3));
+
+		let values: Vec<_> = dictionary
+			.iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(values, [(0, "c"), (1, "b"), (2, "a"), (3, "d")]);
+		let ordered: Vec<_> = dictionary
+			.ordered_iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(ordered, [(2, "a"), (1, "b"), (0, "c"), (3, "d")]);
+
+		let collected: BTreeConstDictionary<AppendVec<String>> =
+			["b", "a", "b"].map(String::from).into_iter().collect();
+		assert_eq!(collected.len(), 2);
+		assert_eq!(collected.get_ref("b").map(|r| r.index()), Some(0));
+		assert_eq!(collected.get_ref("a").map(|r| r.index()), Some(1));
+		assert!(collected.validate().is_valid());
+	}
+
+	#[test]
+	fn compact_keeps_lookups() {
+		let mut dictionary: BTreeConstDictionary<AppendVec<String>> =
+			BTreeConstDictionary::default();
+		let refs: Vec<_> = (0..20).map(|i| dictionary.insert(i.to_string())).collect();
+		for &r in refs.as_slice().iter().step_by(3) {
+			dictionary.remove(r);
+		}
+
+		let remap = dictionary.compact();
+		assert!(!remap.is_empty());
+		assert_eq!(dictionary.len(), 13);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			let value = i.to_string();
+			if i % 3 == 0 {
+				assert_eq!(dictionary.get_ref(value.as_str()), None);
+			} else {
+				let r = remap.apply(r);
+				assert!(r.index() < 13);
+				assert_eq!(dictionary.get_ref(value.as_str()), Some(r));
+				assert_eq!(dictionary.get(r), Some(&value));
+			}
+		}
+
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(dictionary.insert("0".to_string()).index(), 13);
+	}
+
+	#[test]
+	fn replace_and_modify_collisions() {
+		let mut dictionary: BTreeConstDictionary<AppendVec<String>> =
+			BTreeConstDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let collision = dictionary.replace(a, "b".to_string()).unwrap().unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (a, b));
+		let collision = dictionary
+			.modify(b, |value| *value = "a".to_string())
+			.unwrap()
+			.unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (b, a));
+
+		assert_eq!(dictionary.get(a).map(String::as_str), Some("a"));
+		assert_eq!(dictionary.get(b).map(String::as_str), Some("b"));
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(dictionary.validate().is_valid());
+
+		assert_eq!(
+			dictionary.replace(a, "a".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(
+			dictionary.replace(a, "c".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(dictionary.modify(b, |value| value.push('d')), Some(Ok(())));
+		assert_eq!(dictionary.replace(Ref::new(2), "e".to_string()), None);
+		assert_eq!(dictionary.modify(Ref::new(2), |_| unreachable!()), None);
+
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), None);
+		assert_eq!(dictionary.get_ref("b"), None);
+		assert_eq!(dictionary.get_ref("c"), Some(a));
+		assert_eq!(dictionary.get_ref("bd"), Some(b));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn reentrant_insert() {
+		let dictionary = Rc::new(BTreeConstDictionary::with_comparator(
+			AppendVec::default(),
+			Reentrant::default(),
+		));
+		let reentrant = dictionary.comparator();
+		reentrant
+			.dictionary
+			.set(Rc::downgrade(&dictionary))
+			.unwrap();
+
+		let a = dictionary.insert("a".to_string());
+		*reentrant.pending.borrow_mut() = Some("b".to_string());
+		let c = dictionary.insert("c".to_string());
+		assert_eq!(dictionary.get_ref("b"), Some(Ref::new(1)));
+		assert_eq!(c, Ref::new(2));
+
+		*reentrant.pending.borrow_mut() = Some("d".to_string());
+		let d = dictionary.insert("d".to_string());
+		assert_eq!(d, Ref::new(3));
+		assert_eq!(dictionary.insert("a".to_string()), a);
+		assert_eq!(dictionary.len(), 4);
+		assert!(reentrant.errors.borrow().is_empty());
+
+		*reentrant.pending.borrow_mut() = Some("e".to_string());
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(*reentrant.errors.borrow(), [InsertErrorKind::Borrowed]);
+		assert!(!dictionary.contains("e"));
+		assert_eq!(dictionary.len(), 4);
 	}
 }
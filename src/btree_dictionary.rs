@@ -1,57 +1,67 @@
-use crate::{Ref, Shelf, Storage, StorageAllocate, StorageMut, StorageRemove};
+use crate::dictionary::{RefTree, RefTreeIter};
+use crate::shelf::{IntoIter, Iter};
+use crate::{
+	Collision, Comparator, DefaultComparator, DictionaryReport, Ref, RefIndex, Remap, Shelf,
+	Snapshot, Storage, StorageAllocate, StorageCompact, StorageIntoIter, StorageIter, StorageMut,
+	StorageRemove, UndoLog,
+};
 use std::borrow::{Borrow, BorrowMut};
 use std::collections::BTreeMap;
 
-/// Bidirectional mapping between `T` and `Ref<T>`.
-pub struct BTreeDictionary<S: Storage> {
-	map: BTreeMap<S::Value, Ref<S::Value>>,
-	values: Shelf<S>,
+/// Bidirectional mapping between `T` and `Ref<T, I>`.
+///
+/// Each value is stored only once, in the shelf of the dictionary.
+/// Values are mapped to their reference by a balanced search tree of
+/// references, ordered by comparing the values through the shelf.
+/// Looking up, inserting or removing a value takes a logarithmic time.
+pub struct BTreeDictionary<S: Storage, C = DefaultComparator, I: RefIndex = usize> {
+	refs: RefTree<S::Value, I>,
+	comparator: C,
+	values: Shelf<S, I>,
 }
 
-impl<S: Storage + Default> Default for BTreeDictionary<S> {
+impl<S: Storage + Default, C: Default, I: RefIndex> Default for BTreeDictionary<S, C, I> {
 	fn default() -> Self {
-		Self {
-			map: BTreeMap::new(),
-			values: Shelf::default(),
-		}
+		Self::with_comparator(S::default(), C::default())
 	}
 }
 
 impl<S: Storage> BTreeDictionary<S> {
 	/// Creates a new empty dictionary.
 	pub fn new(storage: S) -> Self {
+		Self::with_comparator(storage, DefaultComparator)
+	}
+}
+
+impl<S: Storage, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	/// Creates a new empty dictionary using the given comparator to order
+	/// the values.
+	pub fn with_comparator(storage: S, comparator: C) -> Self {
 		Self {
-			map: BTreeMap::new(),
+			refs: RefTree::new(),
+			comparator,
 			values: Shelf::new(storage),
 		}
 	}
 
-	pub fn len(&self) -> usize {
-		self.map.len()
+	/// Returns the comparator of the dictionary.
+	pub fn comparator(&self) -> &C {
+		&self.comparator
 	}
 
-	pub fn is_empty(&self) -> bool {
-		self.map.is_empty()
-	}
-
-	#[allow(clippy::type_complexity)]
-	pub fn into_parts(self) -> (BTreeMap<S::Value, Ref<S::Value>>, Shelf<S>) {
-		(self.map, self.values)
-	}
-
-	pub fn into_map(self) -> BTreeMap<S::Value, Ref<S::Value>> {
-		self.map
+	pub fn len(&self) -> usize {
+		self.refs.len()
 	}
 
-	pub fn as_map(&self) -> &BTreeMap<S::Value, Ref<S::Value>> {
-		&self.map
+	pub fn is_empty(&self) -> bool {
+		self.refs.is_empty()
 	}
 
-	pub fn into_shelf(self) -> Shelf<S> {
+	pub fn into_shelf(self) -> Shelf<S, I> {
 		self.values
 	}
 
-	pub fn as_shelf(&self) -> &Shelf<S> {
+	pub fn as_shelf(&self) -> &Shelf<S, I> {
 		&self.values
 	}
 
@@ -63,82 +73,551 @@ impl<S: Storage> BTreeDictionary<S> {
 		self.values.as_storage()
 	}
 
-	/// Borrows the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow<T>(&self, r: Ref<T>) -> Option<&T>
+	/// Borrows the definition (`T`) associated to the given term (`Ref<T, I>`).
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.borrow(r)
 	}
 
-	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get<T>(&self, r: Ref<T>) -> Option<&S::Value>
+	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.get(r)
 	}
+
+	/// Returns the reference (`Ref<T, I>`) associated to the given definition, if any.
+	///
+	/// The definition may be any borrowed form of the stored value type,
+	/// which allows querying the dictionary without inserting or even
+	/// allocating the value.
+	pub fn get_ref<Q>(&self, value: &Q) -> Option<Ref<S::Value, I>>
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.refs.find(&self.values, &self.comparator, value)
+	}
+
+	/// Checks if the given definition is registered in the dictionary.
+	pub fn contains<Q>(&self, value: &Q) -> bool
+	where
+		S::Value: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.get_ref(value).is_some()
+	}
 }
 
-impl<S: StorageMut> BTreeDictionary<S> {
-	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow_mut<T>(&mut self, r: Ref<T>) -> Option<&mut T>
+impl<S: Storage, I: RefIndex> BTreeDictionary<S, DefaultComparator, I> {
+	/// Returns the map from the values of the dictionary to their reference,
+	/// borrowing the values.
+	///
+	/// The map is built in `O(n)` time. It is only available with the default
+	/// comparator, so that it orders the values as the dictionary does.
+	pub fn as_map(&self) -> BTreeMap<&S::Value, Ref<S::Value, I>>
+	where
+		S: StorageIter,
+		S::Value: Ord,
+	{
+		self.values.iter().map(|(r, value)| (value, r)).collect()
+	}
+
+	/// Turns the dictionary into the map from its values to their reference.
+	///
+	/// The map is built in `O(n)` time.
+	pub fn into_map(self) -> BTreeMap<S::Value, Ref<S::Value, I>>
+	where
+		S: StorageIntoIter,
+		S::Value: Ord,
+	{
+		self.values
+			.into_iter()
+			.map(|(r, value)| (value, r))
+			.collect()
+	}
+
+	/// Splits the dictionary into the map from its values to their reference,
+	/// and its shelf.
+	///
+	/// Every value is cloned into the map, in `O(n)` time, so that it is
+	/// stored twice. Use [`Self::as_map`] to only borrow the values.
+	#[allow(clippy::type_complexity)]
+	pub fn into_parts(self) -> (BTreeMap<S::Value, Ref<S::Value, I>>, Shelf<S, I>)
+	where
+		S: StorageIter,
+		S::Value: Ord + Clone,
+	{
+		let map = self
+			.values
+			.iter()
+			.map(|(r, value)| (value.clone(), r))
+			.collect();
+		(map, self.values)
+	}
+}
+
+impl<S: Storage, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, ordered by definition.
+	pub fn ordered_iter(&self) -> OrderedIter<'_, S, I> {
+		OrderedIter {
+			refs: self.refs.iter(),
+			values: &self.values,
+		}
+	}
+}
+
+impl<S: StorageIter, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, in storage order.
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		self.values.iter()
+	}
+
+	/// Checks that the value-to-reference index of the dictionary is
+	/// consistent with its shelf.
+	pub fn validate(&self) -> DictionaryReport<S::Value, I>
+	where
+		C: Comparator<S::Value>,
+	{
+		DictionaryReport::new(
+			&self.values,
+			self.refs.iter(),
+			|value| self.get_ref(value),
+			|a, b| self.comparator.compare(a, b).is_eq(),
+		)
+	}
+
+	/// Rebuilds the value-to-reference index of the dictionary from its
+	/// shelf.
+	///
+	/// Only the first of several equal values, in storage order, is
+	/// registered. The others stay in the shelf, and are returned as
+	/// collisions.
+	pub fn rebuild_map(&mut self) -> Vec<Collision<S::Value, I>>
+	where
+		C: Comparator<S::Value>,
+	{
+		let (refs, collisions) = RefTree::build(&self.values, &self.comparator);
+		self.refs = refs;
+		collisions
+	}
+}
+
+impl<S: StorageMut, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// ordering, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn borrow_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.borrow_mut(r)
 	}
 
-	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get_mut<T>(&mut self, r: Ref<T>) -> Option<&mut S::Value>
+	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// ordering, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn get_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.get_mut(r)
 	}
+
+	/// Replaces the definition associated to the given term, and returns the
+	/// old definition.
+	///
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the new definition is already associated to another term, the
+	/// dictionary is left unchanged and a [`Collision`] is returned.
+	#[allow(clippy::type_complexity)]
+	pub fn replace(
+		&mut self,
+		r: Ref<S::Value, I>,
+		value: S::Value,
+	) -> Option<Result<S::Value, Collision<S::Value, I>>>
+	where
+		C: Comparator<S::Value>,
+	{
+		let old = self.values.get::<S::Value>(r)?;
+		if let Ok(node) = self.refs.search(&self.values, &self.comparator, &value) {
+			let existing = self.refs.get(node);
+			if existing != r {
+				return Some(Err(Collision::new(r, existing)));
+			}
+		}
+
+		self.refs.remove_ref(&self.values, &self.comparator, old, r);
+		let old = std::mem::replace(self.values.get_mut::<S::Value>(r).unwrap(), value);
+		if let Err(slot) = self.refs.search::<S, C, S::Value>(
+			&self.values,
+			&self.comparator,
+			self.values.get(r).unwrap(),
+		) {
+			self.refs.insert(slot, r);
+		}
+
+		Some(Ok(old))
+	}
+
+	/// Modifies the definition associated to the given term, keeping the
+	/// dictionary consistent.
+	///
+	/// The given function is applied to a copy of the definition, which then
+	/// replaces it as with [`Self::replace`].
+	/// The definition cannot be modified in place since it must be left
+	/// unchanged on collision, hence the `S::Value: Clone` bound. Use
+	/// [`Self::replace`] for definitions that cannot be cloned.
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the modified definition is equal to the definition of another term,
+	/// the dictionary is left unchanged and a [`Collision`] is returned.
+	pub fn modify(
+		&mut self,
+		r: Ref<S::Value, I>,
+		f: impl FnOnce(&mut S::Value),
+	) -> Option<Result<(), Collision<S::Value, I>>>
+	where
+		S::Value: Clone,
+		C: Comparator<S::Value>,
+	{
+		let mut value = self.values.get::<S::Value>(r)?.clone();
+		f(&mut value);
+		self.replace(r, value).map(|result| result.map(|_| ()))
+	}
 }
 
-impl<S: StorageAllocate> BTreeDictionary<S> {
+impl<S: StorageAllocate, C, I: RefIndex> BTreeDictionary<S, C, I> {
 	/// Inserts a new value and returns its unique reference.
 	///
 	/// If the value is already registered, its old reference is returned
 	/// without overriding the old value.
-	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value>
+	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value, I>
 	where
-		S::Value: Clone + Ord,
+		C: Comparator<S::Value>,
 	{
-		use std::collections::btree_map::Entry;
-		match self.map.entry(value) {
-			Entry::Vacant(entry) => {
-				let r = self.values.insert(entry.key().clone());
-				entry.insert(r);
+		match self.refs.search(&self.values, &self.comparator, &value) {
+			Ok(node) => self.refs.get(node),
+			Err(slot) => {
+				let r = self.values.insert(value);
+				self.refs.insert(slot, r);
 				r
 			}
-			Entry::Occupied(entry) => *entry.get(),
 		}
 	}
+
+	/// Inserts all the given values and returns their unique references.
+	///
+	/// The returned vector contains the reference of each input value,
+	/// in order. Equal values are given the same reference.
+	pub fn insert_all<J>(&mut self, values: J) -> Vec<Ref<S::Value, I>>
+	where
+		J: IntoIterator<Item = S::Value>,
+		C: Comparator<S::Value>,
+	{
+		values.into_iter().map(|value| self.insert(value)).collect()
+	}
 }
 
-impl<S: StorageRemove> BTreeDictionary<S> {
-	pub fn remove<T>(&mut self, r: Ref<T>) -> Option<S::Value>
+impl<S: StorageRemove, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
 	where
-		S::Value: Borrow<T> + Ord,
+		S::Value: Borrow<T>,
+		C: Comparator<S::Value>,
 	{
-		match self.values.remove(r) {
-			Some(value) => {
-				self.map.remove::<S::Value>(&value);
-				Some(value)
-			}
-			None => None,
-		}
+		let value = self.values.get::<S::Value>(r.cast())?;
+		self.refs
+			.remove_ref(&self.values, &self.comparator, value, r.cast());
+
+		self.values.remove(r)
 	}
 
-	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value>, S::Value)>
+	#[allow(clippy::type_complexity)]
+	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value, I>, S::Value)>
 	where
-		S::Value: Ord,
+		C: Comparator<S::Value>,
 	{
-		self.map
-			.remove(value)
-			.map(|r| (r, self.values.remove(r).unwrap()))
+		let node = self
+			.refs
+			.search(&self.values, &self.comparator, value)
+			.ok()?;
+		let r = self.refs.remove(node);
+		Some((r, self.values.remove(r).unwrap()))
+	}
+}
+
+impl<S: StorageCompact, C, I: RefIndex> BTreeDictionary<S, C, I> {
+	/// Moves the values of the dictionary so that they are densely packed,
+	/// updating the references of the dictionary accordingly.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references, to be applied to the references held outside of
+	/// the dictionary.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let remap = self.values.compact();
+		self.refs.remap(&remap);
+		remap
+	}
+}
+
+impl<S: StorageIter, C, I: RefIndex> BTreeDictionary<UndoLog<S>, C, I> {
+	/// Takes a snapshot of the dictionary.
+	///
+	/// The values inserted or removed from now on can be restored using
+	/// [`Self::rollback_to`], or kept using [`Self::commit`].
+	pub fn snapshot(&mut self) -> Snapshot {
+		self.values.snapshot()
+	}
+
+	/// Restores the dictionary as it was when the given snapshot was taken.
+	///
+	/// Values inserted since then are un-interned, and removed values are
+	/// registered again under their original reference.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn rollback_to(&mut self, snapshot: Snapshot)
+	where
+		C: Comparator<S::Value>,
+	{
+		let modified = self.values.modified_since(&snapshot);
+		for &r in &modified {
+			if let Some(value) = self.values.get(r) {
+				self.refs
+					.remove_ref(&self.values, &self.comparator, value, r);
+			}
+		}
+
+		self.values.rollback_to(snapshot);
+		for r in modified {
+			if let Some(value) = self.values.get(r) {
+				if let Err(slot) = self.refs.search(&self.values, &self.comparator, value) {
+					self.refs.insert(slot, r);
+				}
+			}
+		}
+	}
+
+	/// Keeps the values inserted or removed since the given snapshot was
+	/// taken.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn commit(&mut self, snapshot: Snapshot) {
+		self.values.commit(snapshot)
+	}
+}
+
+impl<'a, S: 'a + StorageIter, C, I: RefIndex> IntoIterator for &'a BTreeDictionary<S, C, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<S: StorageIntoIter, C, I: RefIndex> IntoIterator for BTreeDictionary<S, C, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.values.into_iter()
+	}
+}
+
+impl<S: StorageAllocate, C, I: RefIndex> Extend<S::Value> for BTreeDictionary<S, C, I>
+where
+	C: Comparator<S::Value>,
+{
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
+	}
+}
+
+impl<S: StorageAllocate + Default, C: Default, I: RefIndex> FromIterator<S::Value>
+	for BTreeDictionary<S, C, I>
+where
+	C: Comparator<S::Value>,
+{
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<S: Storage + serde::Serialize, C, I: RefIndex> serde::Serialize for BTreeDictionary<S, C, I> {
+	/// Serializes the underlying storage only.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		self.values.as_storage().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, C, I> serde::Deserialize<'de> for BTreeDictionary<S, C, I>
+where
+	S: StorageIter + serde::Deserialize<'de>,
+	C: Default,
+	I: RefIndex,
+	C: Comparator<S::Value>,
+{
+	/// Deserializes the underlying storage and rebuilds the dictionary from it.
+	///
+	/// Fails if the same value is stored at two different indices.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let values = Shelf::new(S::deserialize(deserializer)?);
+		let comparator = C::default();
+		let (refs, collisions) = RefTree::build(&values, &comparator);
+		if let Some(collision) = collisions.first() {
+			return Err(serde::de::Error::custom(format_args!(
+				"duplicate dictionary value at index {}",
+				collision.modified().index()
+			)));
+		}
+
+		Ok(Self {
+			refs,
+			comparator,
+			values,
+		})
+	}
+}
+
+/// Iterator over the terms and definitions of a dictionary, ordered by
+/// definition.
+///
+/// References of the index that do not resolve in the shelf are skipped.
+pub struct OrderedIter<'a, S: Storage, I: RefIndex = usize> {
+	refs: RefTreeIter<'a, S::Value, I>,
+	values: &'a Shelf<S, I>,
+}
+
+impl<'a, S: Storage, I: RefIndex> Iterator for OrderedIter<'a, S, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.refs
+			.by_ref()
+			.find_map(|r| Some((r, self.values.get(r)?)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::FreeList;
+
+	#[test]
+	fn insert_all_deduplicates() {
+		let mut dictionary: BTreeDictionary<Vec<String>> = BTreeDictionary::default();
+		let refs = dictionary.insert_all(["c", "b", "c", "a", "b"].map(String::from));
+		let indices: Vec<_> = refs.as_slice().iter().map(Ref::index).collect();
+		assert_eq!(indices, [0, 1, 0, 2, 1]);
+		assert_eq!(dictionary.len(), 3);
+
+		dictionary.extend(["a", "d", "c", "d"].map(String::from));
+		assert_eq!(dictionary.len(), 4);
+		assert_eq!(dictionary.get_ref("d").map(|r| r.index()), Some(3));
+
+		let values: Vec<_> = dictionary
+			.iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(values, [(0, "c"), (1, "b"), (2, "a"), (3, "d")]);
+		let ordered: Vec<_> = dictionary
+			.ordered_iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(ordered, [(2, "a"), (1, "b"), (0, "c"), (3, "d")]);
+
+		let collected: BTreeDictionary<Vec<String>> =
+			["b", "a", "b"].map(String::from).into_iter().collect();
+		assert_eq!(collected.len(), 2);
+		assert_eq!(collected.get_ref("b").map(|r| r.index()), Some(0));
+		assert_eq!(collected.get_ref("a").map(|r| r.index()), Some(1));
+		assert!(collected.validate().is_valid());
+	}
+
+	#[test]
+	fn compact_keeps_lookups() {
+		let mut dictionary: BTreeDictionary<FreeList<String>> = BTreeDictionary::default();
+		let refs: Vec<_> = (0..20).map(|i| dictionary.insert(i.to_string())).collect();
+		for &r in refs.as_slice().iter().step_by(3) {
+			dictionary.remove(r);
+		}
+
+		let remap = dictionary.compact();
+		assert!(!remap.is_empty());
+		assert_eq!(dictionary.len(), 13);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			let value = i.to_string();
+			if i % 3 == 0 {
+				assert_eq!(dictionary.get_ref(value.as_str()), None);
+			} else {
+				let r = remap.apply(r);
+				assert!(r.index() < 13);
+				assert_eq!(dictionary.get_ref(value.as_str()), Some(r));
+				assert_eq!(dictionary.get(r), Some(&value));
+			}
+		}
+
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(dictionary.insert("0".to_string()).index(), 13);
+	}
+
+	#[test]
+	fn replace_and_modify_collisions() {
+		let mut dictionary: BTreeDictionary<Vec<String>> = BTreeDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let collision = dictionary.replace(a, "b".to_string()).unwrap().unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (a, b));
+		let collision = dictionary
+			.modify(b, |value| *value = "a".to_string())
+			.unwrap()
+			.unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (b, a));
+
+		assert_eq!(dictionary.get(a).map(String::as_str), Some("a"));
+		assert_eq!(dictionary.get(b).map(String::as_str), Some("b"));
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(dictionary.validate().is_valid());
+
+		assert_eq!(
+			dictionary.replace(a, "a".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(
+			dictionary.replace(a, "c".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(dictionary.modify(b, |value| value.push('d')), Some(Ok(())));
+		assert_eq!(dictionary.replace(Ref::new(2), "e".to_string()), None);
+		assert_eq!(dictionary.modify(Ref::new(2), |_| unreachable!()), None);
+
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), None);
+		assert_eq!(dictionary.get_ref("b"), None);
+		assert_eq!(dictionary.get_ref("c"), Some(a));
+		assert_eq!(dictionary.get_ref("bd"), Some(b));
+		assert!(dictionary.validate().is_valid());
 	}
 }
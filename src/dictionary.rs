@@ -0,0 +1,1134 @@
+//! Value-to-reference indices, comparison traits and error types shared by
+//! the dictionaries.
+//!
+//! These indices only store references, and find values by looking them up in
+//! the shelf of the dictionary, so that each value is stored only once.
+use crate::{Ref, RefIndex, Remap, Shelf, Storage, StorageIter};
+use derivative::Derivative;
+use hashbrown::HashTable;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// Equivalence relation used by the hash dictionaries to compare values.
+///
+/// Implementing this trait allows a dictionary to identify values that are
+/// not equal, such as strings differing only by their case, without wrapping
+/// them in a newtype. Equivalent values must have the same hash.
+///
+/// A dictionary looking up a borrowed form `Q` of its values also requires
+/// `Equivalence<Q>`, which must be consistent with `Equivalence<T>`.
+pub trait Equivalence<T: ?Sized> {
+	/// Checks if the given values are equivalent.
+	fn equivalent(&self, a: &T, b: &T) -> bool;
+
+	/// Feeds the given value into the given hasher.
+	fn hash<H: Hasher>(&self, value: &T, state: &mut H);
+}
+
+/// Default equivalence relation, given by the `Eq` and `Hash` traits.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DefaultEquivalence;
+
+impl<T: ?Sized + Eq + Hash> Equivalence<T> for DefaultEquivalence {
+	fn equivalent(&self, a: &T, b: &T) -> bool {
+		a == b
+	}
+
+	fn hash<H: Hasher>(&self, value: &T, state: &mut H) {
+		value.hash(state)
+	}
+}
+
+/// Total order used by the ordered dictionaries to compare values.
+///
+/// Implementing this trait allows a dictionary to order and identify values
+/// differently than their `Ord` implementation, without wrapping them in a
+/// newtype. Values comparing as equal are considered the same value.
+///
+/// A dictionary looking up a borrowed form `Q` of its values also requires
+/// `Comparator<Q>`, which must be consistent with `Comparator<T>`.
+pub trait Comparator<T: ?Sized> {
+	/// Compares the given values.
+	fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Default order, given by the `Ord` trait.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DefaultComparator;
+
+impl<T: ?Sized + Ord> Comparator<T> for DefaultComparator {
+	fn compare(&self, a: &T, b: &T) -> Ordering {
+		a.cmp(b)
+	}
+}
+
+/// Hasher of the values of a hash dictionary, combining a hash builder with
+/// an equivalence relation.
+#[derive(Default)]
+pub(crate) struct ValueHasher<H, E> {
+	pub builder: H,
+	pub equivalence: E,
+}
+
+impl<H, E> ValueHasher<H, E> {
+	pub fn new(builder: H, equivalence: E) -> Self {
+		Self {
+			builder,
+			equivalence,
+		}
+	}
+}
+
+impl<H: BuildHasher, E> ValueHasher<H, E> {
+	/// Computes the hash of the given value.
+	pub fn hash_one<Q: ?Sized>(&self, value: &Q) -> u64
+	where
+		E: Equivalence<Q>,
+	{
+		let mut state = self.builder.build_hasher();
+		self.equivalence.hash(value, &mut state);
+		state.finish()
+	}
+}
+
+/// Error returned when a value of a dictionary is modified to be equal to
+/// another value of the dictionary.
+#[derive(Derivative)]
+#[derivative(
+	Clone(bound = ""),
+	Copy(bound = ""),
+	PartialEq(bound = ""),
+	Eq(bound = ""),
+	Debug(bound = "")
+)]
+pub struct Collision<T, I: RefIndex = usize> {
+	modified: Ref<T, I>,
+	existing: Ref<T, I>,
+}
+
+impl<T, I: RefIndex> Collision<T, I> {
+	pub(crate) fn new(modified: Ref<T, I>, existing: Ref<T, I>) -> Self {
+		Self { modified, existing }
+	}
+
+	/// Returns the reference of the modified value.
+	pub fn modified(&self) -> Ref<T, I> {
+		self.modified
+	}
+
+	/// Returns the reference under which the new value is already registered.
+	pub fn existing(&self) -> Ref<T, I> {
+		self.existing
+	}
+}
+
+impl<T, I: RefIndex> fmt::Display for Collision<T, I> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"value at index {} collides with the value at index {}",
+			self.modified.index(),
+			self.existing.index()
+		)
+	}
+}
+
+impl<T, I: RefIndex> std::error::Error for Collision<T, I> {}
+
+/// Error returned when a value cannot be inserted in a dictionary.
+///
+/// The value that could not be inserted is given back.
+#[derive(Debug)]
+pub struct InsertError<T> {
+	kind: InsertErrorKind,
+	value: T,
+}
+
+/// Cause of an [`InsertError`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InsertErrorKind {
+	/// The index of the dictionary is already being modified, for instance
+	/// by an insertion made from the equivalence relation or comparator of
+	/// the dictionary.
+	Borrowed,
+
+	/// The reference of the new value does not fit in the index type of the
+	/// dictionary.
+	Overflow,
+}
+
+impl<T> InsertError<T> {
+	pub(crate) fn borrowed(value: T) -> Self {
+		Self {
+			kind: InsertErrorKind::Borrowed,
+			value,
+		}
+	}
+
+	pub(crate) fn overflow(value: T) -> Self {
+		Self {
+			kind: InsertErrorKind::Overflow,
+			value,
+		}
+	}
+
+	/// Returns the cause of the error.
+	pub fn kind(&self) -> InsertErrorKind {
+		self.kind
+	}
+
+	/// Returns the value that could not be inserted.
+	pub fn into_value(self) -> T {
+		self.value
+	}
+}
+
+impl<T> fmt::Display for InsertError<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.kind {
+			InsertErrorKind::Borrowed => write!(f, "dictionary index is already being modified"),
+			InsertErrorKind::Overflow => write!(f, "reference index overflow"),
+		}
+	}
+}
+
+impl<T: fmt::Debug> std::error::Error for InsertError<T> {}
+
+/// Consistency report of a dictionary, as returned by its `validate` method.
+///
+/// A dictionary is consistent when each value of its shelf is registered in
+/// its value-to-reference index exactly once, and can be found from its
+/// value. References are listed in index order.
+#[derive(Derivative)]
+#[derivative(
+	Clone(bound = ""),
+	Default(bound = ""),
+	PartialEq(bound = ""),
+	Eq(bound = ""),
+	Debug(bound = "")
+)]
+pub struct DictionaryReport<T, I: RefIndex = usize> {
+	orphans: Vec<Ref<T, I>>,
+	missing: Vec<Ref<T, I>>,
+	mismatched: Vec<Ref<T, I>>,
+	duplicates: Vec<(Ref<T, I>, Ref<T, I>)>,
+}
+
+impl<T, I: RefIndex> DictionaryReport<T, I> {
+	/// Checks the given index of the given values.
+	///
+	/// The `find` function looks up a value in the index, and the
+	/// `equivalent` function checks if two values are the same.
+	pub(crate) fn new<S>(
+		values: &Shelf<S, I>,
+		refs: impl IntoIterator<Item = Ref<T, I>>,
+		find: impl Fn(&T) -> Option<Ref<T, I>>,
+		equivalent: impl Fn(&T, &T) -> bool,
+	) -> Self
+	where
+		S: StorageIter<Value = T>,
+	{
+		let mut report = Self::default();
+		let mut registered = HashSet::new();
+		for r in refs {
+			if !registered.insert(r) {
+				report.duplicates.push((r, r));
+				continue;
+			}
+
+			match values.get::<T>(r) {
+				Some(value) => match find(value) {
+					Some(other) if other == r => (),
+					Some(other)
+						if values
+							.get::<T>(other)
+							.is_some_and(|other| equivalent(other, value)) =>
+					{
+						report.duplicates.push((other.min(r), other.max(r)))
+					}
+					_ => report.mismatched.push(r),
+				},
+				None => report.orphans.push(r),
+			}
+		}
+
+		report.missing = values
+			.iter()
+			.map(|(r, _)| r)
+			.filter(|r| !registered.contains(r))
+			.collect();
+		report.orphans.sort_unstable();
+		report.mismatched.sort_unstable();
+		report.duplicates.sort_unstable();
+		report.duplicates.dedup();
+		report
+	}
+
+	/// Checks if the dictionary is consistent.
+	pub fn is_valid(&self) -> bool {
+		self.orphans.is_empty()
+			&& self.missing.is_empty()
+			&& self.mismatched.is_empty()
+			&& self.duplicates.is_empty()
+	}
+
+	/// Returns the references registered in the index that do not resolve in
+	/// the shelf.
+	pub fn orphans(&self) -> &[Ref<T, I>] {
+		&self.orphans
+	}
+
+	/// Returns the references of the values of the shelf that are not
+	/// registered in the index.
+	pub fn missing(&self) -> &[Ref<T, I>] {
+		&self.missing
+	}
+
+	/// Returns the references registered in the index that cannot be found
+	/// from their value, typically because it has been modified in place.
+	pub fn mismatched(&self) -> &[Ref<T, I>] {
+		&self.mismatched
+	}
+
+	/// Returns the pairs of distinct references registered in the index for
+	/// equal values, or the same reference registered twice.
+	#[allow(clippy::type_complexity)]
+	pub fn duplicates(&self) -> &[(Ref<T, I>, Ref<T, I>)] {
+		&self.duplicates
+	}
+}
+
+/// Hash table of references, hashed and compared by value.
+///
+/// The hash of a value is computed by the caller, so that a value can be
+/// hashed before borrowing the table, and is stored along with its reference
+/// so that the table grows without hashing the values again.
+/// Values are only compared by [`Self::find`]: a caller can instead list the
+/// [candidates](Self::candidates) of a hash and compare them without keeping
+/// the table borrowed, using the [version](Self::version) of the table to
+/// detect modifications made in the meantime.
+pub(crate) struct RefTable<T, I: RefIndex> {
+	table: HashTable<(u64, Ref<T, I>)>,
+	version: usize,
+}
+
+impl<T, I: RefIndex> Default for RefTable<T, I> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T, I: RefIndex> RefTable<T, I> {
+	pub fn new() -> Self {
+		Self {
+			table: HashTable::new(),
+			version: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.table.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.table.is_empty()
+	}
+
+	/// Returns the version of the table, changed by every modification.
+	pub fn version(&self) -> usize {
+		self.version
+	}
+
+	/// Builds the table of the given values.
+	///
+	/// Only the first of several equal values is registered. The others are
+	/// returned as collisions.
+	pub fn build<S, H, E>(
+		values: &Shelf<S, I>,
+		hasher: &ValueHasher<H, E>,
+	) -> (Self, Vec<Collision<T, I>>)
+	where
+		S: StorageIter<Value = T>,
+		H: BuildHasher,
+		E: Equivalence<T>,
+	{
+		let mut table = Self::new();
+		let mut collisions = Vec::new();
+		for (r, value) in values.iter() {
+			let hash = hasher.hash_one(value);
+			match table.find(values, hasher, hash, value) {
+				Some(existing) => collisions.push(Collision::new(r, existing)),
+				None => table.insert(hash, r),
+			}
+		}
+
+		(table, collisions)
+	}
+
+	/// Returns an iterator over the references of the table.
+	pub fn iter(&self) -> impl '_ + Iterator<Item = Ref<T, I>> {
+		self.table.iter().map(|(_, r)| *r)
+	}
+
+	/// Returns an iterator over the references whose value has the given
+	/// hash.
+	pub fn candidates(&self, hash: u64) -> impl '_ + Iterator<Item = Ref<T, I>> {
+		self.table
+			.iter_hash(hash)
+			.filter(move |(h, _)| *h == hash)
+			.map(|(_, r)| *r)
+	}
+
+	/// Finds the reference of the given value, with the given hash.
+	pub fn find<S, H, E, Q>(
+		&self,
+		values: &Shelf<S, I>,
+		hasher: &ValueHasher<H, E>,
+		hash: u64,
+		value: &Q,
+	) -> Option<Ref<T, I>>
+	where
+		S: Storage<Value = T>,
+		T: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		self.candidates(hash)
+			.find(|r| equivalent(values, hasher, *r, value))
+	}
+
+	/// Inserts the reference of a value, with the given hash, that is not
+	/// already in the table.
+	pub fn insert(&mut self, hash: u64, r: Ref<T, I>) {
+		self.table.insert_unique(hash, (hash, r), |(h, _)| *h);
+		self.version += 1
+	}
+
+	/// Removes the given reference, whose value has the given hash.
+	///
+	/// Returns `false` if the reference was not found.
+	pub fn remove(&mut self, hash: u64, r: Ref<T, I>) -> bool {
+		match self.table.find_entry(hash, |(_, other)| *other == r) {
+			Ok(entry) => {
+				entry.remove();
+				self.version += 1;
+				true
+			}
+			Err(_) => false,
+		}
+	}
+
+	/// Replaces the moved references.
+	pub fn remap(&mut self, remap: &Remap<T, I>) {
+		if !remap.is_empty() {
+			for (_, r) in self.table.iter_mut() {
+				*r = remap.apply(*r)
+			}
+
+			self.version += 1
+		}
+	}
+}
+
+/// Checks if the value of the given reference is equivalent to the given
+/// value.
+///
+/// References that do not resolve in the shelf are equivalent to no value.
+pub(crate) fn equivalent<S, H, E, Q, I>(
+	values: &Shelf<S, I>,
+	hasher: &ValueHasher<H, E>,
+	r: Ref<S::Value, I>,
+	value: &Q,
+) -> bool
+where
+	S: Storage,
+	S::Value: Borrow<Q>,
+	E: Equivalence<Q>,
+	Q: ?Sized,
+	I: RefIndex,
+{
+	values.get::<S::Value>(r).is_some_and(|other| {
+		hasher
+			.equivalence
+			.equivalent(Borrow::<Q>::borrow(other), value)
+	})
+}
+
+/// Position of a missing reference in a [`RefTree`], as returned by
+/// [`RefTree::search`].
+#[derive(Clone, Copy)]
+pub(crate) struct Slot {
+	parent: Option<usize>,
+	side: usize,
+}
+
+/// Node of a [`RefTree`].
+struct Node<T, I: RefIndex> {
+	r: Ref<T, I>,
+	parent: Option<usize>,
+	children: [Option<usize>; 2],
+}
+
+/// Balanced search tree of references, ordered by value.
+///
+/// The tree is a treap whose nodes are stored in an arena and identified by
+/// their position in it. The priority of a node is derived from its
+/// position, independently of its value.
+/// Values are only compared by the search functions, so that a caller can
+/// also walk down the tree one node at a time, using [`Self::root`] and
+/// [`Self::child`], without keeping the tree borrowed while comparing
+/// values. The [version](Self::version) of the tree then tells if it was
+/// modified in the meantime.
+pub(crate) struct RefTree<T, I: RefIndex> {
+	nodes: Vec<Node<T, I>>,
+	vacant: Vec<usize>,
+	root: Option<usize>,
+	version: usize,
+}
+
+impl<T, I: RefIndex> Default for RefTree<T, I> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// Priority of the node at the given position.
+fn priority(node: usize) -> u64 {
+	// SplitMix64 finalizer.
+	let mut x = (node as u64).wrapping_add(0x9e3779b97f4a7c15);
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+	x ^ (x >> 31)
+}
+
+/// Direction to follow from a node whose value compares to the searched
+/// value as given.
+fn side(ordering: Ordering) -> usize {
+	match ordering {
+		Ordering::Greater => 0,
+		_ => 1,
+	}
+}
+
+impl<T, I: RefIndex> RefTree<T, I> {
+	pub fn new() -> Self {
+		Self {
+			nodes: Vec::new(),
+			vacant: Vec::new(),
+			root: None,
+			version: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.nodes.len() - self.vacant.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Returns the version of the tree, changed by every modification.
+	pub fn version(&self) -> usize {
+		self.version
+	}
+
+	/// Returns the root node of the tree, or the slot of the root if the tree
+	/// is empty.
+	pub fn root(&self) -> Result<usize, Slot> {
+		self.root.ok_or(Slot {
+			parent: None,
+			side: 0,
+		})
+	}
+
+	/// Returns the reference of the given node.
+	pub fn get(&self, node: usize) -> Ref<T, I> {
+		self.nodes[node].r
+	}
+
+	/// Returns the child of the given node to follow when its value compares
+	/// to the searched value as given, or the slot where the searched value
+	/// would be inserted if there is no such child.
+	///
+	/// An equal ordering leads to the greater values.
+	pub fn child(&self, node: usize, ordering: Ordering) -> Result<usize, Slot> {
+		let side = side(ordering);
+		self.nodes[node].children[side].ok_or(Slot {
+			parent: Some(node),
+			side,
+		})
+	}
+
+	/// Searches a value using the given function, comparing the value of a
+	/// reference to the searched value.
+	///
+	/// Returns the node holding the value if found, or the slot where it
+	/// should be inserted otherwise.
+	pub fn search_by(&self, mut compare: impl FnMut(Ref<T, I>) -> Ordering) -> Result<usize, Slot> {
+		let mut node = self.root()?;
+
+		loop {
+			match compare(self.get(node)) {
+				Ordering::Equal => return Ok(node),
+				ordering => node = self.child(node, ordering)?,
+			}
+		}
+	}
+
+	/// Returns the first node of the tree, in order.
+	pub fn first(&self) -> Option<usize> {
+		self.root.map(|root| self.leftmost(root))
+	}
+
+	fn leftmost(&self, mut node: usize) -> usize {
+		while let Some(left) = self.nodes[node].children[0] {
+			node = left
+		}
+
+		node
+	}
+
+	/// Returns the node following the given node, in order.
+	pub fn next(&self, node: usize) -> Option<usize> {
+		if let Some(right) = self.nodes[node].children[1] {
+			return Some(self.leftmost(right));
+		}
+
+		let mut node = node;
+		loop {
+			let parent = self.nodes[node].parent?;
+			if self.nodes[parent].children[0] == Some(node) {
+				return Some(parent);
+			}
+
+			node = parent
+		}
+	}
+
+	/// Returns an iterator over the references of the tree, in order.
+	pub fn iter(&self) -> RefTreeIter<'_, T, I> {
+		RefTreeIter {
+			tree: self,
+			next: self.first(),
+		}
+	}
+
+	/// Replaces the child `old` of the given parent, or the root, by `new`.
+	fn replace_child(&mut self, parent: Option<usize>, old: usize, new: Option<usize>) {
+		match parent {
+			Some(parent) => {
+				let children = &mut self.nodes[parent].children;
+				let side = if children[0] == Some(old) { 0 } else { 1 };
+				children[side] = new
+			}
+			None => self.root = new,
+		}
+	}
+
+	/// Moves the given node above its parent.
+	fn rotate_up(&mut self, node: usize) {
+		let parent = self.nodes[node].parent.unwrap();
+		let side = if self.nodes[parent].children[0] == Some(node) {
+			0
+		} else {
+			1
+		};
+		let grand_parent = self.nodes[parent].parent;
+
+		let inner = self.nodes[node].children[1 - side];
+		self.nodes[parent].children[side] = inner;
+		if let Some(inner) = inner {
+			self.nodes[inner].parent = Some(parent)
+		}
+
+		self.nodes[node].children[1 - side] = Some(parent);
+		self.nodes[parent].parent = Some(node);
+
+		self.nodes[node].parent = grand_parent;
+		self.replace_child(grand_parent, parent, Some(node));
+	}
+
+	/// Inserts a reference in the given slot, as returned by a search made
+	/// since the last modification of the tree.
+	///
+	/// Returns the node of the reference.
+	pub fn insert(&mut self, slot: Slot, r: Ref<T, I>) -> usize {
+		let new = Node {
+			r,
+			parent: slot.parent,
+			children: [None, None],
+		};
+
+		let node = match self.vacant.pop() {
+			Some(node) => {
+				self.nodes[node] = new;
+				node
+			}
+			None => {
+				self.nodes.push(new);
+				self.nodes.len() - 1
+			}
+		};
+
+		match slot.parent {
+			Some(parent) => self.nodes[parent].children[slot.side] = Some(node),
+			None => self.root = Some(node),
+		}
+
+		while let Some(parent) = self.nodes[node].parent {
+			if priority(parent) >= priority(node) {
+				break;
+			}
+
+			self.rotate_up(node)
+		}
+
+		self.version += 1;
+		node
+	}
+
+	/// Removes the given node, and returns its reference.
+	pub fn remove(&mut self, node: usize) -> Ref<T, I> {
+		loop {
+			let child = match self.nodes[node].children {
+				[None, None] => break,
+				[Some(child), None] | [None, Some(child)] => child,
+				[Some(a), Some(b)] => {
+					if priority(a) > priority(b) {
+						a
+					} else {
+						b
+					}
+				}
+			};
+
+			self.rotate_up(child)
+		}
+
+		self.replace_child(self.nodes[node].parent, node, None);
+		self.vacant.push(node);
+		self.version += 1;
+		self.nodes[node].r
+	}
+
+	/// Builds the tree of the given values.
+	///
+	/// Only the first of several equal values is registered. The others are
+	/// returned as collisions.
+	pub fn build<S, C>(values: &Shelf<S, I>, comparator: &C) -> (Self, Vec<Collision<T, I>>)
+	where
+		S: StorageIter<Value = T>,
+		C: Comparator<T>,
+	{
+		let mut tree = Self::new();
+		let mut collisions = Vec::new();
+		for (r, value) in values.iter() {
+			match tree.search(values, comparator, value) {
+				Ok(node) => collisions.push(Collision::new(r, tree.get(node))),
+				Err(slot) => {
+					tree.insert(slot, r);
+				}
+			}
+		}
+
+		(tree, collisions)
+	}
+
+	/// Searches the given value.
+	///
+	/// Returns the node of its reference if found, or the slot where it
+	/// should be inserted otherwise.
+	/// References that do not resolve in the shelf are ordered before any
+	/// value.
+	pub fn search<S, C, Q>(
+		&self,
+		values: &Shelf<S, I>,
+		comparator: &C,
+		value: &Q,
+	) -> Result<usize, Slot>
+	where
+		S: Storage<Value = T>,
+		T: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.search_by(|r| compare(values, comparator, r, value))
+	}
+
+	/// Finds the reference of the given value.
+	pub fn find<S, C, Q>(
+		&self,
+		values: &Shelf<S, I>,
+		comparator: &C,
+		value: &Q,
+	) -> Option<Ref<T, I>>
+	where
+		S: Storage<Value = T>,
+		T: Borrow<Q>,
+		C: Comparator<Q>,
+		Q: ?Sized,
+	{
+		self.search(values, comparator, value)
+			.ok()
+			.map(|node| self.get(node))
+	}
+
+	/// Removes the given reference, whose value is `value`.
+	///
+	/// Returns `false` if the reference was not found.
+	pub fn remove_ref<S, C>(
+		&mut self,
+		values: &Shelf<S, I>,
+		comparator: &C,
+		value: &T,
+		r: Ref<T, I>,
+	) -> bool
+	where
+		S: Storage<Value = T>,
+		C: Comparator<T>,
+	{
+		match self.search::<S, C, T>(values, comparator, value) {
+			Ok(node) if self.get(node) == r => {
+				self.remove(node);
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Replaces the moved references.
+	pub fn remap(&mut self, remap: &Remap<T, I>) {
+		if !remap.is_empty() {
+			for node in &mut self.nodes {
+				node.r = remap.apply(node.r)
+			}
+
+			self.version += 1
+		}
+	}
+}
+
+/// Compares the value of the given reference to the given value.
+///
+/// References that do not resolve in the shelf are ordered before any value.
+pub(crate) fn compare<S, C, Q, I>(
+	values: &Shelf<S, I>,
+	comparator: &C,
+	r: Ref<S::Value, I>,
+	value: &Q,
+) -> Ordering
+where
+	S: Storage,
+	S::Value: Borrow<Q>,
+	C: Comparator<Q>,
+	Q: ?Sized,
+	I: RefIndex,
+{
+	match values.get::<S::Value>(r) {
+		Some(other) => comparator.compare(Borrow::<Q>::borrow(other), value),
+		None => Ordering::Less,
+	}
+}
+
+/// Iterator over the references of a [`RefTree`], in order.
+pub(crate) struct RefTreeIter<'a, T, I: RefIndex> {
+	tree: &'a RefTree<T, I>,
+	next: Option<usize>,
+}
+
+impl<T, I: RefIndex> Iterator for RefTreeIter<'_, T, I> {
+	type Item = Ref<T, I>;
+
+	fn next(&mut self) -> Option<Ref<T, I>> {
+		let node = self.next?;
+		self.next = self.tree.next(node);
+		Some(self.tree.get(node))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		AppendVec, BTreeConstDictionary, BTreeDictionary, FreeList, HashConstDictionary,
+		HashDictionary, SyncAppendVec, SyncHashConstDictionary,
+	};
+	use std::collections::hash_map::DefaultHasher;
+	use std::collections::BTreeMap;
+	use std::hash::BuildHasherDefault;
+
+	/// Compares strings ignoring the case of ASCII letters.
+	#[derive(Default)]
+	struct CaseInsensitive;
+
+	impl Equivalence<str> for CaseInsensitive {
+		fn equivalent(&self, a: &str, b: &str) -> bool {
+			a.eq_ignore_ascii_case(b)
+		}
+
+		fn hash<H: Hasher>(&self, value: &str, state: &mut H) {
+			for byte in value.bytes() {
+				state.write_u8(byte.to_ascii_lowercase())
+			}
+		}
+	}
+
+	impl Equivalence<String> for CaseInsensitive {
+		fn equivalent(&self, a: &String, b: &String) -> bool {
+			Equivalence::<str>::equivalent(self, a, b)
+		}
+
+		fn hash<H: Hasher>(&self, value: &String, state: &mut H) {
+			Equivalence::<str>::hash(self, value, state)
+		}
+	}
+
+	impl Comparator<str> for CaseInsensitive {
+		fn compare(&self, a: &str, b: &str) -> Ordering {
+			a.bytes()
+				.map(|byte| byte.to_ascii_lowercase())
+				.cmp(b.bytes().map(|byte| byte.to_ascii_lowercase()))
+		}
+	}
+
+	impl Comparator<String> for CaseInsensitive {
+		fn compare(&self, a: &String, b: &String) -> Ordering {
+			Comparator::<str>::compare(self, a, b)
+		}
+	}
+
+	/// Checks that the given dictionary of strings looks up and deduplicates
+	/// values ignoring their case.
+	macro_rules! check_case_insensitive {
+		($dictionary:expr) => {{
+			#[allow(unused_mut)]
+			let mut dictionary = $dictionary;
+			let a = dictionary.insert("Hello".to_string());
+			let b = dictionary.insert("world".to_string());
+			assert_eq!(dictionary.insert("HELLO".to_string()), a);
+			assert_eq!(dictionary.insert("World".to_string()), b);
+
+			assert_eq!(dictionary.len(), 2);
+			assert_eq!(dictionary.get(a).map(String::as_str), Some("Hello"));
+			assert_eq!(dictionary.get(b).map(String::as_str), Some("world"));
+			assert_eq!(dictionary.get_ref("hello"), Some(a));
+			assert_eq!(dictionary.get_ref("WORLD"), Some(b));
+			assert!(dictionary.contains("hElLo"));
+			assert!(!dictionary.contains("hello!"));
+			assert!(dictionary.validate().is_valid());
+			dictionary
+		}};
+	}
+
+	/// Xorshift pseudo-random number generator.
+	struct Rng(u64);
+
+	impl Rng {
+		fn next(&mut self, bound: u64) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0 % bound
+		}
+	}
+
+	/// Checks the links and priorities of the tree, and returns its number of
+	/// nodes.
+	fn check_tree<T, I: RefIndex>(tree: &RefTree<T, I>) -> usize {
+		fn check_node<T, I: RefIndex>(tree: &RefTree<T, I>, node: usize) -> usize {
+			let mut count = 1;
+			for child in tree.nodes[node].children.into_iter().flatten() {
+				assert_eq!(tree.nodes[child].parent, Some(node));
+				assert!(priority(child) <= priority(node));
+				count += check_node(tree, child)
+			}
+
+			count
+		}
+
+		match tree.root {
+			Some(root) => {
+				assert_eq!(tree.nodes[root].parent, None);
+				check_node(tree, root)
+			}
+			None => 0,
+		}
+	}
+
+	#[test]
+	fn ref_tree_structure() {
+		let mut rng = Rng(0x243f6a8885a308d3);
+		let mut values: Shelf<FreeList<u32>> = Shelf::default();
+		let mut tree = RefTree::new();
+
+		for _ in 0..5000 {
+			let value = rng.next(300) as u32;
+			match tree.search(&values, &DefaultComparator, &value) {
+				Ok(node) if rng.next(3) == 0 => {
+					let r = tree.remove(node);
+					assert_eq!(values.remove(r), Some(value));
+				}
+				Ok(_) => (),
+				Err(slot) => {
+					let r = values.insert(value);
+					tree.insert(slot, r);
+				}
+			}
+
+			assert_eq!(check_tree(&tree), values.len());
+			assert_eq!(tree.len(), values.len());
+		}
+
+		let mut expected: Vec<u32> = values.iter().map(|(_, value)| *value).collect();
+		expected.sort_unstable();
+		assert!(tree
+			.iter()
+			.map(|r| values[r])
+			.eq(expected.as_slice().iter().copied()));
+	}
+
+	#[test]
+	fn ref_tree_against_btree_map() {
+		let mut rng = Rng(0x2545f4914f6cdd1d);
+		let mut dictionary: BTreeDictionary<FreeList<u32>> = BTreeDictionary::default();
+		let mut expected: BTreeMap<u32, Ref<u32>> = BTreeMap::new();
+
+		for step in 0..5000 {
+			let value = rng.next(300) as u32;
+			if rng.next(3) == 0 {
+				let removed = dictionary.remove_value::<u32>(&value);
+				assert_eq!(removed, expected.remove(&value).map(|r| (r, value)));
+			} else {
+				let r = dictionary.insert(value);
+				assert_eq!(*expected.entry(value).or_insert(r), r);
+			}
+
+			if step % 100 == 0 {
+				for value in 0..300 {
+					assert_eq!(dictionary.get_ref(&value), expected.get(&value).copied());
+				}
+
+				assert!(dictionary
+					.ordered_iter()
+					.map(|(r, value)| (*value, r))
+					.eq(expected.iter().map(|(value, r)| (*value, *r))));
+				assert!(dictionary.validate().is_valid());
+			}
+		}
+	}
+
+	#[test]
+	fn const_ref_tree_against_btree_map() {
+		let mut rng = Rng(0x9e3779b97f4a7c15);
+		let mut dictionary: BTreeConstDictionary<AppendVec<u32>> = BTreeConstDictionary::default();
+		let mut expected: BTreeMap<u32, Ref<u32>> = BTreeMap::new();
+
+		for _ in 0..2000 {
+			let value = rng.next(200) as u32;
+			if rng.next(3) == 0 {
+				if let Some(r) = expected.remove(&value) {
+					assert_eq!(dictionary.remove(r), Some(value));
+				}
+			} else {
+				let r = dictionary.insert(value);
+				assert_eq!(*expected.entry(value).or_insert(r), r);
+			}
+		}
+
+		for value in 0..200 {
+			assert_eq!(dictionary.get_ref(&value), expected.get(&value).copied());
+		}
+
+		assert!(dictionary
+			.ordered_iter()
+			.map(|(r, value)| (*value, r))
+			.eq(expected.iter().map(|(value, r)| (*value, *r))));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn rebuild_corrupted_ref_tree() {
+		let mut rng = Rng(0xd1b54a32d192ed03);
+		let mut dictionary: BTreeDictionary<FreeList<u32>> = BTreeDictionary::default();
+		for _ in 0..500 {
+			dictionary.insert(rng.next(1000) as u32 * 2);
+		}
+
+		let refs: Vec<_> = dictionary.iter().map(|(r, _)| r).collect();
+		for &r in refs.as_slice().iter().step_by(7) {
+			*dictionary.get_mut_unchecked(r).unwrap() += 1001;
+		}
+
+		assert!(!dictionary.validate().is_valid());
+		assert!(dictionary.rebuild_map().is_empty());
+		assert!(dictionary.validate().is_valid());
+
+		let mut expected: Vec<_> = dictionary.iter().map(|(r, value)| (*value, r)).collect();
+		expected.sort_unstable();
+		assert!(dictionary
+			.ordered_iter()
+			.map(|(r, value)| (*value, r))
+			.eq(expected.as_slice().iter().copied()));
+		for (value, r) in expected {
+			assert_eq!(dictionary.get_ref(&value), Some(r));
+		}
+
+		let (a, b) = (refs[0], refs[1]);
+		let value = *dictionary.get(b).unwrap();
+		*dictionary.get_mut_unchecked(a).unwrap() = value;
+		let collisions = dictionary.rebuild_map();
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].modified(), a.max(b));
+		assert_eq!(collisions[0].existing(), a.min(b));
+		assert_eq!(dictionary.len(), refs.len() - 1);
+		assert_eq!(dictionary.ordered_iter().count(), refs.len() - 1);
+	}
+
+	#[test]
+	fn case_insensitive_hash_dictionaries() {
+		type Builder = BuildHasherDefault<DefaultHasher>;
+
+		check_case_insensitive!(HashDictionary::<_, _, _>::with_equivalence(
+			FreeList::<String>::default(),
+			Builder::default(),
+			CaseInsensitive
+		));
+		check_case_insensitive!(HashConstDictionary::<_, _, _>::with_equivalence(
+			AppendVec::<String>::default(),
+			Builder::default(),
+			CaseInsensitive
+		));
+		check_case_insensitive!(SyncHashConstDictionary::<_, _, _>::with_equivalence(
+			SyncAppendVec::<String>::default(),
+			Builder::default(),
+			CaseInsensitive
+		));
+	}
+
+	#[test]
+	fn case_insensitive_btree_dictionaries() {
+		let dictionary = check_case_insensitive!(BTreeDictionary::<_, _>::with_comparator(
+			FreeList::<String>::default(),
+			CaseInsensitive
+		));
+		assert!(dictionary
+			.ordered_iter()
+			.map(|(_, value)| value.as_str())
+			.eq(["Hello", "world"]));
+
+		let dictionary = check_case_insensitive!(BTreeConstDictionary::<_, _>::with_comparator(
+			AppendVec::<String>::default(),
+			CaseInsensitive
+		));
+		dictionary.insert("apple".to_string());
+		dictionary.insert("Banana".to_string());
+		assert!(dictionary
+			.ordered_iter()
+			.map(|(_, value)| value.as_str())
+			.eq(["apple", "Banana", "Hello", "world"]));
+	}
+}
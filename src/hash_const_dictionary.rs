@@ -1,59 +1,93 @@
-use crate::{Ref, Shelf, Storage, StorageAllocateConst, StorageMut, StorageRemove};
+use crate::dictionary::{equivalent, RefTable, ValueHasher};
+use crate::shelf::{IntoIter, Iter};
+use crate::{
+	Collision, DefaultEquivalence, DictionaryReport, Equivalence, InsertError, Ref, RefIndex,
+	Remap, Shelf, Storage, StorageAllocateConst, StorageCompact, StorageIntoIter, StorageIter,
+	StorageMut, StorageRemove,
+};
 use std::borrow::{Borrow, BorrowMut};
 use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 
-/// Bidirectional mapping between `T` and `Ref<T>`.
-pub struct HashConstDictionary<S: Storage> {
-	map: RefCell<HashMap<S::Value, Ref<S::Value>>>,
-	values: Shelf<S>,
+/// Bidirectional mapping between `T` and `Ref<T, I>`.
+///
+/// Each value is stored only once, in the shelf of the dictionary.
+/// Values are mapped to their reference by a hash table of references,
+/// hashing and comparing the values through the shelf.
+/// The table is never borrowed while hashing or comparing values, so that
+/// the hash function and equivalence relation may themselves look up or
+/// insert values.
+pub struct HashConstDictionary<
+	S: Storage,
+	H = RandomState,
+	E = DefaultEquivalence,
+	I: RefIndex = usize,
+> {
+	table: RefCell<RefTable<S::Value, I>>,
+	hasher: ValueHasher<H, E>,
+	values: Shelf<S, I>,
 }
 
-impl<S: Storage + Default> Default for HashConstDictionary<S> {
+impl<S: Storage + Default, H: Default, E: Default, I: RefIndex> Default
+	for HashConstDictionary<S, H, E, I>
+{
 	fn default() -> Self {
-		Self {
-			map: RefCell::new(HashMap::new()),
-			values: Shelf::default(),
-		}
+		Self::with_equivalence(S::default(), H::default(), E::default())
 	}
 }
 
 impl<S: Storage> HashConstDictionary<S> {
 	/// Creates a new empty dictionary.
 	pub fn new(storage: S) -> Self {
+		Self::with_hasher(storage, RandomState::new())
+	}
+}
+
+impl<S: Storage, H, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values.
+	pub fn with_hasher(storage: S, hasher: H) -> Self
+	where
+		E: Default,
+	{
+		Self::with_equivalence(storage, hasher, E::default())
+	}
+
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values, and the given equivalence relation to compare them.
+	pub fn with_equivalence(storage: S, hasher: H, equivalence: E) -> Self {
 		Self {
-			map: RefCell::new(HashMap::new()),
+			table: RefCell::default(),
+			hasher: ValueHasher::new(hasher, equivalence),
 			values: Shelf::new(storage),
 		}
 	}
 
-	pub fn len(&self) -> usize {
-		self.map.borrow().len()
-	}
-
-	pub fn is_empty(&self) -> bool {
-		self.map.borrow().is_empty()
+	/// Returns the hash builder of the dictionary.
+	pub fn hasher(&self) -> &H {
+		&self.hasher.builder
 	}
 
-	#[allow(clippy::type_complexity)]
-	pub fn into_parts(self) -> (RefCell<HashMap<S::Value, Ref<S::Value>>>, Shelf<S>) {
-		(self.map, self.values)
+	/// Returns the equivalence relation of the dictionary.
+	pub fn equivalence(&self) -> &E {
+		&self.hasher.equivalence
 	}
 
-	pub fn into_map(self) -> HashMap<S::Value, Ref<S::Value>> {
-		self.map.into_inner()
+	pub fn len(&self) -> usize {
+		self.table.borrow().len()
 	}
 
-	pub fn as_map(&self) -> &RefCell<HashMap<S::Value, Ref<S::Value>>> {
-		&self.map
+	pub fn is_empty(&self) -> bool {
+		self.table.borrow().is_empty()
 	}
 
-	pub fn into_shelf(self) -> Shelf<S> {
+	pub fn into_shelf(self) -> Shelf<S, I> {
 		self.values
 	}
 
-	pub fn as_shelf(&self) -> &Shelf<S> {
+	pub fn as_shelf(&self) -> &Shelf<S, I> {
 		&self.values
 	}
 
@@ -65,84 +99,661 @@ impl<S: Storage> HashConstDictionary<S> {
 		self.values.as_storage()
 	}
 
-	/// Borrows the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow<T>(&self, r: Ref<T>) -> Option<&T>
+	/// Borrows the definition (`T`) associated to the given term (`Ref<T, I>`).
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.borrow(r)
 	}
 
-	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get<T>(&self, r: Ref<T>) -> Option<&S::Value>
+	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.get(r)
 	}
+
+	/// Returns the reference (`Ref<T, I>`) associated to the given definition, if any.
+	///
+	/// The definition may be any borrowed form of the stored value type,
+	/// which allows querying the dictionary without inserting or even
+	/// allocating the value.
+	pub fn get_ref<Q>(&self, value: &Q) -> Option<Ref<S::Value, I>>
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		let hash = self.hasher.hash_one(value);
+		self.search(hash, value).ok()
+	}
+
+	/// Searches the value with the given hash, comparing the candidate
+	/// values once the table is released.
+	///
+	/// Returns the reference of the value if found, or the version of the
+	/// table it is missing from otherwise.
+	fn search<Q>(&self, hash: u64, value: &Q) -> Result<Ref<S::Value, I>, usize>
+	where
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		let (first, others, version) = {
+			let table = self.table.borrow();
+			let mut candidates = table.candidates(hash);
+			let first = candidates.next();
+			(first, candidates.collect::<Vec<_>>(), table.version())
+		};
+
+		first
+			.into_iter()
+			.chain(others)
+			.find(|r| equivalent(&self.values, &self.hasher, *r, value))
+			.ok_or(version)
+	}
+
+	/// Checks if the given definition is registered in the dictionary.
+	pub fn contains<Q>(&self, value: &Q) -> bool
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		self.get_ref(value).is_some()
+	}
+}
+
+impl<S: Storage, H, I: RefIndex> HashConstDictionary<S, H, DefaultEquivalence, I> {
+	/// Returns the map from the values of the dictionary to their reference,
+	/// borrowing the values.
+	///
+	/// The map is built in `O(n)` time, and uses a clone of the hash builder
+	/// of the dictionary. It is only available with the default equivalence,
+	/// so that it compares the values as the dictionary does.
+	pub fn as_map(&self) -> HashMap<&S::Value, Ref<S::Value, I>, H>
+	where
+		S: StorageIter,
+		S::Value: Eq + Hash,
+		H: BuildHasher + Clone,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder.clone());
+		map.extend(self.values.iter().map(|(r, value)| (value, r)));
+		map
+	}
+
+	/// Turns the dictionary into the map from its values to their reference.
+	///
+	/// The map is built in `O(n)` time, and uses the hash builder of the
+	/// dictionary.
+	pub fn into_map(self) -> HashMap<S::Value, Ref<S::Value, I>, H>
+	where
+		S: StorageIntoIter,
+		S::Value: Eq + Hash,
+		H: BuildHasher,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder);
+		map.extend(self.values.into_iter().map(|(r, value)| (value, r)));
+		map
+	}
+
+	/// Splits the dictionary into the map from its values to their reference,
+	/// and its shelf.
+	///
+	/// Every value is cloned into the map, in `O(n)` time, so that it is
+	/// stored twice. Use [`Self::as_map`] to only borrow the values.
+	/// The map uses the hash builder of the dictionary.
+	#[allow(clippy::type_complexity)]
+	pub fn into_parts(self) -> (HashMap<S::Value, Ref<S::Value, I>, H>, Shelf<S, I>)
+	where
+		S: StorageIter,
+		S::Value: Eq + Hash + Clone,
+		H: BuildHasher,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder);
+		map.extend(self.values.iter().map(|(r, value)| (value.clone(), r)));
+		(map, self.values)
+	}
+}
+
+impl<S: StorageIter, H, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, in storage order.
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		self.values.iter()
+	}
+
+	/// Checks that the value-to-reference index of the dictionary is
+	/// consistent with its shelf.
+	pub fn validate(&self) -> DictionaryReport<S::Value, I>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		DictionaryReport::new(
+			&self.values,
+			self.table.borrow().iter(),
+			|value| self.get_ref(value),
+			|a, b| self.hasher.equivalence.equivalent(a, b),
+		)
+	}
+
+	/// Rebuilds the value-to-reference index of the dictionary from its
+	/// shelf.
+	///
+	/// Only the first of several equal values, in storage order, is
+	/// registered. The others stay in the shelf, and are returned as
+	/// collisions.
+	pub fn rebuild_map(&mut self) -> Vec<Collision<S::Value, I>>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		let (table, collisions) = RefTable::build(&self.values, &self.hasher);
+		*self.table.get_mut() = table;
+		collisions
+	}
 }
 
-impl<S: StorageMut> HashConstDictionary<S> {
-	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow_mut<T>(&mut self, r: Ref<T>) -> Option<&mut T>
+impl<S: StorageMut, H: BuildHasher, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
+	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn borrow_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.borrow_mut(r)
 	}
 
-	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get_mut<T>(&mut self, r: Ref<T>) -> Option<&mut S::Value>
+	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn get_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.get_mut(r)
 	}
+
+	/// Replaces the definition associated to the given term, and returns the
+	/// old definition.
+	///
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the new definition is already associated to another term, the
+	/// dictionary is left unchanged and a [`Collision`] is returned.
+	#[allow(clippy::type_complexity)]
+	pub fn replace(
+		&mut self,
+		r: Ref<S::Value, I>,
+		value: S::Value,
+	) -> Option<Result<S::Value, Collision<S::Value, I>>>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let old_hash = self.hasher.hash_one(self.values.get::<S::Value>(r)?);
+		let hash = self.hasher.hash_one(&value);
+		let table = self.table.get_mut();
+		match table.find(&self.values, &self.hasher, hash, &value) {
+			Some(existing) if existing != r => Some(Err(Collision::new(r, existing))),
+			_ => {
+				table.remove(old_hash, r);
+				let old = std::mem::replace(self.values.get_mut::<S::Value>(r).unwrap(), value);
+				table.insert(hash, r);
+				Some(Ok(old))
+			}
+		}
+	}
+
+	/// Modifies the definition associated to the given term, keeping the
+	/// dictionary consistent.
+	///
+	/// The given function is applied to a copy of the definition, which then
+	/// replaces it as with [`Self::replace`].
+	/// The definition cannot be modified in place since it must be left
+	/// unchanged on collision, hence the `S::Value: Clone` bound. Use
+	/// [`Self::replace`] for definitions that cannot be cloned.
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the modified definition is equal to the definition of another term,
+	/// the dictionary is left unchanged and a [`Collision`] is returned.
+	pub fn modify(
+		&mut self,
+		r: Ref<S::Value, I>,
+		f: impl FnOnce(&mut S::Value),
+	) -> Option<Result<(), Collision<S::Value, I>>>
+	where
+		S::Value: Clone,
+		E: Equivalence<S::Value>,
+	{
+		let mut value = self.values.get::<S::Value>(r)?.clone();
+		f(&mut value);
+		self.replace(r, value).map(|result| result.map(|_| ()))
+	}
 }
 
-impl<S: StorageAllocateConst> HashConstDictionary<S> {
+impl<S: StorageAllocateConst, H: BuildHasher, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
 	/// Inserts a new value and returns its unique reference.
 	///
 	/// If the value is already registered, its old reference is returned
 	/// without overriding the old value.
-	pub fn insert(&self, value: S::Value) -> Ref<S::Value>
+	///
+	/// # Panics
+	///
+	/// Panics if the index of the dictionary is already being modified, or if
+	/// the reference of a new value does not fit in the index type `I`. The
+	/// dictionary is left unchanged in both cases. See [`Self::try_insert`].
+	pub fn insert(&self, value: S::Value) -> Ref<S::Value, I>
 	where
-		S::Value: Clone + Eq + Hash,
+		E: Equivalence<S::Value>,
 	{
-		use std::collections::hash_map::Entry;
-		let mut map = self.map.borrow_mut();
-		match map.entry(value) {
-			Entry::Vacant(entry) => {
-				let r = self.values.insert_const(entry.key().clone());
-				entry.insert(r);
-				r
+		self.try_insert(value).unwrap_or_else(|e| panic!("{}", e))
+	}
+
+	/// Inserts a new value and returns its unique reference, or returns the
+	/// value back in an [`InsertError`] if the index of the dictionary is
+	/// borrowed or if the reference of the value would not fit in the index
+	/// type `I`.
+	///
+	/// The value is hashed and compared to the registered values while the
+	/// index is not borrowed, and the lookup starts over if the index was
+	/// modified in the meantime. The index is only borrowed mutably to
+	/// register the new reference, without hashing or comparing values.
+	/// It is borrowed by [`Self::validate`], so inserting from the hash
+	/// function or equivalence relation during a validation fails.
+	pub fn try_insert(&self, value: S::Value) -> Result<Ref<S::Value, I>, InsertError<S::Value>>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let hash = self.hasher.hash_one(&value);
+		loop {
+			let version = match self.search(hash, &value) {
+				Ok(r) => return Ok(r),
+				Err(version) => version,
+			};
+
+			let mut table = match self.table.try_borrow_mut() {
+				Ok(table) => table,
+				Err(_) => return Err(InsertError::borrowed(value)),
+			};
+
+			if table.version() == version {
+				let r = self
+					.values
+					.try_insert_const(value)
+					.map_err(InsertError::overflow)?;
+				table.insert(hash, r);
+				return Ok(r);
 			}
-			Entry::Occupied(entry) => *entry.get(),
 		}
 	}
+
+	/// Inserts all the given values and returns their unique references.
+	///
+	/// The returned vector contains the reference of each input value,
+	/// in order. Equal values are given the same reference.
+	pub fn insert_all<J>(&self, values: J) -> Vec<Ref<S::Value, I>>
+	where
+		J: IntoIterator<Item = S::Value>,
+		E: Equivalence<S::Value>,
+	{
+		values.into_iter().map(|value| self.insert(value)).collect()
+	}
 }
 
-impl<S: StorageRemove> HashConstDictionary<S> {
-	pub fn remove<T>(&mut self, r: Ref<T>) -> Option<S::Value>
+impl<S: StorageRemove, H: BuildHasher, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
 	where
-		S::Value: Borrow<T> + Eq + Hash,
+		S::Value: Borrow<T>,
+		E: Equivalence<S::Value>,
 	{
-		match self.values.remove(r) {
-			Some(value) => {
-				let mut map = self.map.borrow_mut();
-				map.remove::<S::Value>(&value);
-				Some(value)
-			}
-			None => None,
-		}
+		let value = self.values.remove(r)?;
+		let hash = self.hasher.hash_one(&value);
+		self.table.get_mut().remove(hash, r.cast());
+		Some(value)
 	}
 
-	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value>, S::Value)>
+	#[allow(clippy::type_complexity)]
+	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value, I>, S::Value)>
 	where
-		S::Value: Eq + Hash,
+		E: Equivalence<S::Value>,
 	{
-		let mut map = self.map.borrow_mut();
-		map.remove(value)
-			.map(|r| (r, self.values.remove(r).unwrap()))
+		let hash = self.hasher.hash_one(value);
+		let table = self.table.get_mut();
+		let r = table.find(&self.values, &self.hasher, hash, value)?;
+		table.remove(hash, r);
+		Some((r, self.values.remove(r).unwrap()))
+	}
+}
+
+impl<S: StorageCompact, H, E, I: RefIndex> HashConstDictionary<S, H, E, I> {
+	/// Moves the values of the dictionary so that they are densely packed,
+	/// updating the references of the dictionary accordingly.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references, to be applied to the references held outside of
+	/// the dictionary.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let remap = self.values.compact();
+		self.table.get_mut().remap(&remap);
+		remap
+	}
+}
+
+impl<'a, S: 'a + StorageIter, H, E, I: RefIndex> IntoIterator
+	for &'a HashConstDictionary<S, H, E, I>
+{
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<S: StorageIntoIter, H, E, I: RefIndex> IntoIterator for HashConstDictionary<S, H, E, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.values.into_iter()
+	}
+}
+
+impl<S: StorageAllocateConst, H: BuildHasher, E, I: RefIndex> Extend<S::Value>
+	for HashConstDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
+	}
+}
+
+impl<S: StorageAllocateConst + Default, H: BuildHasher + Default, E: Default, I: RefIndex>
+	FromIterator<S::Value> for HashConstDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<S: Storage + serde::Serialize, H, E, I: RefIndex> serde::Serialize
+	for HashConstDictionary<S, H, E, I>
+{
+	/// Serializes the underlying storage only.
+	fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+		self.values.as_storage().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, H, E, I> serde::Deserialize<'de> for HashConstDictionary<S, H, E, I>
+where
+	S: StorageIter + serde::Deserialize<'de>,
+	H: BuildHasher + Default,
+	E: Default,
+	I: RefIndex,
+	E: Equivalence<S::Value>,
+{
+	/// Deserializes the underlying storage and rebuilds the dictionary from it.
+	///
+	/// Fails if the same value is stored at two different indices.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let values = Shelf::new(S::deserialize(deserializer)?);
+		let hasher = ValueHasher::<H, E>::default();
+		let (table, collisions) = RefTable::build(&values, &hasher);
+		if let Some(collision) = collisions.first() {
+			return Err(serde::de::Error::custom(format_args!(
+				"duplicate dictionary value at index {}",
+				collision.modified().index()
+			)));
+		}
+
+		Ok(Self {
+			table: RefCell::new(table),
+			hasher,
+			values,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{AppendVec, InsertErrorKind};
+	use std::cell::OnceCell;
+	use std::hash::Hasher;
+	use std::panic::{catch_unwind, AssertUnwindSafe};
+	use std::rc::{Rc, Weak};
+
+	type ReentrantDictionary = HashConstDictionary<AppendVec<String>, RandomState, Reentrant>;
+
+	/// Equivalence inserting a pending value in its own dictionary the next
+	/// time it compares values.
+	///
+	/// All the values have the same hash, so that every lookup compares them.
+	#[derive(Default)]
+	struct Reentrant {
+		dictionary: OnceCell<Weak<ReentrantDictionary>>,
+		pending: RefCell<Option<String>>,
+		errors: RefCell<Vec<InsertErrorKind>>,
+	}
+
+	impl Equivalence<str> for Reentrant {
+		fn equivalent(&self, a: &str, b: &str) -> bool {
+			if let Some(value) = self.pending.take() {
+				let dictionary = self.dictionary.get().unwrap().upgrade().unwrap();
+				if let Err(e) = dictionary.try_insert(value) {
+					self.errors.borrow_mut().push(e.kind())
+				}
+			}
+
+			a == b
+		}
+
+		fn hash<H: Hasher>(&self, _value: &str, _state: &mut H) {}
+	}
+
+	impl Equivalence<String> for Reentrant {
+		fn equivalent(&self, a: &String, b: &String) -> bool {
+			Equivalence::<str>::equivalent(self, a, b)
+		}
+
+		fn hash<H: Hasher>(&self, _value: &String, _state: &mut H) {}
+	}
+
+	#[test]
+	fn index_overflow() {
+		let dictionary: HashConstDictionary<AppendVec<u32>, RandomState, DefaultEquivalence, u16> =
+			HashConstDictionary::default();
+		for i in 0..=u16::MAX as u32 {
+			dictionary.insert(i);
+		}
+
+		let overflow = dictionary.try_insert(u16::MAX as u32 + 1).unwrap_err();
+		assert_eq!(overflow.kind(), InsertErrorKind::Overflow);
+		assert_eq!(overflow.into_value(), u16::MAX as u32 + 1);
+		assert_eq!(dictionary.try_insert(7).unwrap().index(), 7);
+
+		let overflow = catch_unwind(AssertUnwindSafe(|| dictionary.insert(u16::MAX as u32 + 1)));
+		assert!(overflow.is_err());
+
+		assert_eq!(dictionary.len(), u16::MAX as usize + 1);
+		assert_eq!(dictionary.as_shelf().len(), u16::MAX as usize + 1);
+		assert!(!dictionary.contains(&(u16::MAX as u32 + 1)));
+		assert_eq!(dictionary.insert(7).index(), 7);
+		assert!(dictionary.validate().is_valid())
+	}
+
+	#[test]
+	fn insert_all_deduplicates() {
+		let mut dictionary: HashConstDictionary<AppendVec<String>> = HashConstDictionary::default();
+		let refs = dictionary.insert_all(["a", "b", "a", "c", "b"].map(String::from));
+		let indices: Vec<_> = refs.as_slice().iter().map(Ref::index).collect();
+		assert_eq!(indices, [0, 1, 0, 2, 1]);
+		assert_eq!(dictionary.len(), 3);
+
+		dictionary.extend(["c", "d", "a", "d"].map(String::from));
+		assert_eq!(dictionary.len(), 4);
+		assert_eq!(dictionary.get_ref("d").map(|r| r.index()), Some(3));
+
+		let values: Vec<_> = dictionary
+			.iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(values, [(0, "a"), (1, "b"), (2, "c"), (3, "d")]);
+
+		let collected: HashConstDictionary<AppendVec<String>> =
+			["b", "a", "b"].map(String::from).into_iter().collect();
+		assert_eq!(collected.len(), 2);
+		assert_eq!(collected.get_ref("b").map(|r| r.index()), Some(0));
+		assert_eq!(collected.get_ref("a").map(|r| r.index()), Some(1));
+		assert!(collected.validate().is_valid());
+	}
+
+	#[test]
+	fn compact_keeps_lookups() {
+		let mut dictionary: HashConstDictionary<AppendVec<String>> = HashConstDictionary::default();
+		let refs: Vec<_> = (0..20).map(|i| dictionary.insert(i.to_string())).collect();
+		for &r in refs.as_slice().iter().step_by(3) {
+			dictionary.remove(r);
+		}
+
+		let remap = dictionary.compact();
+		assert!(!remap.is_empty());
+		assert_eq!(dictionary.len(), 13);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			let value = i.to_string();
+			if i % 3 == 0 {
+				assert_eq!(dictionary.get_ref(value.as_str()), None);
+			} else {
+				let r = remap.apply(r);
+				assert!(r.index() < 13);
+				assert_eq!(dictionary.get_ref(value.as_str()), Some(r));
+				assert_eq!(dictionary.get(r), Some(&value));
+			}
+		}
+
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(dictionary.insert("0".to_string()).index(), 13);
+	}
+
+	#[test]
+	fn replace_and_modify_collisions() {
+		let mut dictionary: HashConstDictionary<AppendVec<String>> = HashConstDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let collision = dictionary.replace(a, "b".to_string()).unwrap().unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (a, b));
+		let collision = dictionary
+			.modify(b, |value| *value = "a".to_string())
+			.unwrap()
+			.unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (b, a));
+
+		assert_eq!(dictionary.get(a).map(String::as_str), Some("a"));
+		assert_eq!(dictionary.get(b).map(String::as_str), Some("b"));
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(dictionary.validate().is_valid());
+
+		assert_eq!(
+			dictionary.replace(a, "a".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(
+			dictionary.replace(a, "c".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(dictionary.modify(b, |value| value.push('d')), Some(Ok(())));
+		assert_eq!(dictionary.replace(Ref::new(2), "e".to_string()), None);
+		assert_eq!(dictionary.modify(Ref::new(2), |_| unreachable!()), None);
+
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), None);
+		assert_eq!(dictionary.get_ref("b"), None);
+		assert_eq!(dictionary.get_ref("c"), Some(a));
+		assert_eq!(dictionary.get_ref("bd"), Some(b));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn rebuild_corrupted_ref_table() {
+		let mut dictionary: HashConstDictionary<AppendVec<u32>> = HashConstDictionary::default();
+		let refs = dictionary.insert_all((0..500).map(|i| (i * 7919 % 1000) as u32 * 2));
+		assert_eq!(dictionary.len(), refs.len());
+
+		for &r in refs.as_slice().iter().step_by(7) {
+			*dictionary.get_mut_unchecked(r).unwrap() += 1001;
+		}
+
+		let report = dictionary.validate();
+		assert!(!report.is_valid());
+		assert_eq!(report.mismatched().len(), refs.len().div_ceil(7));
+		assert!(dictionary.rebuild_map().is_empty());
+		assert!(dictionary.validate().is_valid());
+		for (r, value) in dictionary.iter() {
+			assert_eq!(dictionary.get_ref(value), Some(r));
+		}
+
+		let (a, b) = (refs[0], refs[1]);
+		let value = *dictionary.get(b).unwrap();
+		*dictionary.get_mut_unchecked(a).unwrap() = value;
+		let collisions = dictionary.rebuild_map();
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].modified(), a.max(b));
+		assert_eq!(collisions[0].existing(), a.min(b));
+		assert_eq!(dictionary.len(), refs.len() - 1);
+		assert_eq!(dictionary.get_ref(&value), Some(a.min(b)));
+		assert_eq!(dictionary.validate().missing(), &[a.max(b)]);
+	}
+
+	#[test]
+	fn reentrant_insert() {
+		let dictionary = Rc::new(HashConstDictionary::with_equivalence(
+			AppendVec::default(),
+			RandomState::new(),
+			Reentrant::default(),
+		));
+		let reentrant = dictionary.equivalence();
+		reentrant
+			.dictionary
+			.set(Rc::downgrade(&dictionary))
+			.unwrap();
+
+		let a = dictionary.insert("a".to_string());
+		*reentrant.pending.borrow_mut() = Some("b".to_string());
+		let c = dictionary.insert("c".to_string());
+		assert_eq!(dictionary.get_ref("b"), Some(Ref::new(1)));
+		assert_eq!(c, Ref::new(2));
+
+		*reentrant.pending.borrow_mut() = Some("d".to_string());
+		let d = dictionary.insert("d".to_string());
+		assert_eq!(d, Ref::new(3));
+		assert_eq!(dictionary.insert("a".to_string()), a);
+		assert_eq!(dictionary.len(), 4);
+		assert!(reentrant.errors.borrow().is_empty());
+
+		*reentrant.pending.borrow_mut() = Some("e".to_string());
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(*reentrant.errors.borrow(), [InsertErrorKind::Borrowed]);
+		assert!(!dictionary.contains("e"));
+		assert_eq!(dictionary.len(), 4);
 	}
 }
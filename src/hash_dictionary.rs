@@ -1,58 +1,85 @@
-use crate::{Ref, Shelf, Storage, StorageAllocate, StorageMut, StorageRemove};
+use crate::dictionary::{RefTable, ValueHasher};
+use crate::shelf::{IntoIter, Iter};
+use crate::{
+	Collision, DefaultEquivalence, DictionaryReport, Equivalence, Ref, RefIndex, Remap, Shelf,
+	Snapshot, Storage, StorageAllocate, StorageCompact, StorageIntoIter, StorageIter, StorageMut,
+	StorageRemove, UndoLog,
+};
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
 
-/// Bidirectional mapping between `T` and `Ref<T>`.
-pub struct HashDictionary<S: Storage> {
-	map: HashMap<S::Value, Ref<S::Value>>,
-	values: Shelf<S>,
+/// Bidirectional mapping between `T` and `Ref<T, I>`.
+///
+/// Each value is stored only once, in the shelf of the dictionary.
+/// Values are mapped to their reference by a hash table of references,
+/// hashing and comparing the values through the shelf.
+pub struct HashDictionary<S: Storage, H = RandomState, E = DefaultEquivalence, I: RefIndex = usize>
+{
+	table: RefTable<S::Value, I>,
+	hasher: ValueHasher<H, E>,
+	values: Shelf<S, I>,
 }
 
-impl<S: Storage + Default> Default for HashDictionary<S> {
+impl<S: Storage + Default, H: Default, E: Default, I: RefIndex> Default
+	for HashDictionary<S, H, E, I>
+{
 	fn default() -> Self {
-		Self {
-			map: HashMap::new(),
-			values: Shelf::default(),
-		}
+		Self::with_equivalence(S::default(), H::default(), E::default())
 	}
 }
 
 impl<S: Storage> HashDictionary<S> {
 	/// Creates a new empty dictionary.
 	pub fn new(storage: S) -> Self {
+		Self::with_hasher(storage, RandomState::new())
+	}
+}
+
+impl<S: Storage, H, E, I: RefIndex> HashDictionary<S, H, E, I> {
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values.
+	pub fn with_hasher(storage: S, hasher: H) -> Self
+	where
+		E: Default,
+	{
+		Self::with_equivalence(storage, hasher, E::default())
+	}
+
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values, and the given equivalence relation to compare them.
+	pub fn with_equivalence(storage: S, hasher: H, equivalence: E) -> Self {
 		Self {
-			map: HashMap::new(),
+			table: RefTable::new(),
+			hasher: ValueHasher::new(hasher, equivalence),
 			values: Shelf::new(storage),
 		}
 	}
 
-	pub fn len(&self) -> usize {
-		self.map.len()
+	/// Returns the hash builder of the dictionary.
+	pub fn hasher(&self) -> &H {
+		&self.hasher.builder
 	}
 
-	pub fn is_empty(&self) -> bool {
-		self.map.is_empty()
+	/// Returns the equivalence relation of the dictionary.
+	pub fn equivalence(&self) -> &E {
+		&self.hasher.equivalence
 	}
 
-	#[allow(clippy::type_complexity)]
-	pub fn into_parts(self) -> (HashMap<S::Value, Ref<S::Value>>, Shelf<S>) {
-		(self.map, self.values)
-	}
-
-	pub fn into_map(self) -> HashMap<S::Value, Ref<S::Value>> {
-		self.map
+	pub fn len(&self) -> usize {
+		self.table.len()
 	}
 
-	pub fn as_map(&self) -> &HashMap<S::Value, Ref<S::Value>> {
-		&self.map
+	pub fn is_empty(&self) -> bool {
+		self.table.is_empty()
 	}
 
-	pub fn into_shelf(self) -> Shelf<S> {
+	pub fn into_shelf(self) -> Shelf<S, I> {
 		self.values
 	}
 
-	pub fn as_shelf(&self) -> &Shelf<S> {
+	pub fn as_shelf(&self) -> &Shelf<S, I> {
 		&self.values
 	}
 
@@ -64,82 +91,584 @@ impl<S: Storage> HashDictionary<S> {
 		self.values.as_storage()
 	}
 
-	/// Borrows the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow<T>(&self, r: Ref<T>) -> Option<&T>
+	/// Borrows the definition (`T`) associated to the given term (`Ref<T, I>`).
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.borrow(r)
 	}
 
-	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get<T>(&self, r: Ref<T>) -> Option<&S::Value>
+	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.values.get(r)
 	}
+
+	/// Returns the reference (`Ref<T, I>`) associated to the given definition, if any.
+	///
+	/// The definition may be any borrowed form of the stored value type,
+	/// which allows querying the dictionary without inserting or even
+	/// allocating the value.
+	pub fn get_ref<Q>(&self, value: &Q) -> Option<Ref<S::Value, I>>
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		let hash = self.hasher.hash_one(value);
+		self.table.find(&self.values, &self.hasher, hash, value)
+	}
+
+	/// Checks if the given definition is registered in the dictionary.
+	pub fn contains<Q>(&self, value: &Q) -> bool
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		self.get_ref(value).is_some()
+	}
 }
 
-impl<S: StorageMut> HashDictionary<S> {
-	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T>`).
-	pub fn borrow_mut<T>(&mut self, r: Ref<T>) -> Option<&mut T>
+impl<S: Storage, H, I: RefIndex> HashDictionary<S, H, DefaultEquivalence, I> {
+	/// Returns the map from the values of the dictionary to their reference,
+	/// borrowing the values.
+	///
+	/// The map is built in `O(n)` time, and uses a clone of the hash builder
+	/// of the dictionary. It is only available with the default equivalence,
+	/// so that it compares the values as the dictionary does.
+	pub fn as_map(&self) -> HashMap<&S::Value, Ref<S::Value, I>, H>
+	where
+		S: StorageIter,
+		S::Value: Eq + Hash,
+		H: BuildHasher + Clone,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder.clone());
+		map.extend(self.values.iter().map(|(r, value)| (value, r)));
+		map
+	}
+
+	/// Turns the dictionary into the map from its values to their reference.
+	///
+	/// The map is built in `O(n)` time, and uses the hash builder of the
+	/// dictionary.
+	pub fn into_map(self) -> HashMap<S::Value, Ref<S::Value, I>, H>
+	where
+		S: StorageIntoIter,
+		S::Value: Eq + Hash,
+		H: BuildHasher,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder);
+		map.extend(self.values.into_iter().map(|(r, value)| (value, r)));
+		map
+	}
+
+	/// Splits the dictionary into the map from its values to their reference,
+	/// and its shelf.
+	///
+	/// Every value is cloned into the map, in `O(n)` time, so that it is
+	/// stored twice. Use [`Self::as_map`] to only borrow the values.
+	/// The map uses the hash builder of the dictionary.
+	#[allow(clippy::type_complexity)]
+	pub fn into_parts(self) -> (HashMap<S::Value, Ref<S::Value, I>, H>, Shelf<S, I>)
+	where
+		S: StorageIter,
+		S::Value: Eq + Hash + Clone,
+		H: BuildHasher,
+	{
+		let mut map = HashMap::with_capacity_and_hasher(self.len(), self.hasher.builder);
+		map.extend(self.values.iter().map(|(r, value)| (value.clone(), r)));
+		(map, self.values)
+	}
+}
+
+impl<S: StorageIter, H, E, I: RefIndex> HashDictionary<S, H, E, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, in storage order.
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		self.values.iter()
+	}
+
+	/// Checks that the value-to-reference index of the dictionary is
+	/// consistent with its shelf.
+	pub fn validate(&self) -> DictionaryReport<S::Value, I>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		DictionaryReport::new(
+			&self.values,
+			self.table.iter(),
+			|value| self.get_ref(value),
+			|a, b| self.hasher.equivalence.equivalent(a, b),
+		)
+	}
+
+	/// Rebuilds the value-to-reference index of the dictionary from its
+	/// shelf.
+	///
+	/// Only the first of several equal values, in storage order, is
+	/// registered. The others stay in the shelf, and are returned as
+	/// collisions.
+	pub fn rebuild_map(&mut self) -> Vec<Collision<S::Value, I>>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		let (table, collisions) = RefTable::build(&self.values, &self.hasher);
+		self.table = table;
+		collisions
+	}
+}
+
+impl<S: StorageMut, H: BuildHasher, E, I: RefIndex> HashDictionary<S, H, E, I> {
+	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn borrow_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.borrow_mut(r)
 	}
 
-	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T>`).
-	pub fn get_mut<T>(&mut self, r: Ref<T>) -> Option<&mut S::Value>
+	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn get_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.values.get_mut(r)
 	}
+
+	/// Replaces the definition associated to the given term, and returns the
+	/// old definition.
+	///
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the new definition is already associated to another term, the
+	/// dictionary is left unchanged and a [`Collision`] is returned.
+	#[allow(clippy::type_complexity)]
+	pub fn replace(
+		&mut self,
+		r: Ref<S::Value, I>,
+		value: S::Value,
+	) -> Option<Result<S::Value, Collision<S::Value, I>>>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let old_hash = self.hasher.hash_one(self.values.get::<S::Value>(r)?);
+		let hash = self.hasher.hash_one(&value);
+		match self.table.find(&self.values, &self.hasher, hash, &value) {
+			Some(existing) if existing != r => Some(Err(Collision::new(r, existing))),
+			_ => {
+				self.table.remove(old_hash, r);
+				let old = std::mem::replace(self.values.get_mut::<S::Value>(r).unwrap(), value);
+				self.table.insert(hash, r);
+				Some(Ok(old))
+			}
+		}
+	}
+
+	/// Modifies the definition associated to the given term, keeping the
+	/// dictionary consistent.
+	///
+	/// The given function is applied to a copy of the definition, which then
+	/// replaces it as with [`Self::replace`].
+	/// The definition cannot be modified in place since it must be left
+	/// unchanged on collision, hence the `S::Value: Clone` bound. Use
+	/// [`Self::replace`] for definitions that cannot be cloned.
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the modified definition is equal to the definition of another term,
+	/// the dictionary is left unchanged and a [`Collision`] is returned.
+	pub fn modify(
+		&mut self,
+		r: Ref<S::Value, I>,
+		f: impl FnOnce(&mut S::Value),
+	) -> Option<Result<(), Collision<S::Value, I>>>
+	where
+		S::Value: Clone,
+		E: Equivalence<S::Value>,
+	{
+		let mut value = self.values.get::<S::Value>(r)?.clone();
+		f(&mut value);
+		self.replace(r, value).map(|result| result.map(|_| ()))
+	}
 }
 
-impl<S: StorageAllocate> HashDictionary<S> {
+impl<S: StorageAllocate, H: BuildHasher, E, I: RefIndex> HashDictionary<S, H, E, I> {
 	/// Inserts a new value and returns its unique reference.
 	///
 	/// If the value is already registered, its old reference is returned
 	/// without overriding the old value.
-	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value>
+	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value, I>
 	where
-		S::Value: Clone + Eq + Hash,
+		E: Equivalence<S::Value>,
 	{
-		use std::collections::hash_map::Entry;
-		match self.map.entry(value) {
-			Entry::Vacant(entry) => {
-				let r = self.values.insert(entry.key().clone());
-				entry.insert(r);
+		let hash = self.hasher.hash_one(&value);
+		match self.table.find(&self.values, &self.hasher, hash, &value) {
+			Some(r) => r,
+			None => {
+				let r = self.values.insert(value);
+				self.table.insert(hash, r);
 				r
 			}
-			Entry::Occupied(entry) => *entry.get(),
 		}
 	}
+
+	/// Inserts all the given values and returns their unique references.
+	///
+	/// The returned vector contains the reference of each input value,
+	/// in order. Equal values are given the same reference.
+	pub fn insert_all<J>(&mut self, values: J) -> Vec<Ref<S::Value, I>>
+	where
+		J: IntoIterator<Item = S::Value>,
+		E: Equivalence<S::Value>,
+	{
+		values.into_iter().map(|value| self.insert(value)).collect()
+	}
 }
 
-impl<S: StorageRemove> HashDictionary<S> {
-	pub fn remove<T>(&mut self, r: Ref<T>) -> Option<S::Value>
+impl<S: StorageRemove, H: BuildHasher, E, I: RefIndex> HashDictionary<S, H, E, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
 	where
-		S::Value: Borrow<T> + Eq + Hash,
+		S::Value: Borrow<T>,
+		E: Equivalence<S::Value>,
 	{
-		match self.values.remove(r) {
-			Some(value) => {
-				self.map.remove::<S::Value>(&value);
-				Some(value)
-			}
-			None => None,
-		}
+		let value = self.values.remove(r)?;
+		self.table.remove(self.hasher.hash_one(&value), r.cast());
+		Some(value)
 	}
 
-	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value>, S::Value)>
+	#[allow(clippy::type_complexity)]
+	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value, I>, S::Value)>
 	where
-		S::Value: Eq + Hash,
+		E: Equivalence<S::Value>,
 	{
-		self.map
-			.remove(value)
-			.map(|r| (r, self.values.remove(r).unwrap()))
+		let hash = self.hasher.hash_one(value);
+		let r = self.table.find(&self.values, &self.hasher, hash, value)?;
+		self.table.remove(hash, r);
+		Some((r, self.values.remove(r).unwrap()))
+	}
+}
+
+impl<S: StorageCompact, H, E, I: RefIndex> HashDictionary<S, H, E, I> {
+	/// Moves the values of the dictionary so that they are densely packed,
+	/// updating the references of the dictionary accordingly.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references, to be applied to the references held outside of
+	/// the dictionary.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let remap = self.values.compact();
+		self.table.remap(&remap);
+		remap
+	}
+}
+
+impl<S: StorageIter, H: BuildHasher, E, I: RefIndex> HashDictionary<UndoLog<S>, H, E, I> {
+	/// Takes a snapshot of the dictionary.
+	///
+	/// The values inserted or removed from now on can be restored using
+	/// [`Self::rollback_to`], or kept using [`Self::commit`].
+	pub fn snapshot(&mut self) -> Snapshot {
+		self.values.snapshot()
+	}
+
+	/// Restores the dictionary as it was when the given snapshot was taken.
+	///
+	/// Values inserted since then are un-interned, and removed values are
+	/// registered again under their original reference.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn rollback_to(&mut self, snapshot: Snapshot)
+	where
+		E: Equivalence<S::Value>,
+	{
+		let modified = self.values.modified_since(&snapshot);
+		for &r in &modified {
+			if let Some(value) = self.values.get(r) {
+				self.table.remove(self.hasher.hash_one(value), r);
+			}
+		}
+
+		self.values.rollback_to(snapshot);
+		for r in modified {
+			if let Some(value) = self.values.get(r) {
+				let hash = self.hasher.hash_one(value);
+				if self
+					.table
+					.find(&self.values, &self.hasher, hash, value)
+					.is_none()
+				{
+					self.table.insert(hash, r);
+				}
+			}
+		}
+	}
+
+	/// Keeps the values inserted or removed since the given snapshot was
+	/// taken.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn commit(&mut self, snapshot: Snapshot) {
+		self.values.commit(snapshot)
+	}
+}
+
+impl<'a, S: 'a + StorageIter, H, E, I: RefIndex> IntoIterator for &'a HashDictionary<S, H, E, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<S: StorageIntoIter, H, E, I: RefIndex> IntoIterator for HashDictionary<S, H, E, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.values.into_iter()
+	}
+}
+
+impl<S: StorageAllocate, H: BuildHasher, E, I: RefIndex> Extend<S::Value>
+	for HashDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
+	}
+}
+
+impl<S: StorageAllocate + Default, H: BuildHasher + Default, E: Default, I: RefIndex>
+	FromIterator<S::Value> for HashDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<S: Storage + serde::Serialize, H, E, I: RefIndex> serde::Serialize
+	for HashDictionary<S, H, E, I>
+{
+	/// Serializes the underlying storage only.
+	fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+		self.values.as_storage().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, H, E, I> serde::Deserialize<'de> for HashDictionary<S, H, E, I>
+where
+	S: StorageIter + serde::Deserialize<'de>,
+	H: BuildHasher + Default,
+	E: Default,
+	I: RefIndex,
+	E: Equivalence<S::Value>,
+{
+	/// Deserializes the underlying storage and rebuilds the dictionary from it.
+	///
+	/// Fails if the same value is stored at two different indices.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let values = Shelf::new(S::deserialize(deserializer)?);
+		let hasher = ValueHasher::<H, E>::default();
+		let (table, collisions) = RefTable::build(&values, &hasher);
+		if let Some(collision) = collisions.first() {
+			return Err(serde::de::Error::custom(format_args!(
+				"duplicate dictionary value at index {}",
+				collision.modified().index()
+			)));
+		}
+
+		Ok(Self {
+			table,
+			hasher,
+			values,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::FreeList;
+
+	#[test]
+	fn rollback_uninterns_values() {
+		let mut dictionary: HashDictionary<UndoLog<FreeList<String>>> = HashDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let outer = dictionary.snapshot();
+		let c = dictionary.insert("c".to_string());
+		dictionary.remove(a);
+
+		let inner = dictionary.snapshot();
+		dictionary.insert("d".to_string());
+		dictionary.remove(b);
+		assert_eq!(dictionary.insert("c".to_string()), c);
+
+		dictionary.rollback_to(inner);
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert_eq!(dictionary.get_ref("c"), Some(c));
+		assert!(!dictionary.contains("a"));
+		assert!(!dictionary.contains("d"));
+		assert!(dictionary.validate().is_valid());
+
+		dictionary.rollback_to(outer);
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(!dictionary.contains("c"));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn insert_all_deduplicates() {
+		let mut dictionary: HashDictionary<Vec<String>> = HashDictionary::default();
+		let refs = dictionary.insert_all(["a", "b", "a", "c", "b"].map(String::from));
+		let indices: Vec<_> = refs.as_slice().iter().map(Ref::index).collect();
+		assert_eq!(indices, [0, 1, 0, 2, 1]);
+		assert_eq!(dictionary.len(), 3);
+
+		dictionary.extend(["c", "d", "a", "d"].map(String::from));
+		assert_eq!(dictionary.len(), 4);
+		assert_eq!(dictionary.get_ref("d").map(|r| r.index()), Some(3));
+
+		let values: Vec<_> = dictionary
+			.iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(values, [(0, "a"), (1, "b"), (2, "c"), (3, "d")]);
+
+		let collected: HashDictionary<Vec<String>> =
+			["b", "a", "b"].map(String::from).into_iter().collect();
+		assert_eq!(collected.len(), 2);
+		assert_eq!(collected.get_ref("b").map(|r| r.index()), Some(0));
+		assert_eq!(collected.get_ref("a").map(|r| r.index()), Some(1));
+		assert!(collected.validate().is_valid());
+	}
+
+	#[test]
+	fn compact_keeps_lookups() {
+		let mut dictionary: HashDictionary<HashMap<usize, String>> = HashDictionary::default();
+		let refs: Vec<_> = (0..20).map(|i| dictionary.insert(i.to_string())).collect();
+		for &r in refs.as_slice().iter().step_by(3) {
+			dictionary.remove(r);
+		}
+
+		let remap = dictionary.compact();
+		assert!(!remap.is_empty());
+		assert_eq!(dictionary.len(), 13);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			let value = i.to_string();
+			if i % 3 == 0 {
+				assert_eq!(dictionary.get_ref(value.as_str()), None);
+			} else {
+				let r = remap.apply(r);
+				assert!(r.index() < 13);
+				assert_eq!(dictionary.get_ref(value.as_str()), Some(r));
+				assert_eq!(dictionary.get(r), Some(&value));
+			}
+		}
+
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(dictionary.insert("0".to_string()).index(), 13);
+	}
+
+	#[test]
+	fn replace_and_modify_collisions() {
+		let mut dictionary: HashDictionary<Vec<String>> = HashDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let collision = dictionary.replace(a, "b".to_string()).unwrap().unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (a, b));
+		let collision = dictionary
+			.modify(b, |value| *value = "a".to_string())
+			.unwrap()
+			.unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (b, a));
+
+		assert_eq!(dictionary.get(a).map(String::as_str), Some("a"));
+		assert_eq!(dictionary.get(b).map(String::as_str), Some("b"));
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(dictionary.validate().is_valid());
+
+		assert_eq!(
+			dictionary.replace(a, "a".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(
+			dictionary.replace(a, "c".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(dictionary.modify(b, |value| value.push('d')), Some(Ok(())));
+		assert_eq!(dictionary.replace(Ref::new(2), "e".to_string()), None);
+		assert_eq!(dictionary.modify(Ref::new(2), |_| unreachable!()), None);
+
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), None);
+		assert_eq!(dictionary.get_ref("b"), None);
+		assert_eq!(dictionary.get_ref("c"), Some(a));
+		assert_eq!(dictionary.get_ref("bd"), Some(b));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn rebuild_corrupted_ref_table() {
+		let mut dictionary: HashDictionary<FreeList<u32>> = HashDictionary::default();
+		let refs = dictionary.insert_all((0..500).map(|i| (i * 7919 % 1000) as u32 * 2));
+		assert_eq!(dictionary.len(), refs.len());
+
+		for &r in refs.as_slice().iter().step_by(7) {
+			*dictionary.get_mut_unchecked(r).unwrap() += 1001;
+		}
+
+		let report = dictionary.validate();
+		assert!(!report.is_valid());
+		assert_eq!(report.mismatched().len(), refs.len().div_ceil(7));
+		assert!(dictionary.rebuild_map().is_empty());
+		assert!(dictionary.validate().is_valid());
+		for (r, value) in dictionary.iter() {
+			assert_eq!(dictionary.get_ref(value), Some(r));
+		}
+
+		let (a, b) = (refs[0], refs[1]);
+		let value = *dictionary.get(b).unwrap();
+		*dictionary.get_mut_unchecked(a).unwrap() = value;
+		let collisions = dictionary.rebuild_map();
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].modified(), a.max(b));
+		assert_eq!(collisions[0].existing(), a.min(b));
+		assert_eq!(dictionary.len(), refs.len() - 1);
+		assert_eq!(dictionary.get_ref(&value), Some(a.min(b)));
+		assert_eq!(dictionary.validate().missing(), &[a.max(b)]);
 	}
 }
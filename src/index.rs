@@ -0,0 +1,88 @@
+use std::fmt;
+use std::hash::Hash;
+use std::num::{NonZeroU16, NonZeroU32, NonZeroUsize};
+
+/// Index type of a [`Ref`](crate::Ref).
+///
+/// A reference stores the index of its value using this type, which can be
+/// narrower than `usize` to reduce the size of the values holding references.
+/// The `NonZero` types store the index plus one, so that an
+/// `Option<Ref<T, I>>` has the same size as a `Ref<T, I>`.
+pub trait RefIndex: Copy + Ord + Hash + fmt::Debug {
+	/// Greatest storage index that fits in this type.
+	const MAX_INDEX: usize;
+
+	/// Converts a storage index into a reference index.
+	///
+	/// Returns `None` if the index does not fit.
+	fn from_index(index: usize) -> Option<Self>;
+
+	/// Returns the storage index.
+	fn index(self) -> usize;
+}
+
+impl RefIndex for usize {
+	const MAX_INDEX: usize = usize::MAX;
+
+	fn from_index(index: usize) -> Option<Self> {
+		Some(index)
+	}
+
+	fn index(self) -> usize {
+		self
+	}
+}
+
+/// Returns the given index, saturated to `usize::MAX`.
+const fn max_index(index: u128) -> usize {
+	if index < usize::MAX as u128 {
+		index as usize
+	} else {
+		usize::MAX
+	}
+}
+
+macro_rules! ref_index {
+	($($ty:ty : $non_zero:ty),*) => {
+		$(
+			impl RefIndex for $ty {
+				const MAX_INDEX: usize = max_index(<$ty>::MAX as u128);
+
+				fn from_index(index: usize) -> Option<Self> {
+					index.try_into().ok()
+				}
+
+				fn index(self) -> usize {
+					self as usize
+				}
+			}
+
+			impl RefIndex for $non_zero {
+				const MAX_INDEX: usize = max_index(<$ty>::MAX as u128 - 1);
+
+				fn from_index(index: usize) -> Option<Self> {
+					let index: $ty = index.checked_add(1)?.try_into().ok()?;
+					Self::new(index)
+				}
+
+				fn index(self) -> usize {
+					self.get() as usize - 1
+				}
+			}
+		)*
+	};
+}
+
+ref_index!(u16: NonZeroU16, u32: NonZeroU32);
+
+impl RefIndex for NonZeroUsize {
+	const MAX_INDEX: usize = usize::MAX - 1;
+
+	fn from_index(index: usize) -> Option<Self> {
+		Self::new(index.checked_add(1)?)
+	}
+
+	fn index(self) -> usize {
+		self.get() - 1
+	}
+}
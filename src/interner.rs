@@ -0,0 +1,292 @@
+use crate::Ref;
+use hashbrown::HashTable;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+/// String interner, mapping each distinct string to a unique `Ref<str>`.
+///
+/// Contrarily to a `HashDictionary<Vec<String>>`, the interned strings are
+/// concatenated in a single arena instead of being allocated separately, and
+/// can be looked up from a `&str` without allocating.
+/// The reference of the `i`-th interned string has index `i`.
+///
+/// The interner is not built on top of a dictionary since shelves store
+/// sized values, whereas interned strings are slices of a single arena.
+/// As with hash dictionaries, strings are hashed using the `H` hash builder.
+pub struct Interner<H = RandomState> {
+	/// Concatenation of all the interned strings.
+	arena: String,
+	/// End offset of each interned string in the arena.
+	ends: Vec<usize>,
+	table: HashTable<Ref<str>>,
+	hasher: H,
+}
+
+impl<H: Default> Default for Interner<H> {
+	fn default() -> Self {
+		Self::with_hasher(H::default())
+	}
+}
+
+/// Returns the string referenced by `r`, given the arena and string ends of
+/// an interner.
+fn resolve<'a>(arena: &'a str, ends: &[usize], r: Ref<str>) -> &'a str {
+	let i = r.index();
+	let start = if i == 0 { 0 } else { ends[i - 1] };
+	&arena[start..ends[i]]
+}
+
+impl Interner {
+	/// Creates a new empty interner.
+	pub fn new() -> Self {
+		Self::with_hasher(RandomState::new())
+	}
+
+	/// Creates a new empty interner with enough capacity to store `len`
+	/// strings totalling `bytes` bytes without reallocating.
+	pub fn with_capacity(len: usize, bytes: usize) -> Self {
+		Self::with_capacity_and_hasher(len, bytes, RandomState::new())
+	}
+}
+
+impl<H> Interner<H> {
+	/// Creates a new empty interner using the given hash builder to hash the
+	/// strings.
+	pub fn with_hasher(hasher: H) -> Self {
+		Self {
+			arena: String::new(),
+			ends: Vec::new(),
+			table: HashTable::new(),
+			hasher,
+		}
+	}
+
+	/// Creates a new empty interner with enough capacity to store `len`
+	/// strings totalling `bytes` bytes without reallocating, using the given
+	/// hash builder to hash the strings.
+	pub fn with_capacity_and_hasher(len: usize, bytes: usize, hasher: H) -> Self {
+		Self {
+			arena: String::with_capacity(bytes),
+			ends: Vec::with_capacity(len),
+			table: HashTable::with_capacity(len),
+			hasher,
+		}
+	}
+
+	/// Returns the hash builder of the interner.
+	pub fn hasher(&self) -> &H {
+		&self.hasher
+	}
+
+	/// Returns the number of interned strings.
+	pub fn len(&self) -> usize {
+		self.ends.len()
+	}
+
+	/// Checks if no string has been interned.
+	pub fn is_empty(&self) -> bool {
+		self.ends.is_empty()
+	}
+
+	/// Returns the concatenation of all the interned strings, in order.
+	pub fn as_str(&self) -> &str {
+		&self.arena
+	}
+
+	/// Returns the string referenced by `r`.
+	///
+	/// # Panics
+	///
+	/// Panics if `r` does not reference a string of this interner.
+	pub fn resolve(&self, r: Ref<str>) -> &str {
+		self.try_resolve(r)
+			.expect("reference to a string of another interner")
+	}
+
+	/// Returns the string referenced by `r`, if any.
+	pub fn try_resolve(&self, r: Ref<str>) -> Option<&str> {
+		if r.index() < self.ends.len() {
+			Some(resolve(&self.arena, &self.ends, r))
+		} else {
+			None
+		}
+	}
+
+	/// Returns an iterator over the references and interned strings, in
+	/// interning order.
+	pub fn iter(&self) -> Iter<'_, H> {
+		Iter {
+			interner: self,
+			index: 0,
+		}
+	}
+}
+
+impl<H: BuildHasher> Interner<H> {
+	/// Interns the given string and returns its unique reference.
+	///
+	/// If the string is already interned, its old reference is returned.
+	pub fn intern(&mut self, value: &str) -> Ref<str> {
+		let hash = self.hasher.hash_one(value);
+		let (arena, ends) = (&self.arena, &self.ends);
+		let entry = self.table.entry(
+			hash,
+			|r| resolve(arena, ends, *r) == value,
+			|r| self.hasher.hash_one(resolve(arena, ends, *r)),
+		);
+
+		match entry {
+			hashbrown::hash_table::Entry::Occupied(entry) => *entry.get(),
+			hashbrown::hash_table::Entry::Vacant(entry) => {
+				let r = Ref::new(self.ends.len());
+				self.arena.push_str(value);
+				self.ends.push(self.arena.len());
+				entry.insert(r);
+				r
+			}
+		}
+	}
+
+	/// Returns the reference of the given string, if it is interned.
+	pub fn get(&self, value: &str) -> Option<Ref<str>> {
+		let hash = self.hasher.hash_one(value);
+		self.table
+			.find(hash, |r| resolve(&self.arena, &self.ends, *r) == value)
+			.copied()
+	}
+
+	/// Checks if the given string is interned.
+	pub fn contains(&self, value: &str) -> bool {
+		self.get(value).is_some()
+	}
+}
+
+impl<'a, H> IntoIterator for &'a Interner<H> {
+	type Item = (Ref<str>, &'a str);
+	type IntoIter = Iter<'a, H>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+pub struct Iter<'a, H = RandomState> {
+	interner: &'a Interner<H>,
+	index: usize,
+}
+
+impl<'a, H> Iterator for Iter<'a, H> {
+	type Item = (Ref<str>, &'a str);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let r = Ref::new(self.index);
+		let value = self.interner.try_resolve(r)?;
+		self.index += 1;
+		Some((r, value))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let len = self.interner.len() - self.index;
+		(len, Some(len))
+	}
+}
+
+impl<H> ExactSizeIterator for Iter<'_, H> {}
+
+impl<'a, H: BuildHasher> Extend<&'a str> for Interner<H> {
+	fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+		for value in iter {
+			self.intern(value);
+		}
+	}
+}
+
+impl<'a, H: BuildHasher + Default> FromIterator<&'a str> for Interner<H> {
+	fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<H> serde::Serialize for Interner<H> {
+	/// Serializes the interned strings as a sequence, in interning order.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		serializer.collect_seq(self.iter().map(|(_, value)| value))
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, H: BuildHasher + Default> serde::Deserialize<'de> for Interner<H> {
+	/// Deserializes a sequence of strings, interned in order.
+	///
+	/// Fails if the same string appears twice, since it would not keep its
+	/// index.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let values = Vec::<String>::deserialize(deserializer)?;
+		let mut result = Self::with_capacity_and_hasher(
+			values.len(),
+			values.iter().map(String::len).sum(),
+			H::default(),
+		);
+		for (i, value) in values.iter().enumerate() {
+			if result.intern(value).index() != i {
+				return Err(serde::de::Error::custom(format_args!(
+					"duplicate interned string at index {i}"
+				)));
+			}
+		}
+
+		Ok(result)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::hash_map::DefaultHasher;
+	use std::hash::BuildHasherDefault;
+
+	#[test]
+	fn intern_and_resolve() {
+		let mut interner = Interner::new();
+		let a = interner.intern("alpha");
+		let b = interner.intern("beta");
+		let empty = interner.intern("");
+		assert_eq!((a.index(), b.index(), empty.index()), (0, 1, 2));
+
+		assert_eq!(interner.get("beta"), Some(b));
+		assert_eq!(interner.get("gamma"), None);
+		assert!(interner.contains(""));
+		assert_eq!(interner.resolve(a), "alpha");
+		assert_eq!(interner.resolve(empty), "");
+		assert_eq!(interner.try_resolve(Ref::new(3)), None);
+		assert_eq!(interner.as_str(), "alphabeta");
+	}
+
+	#[test]
+	fn deduplicate() {
+		let mut interner: Interner = ["a", "b", "a", "ab", "b"].into_iter().collect();
+		assert_eq!(interner.len(), 3);
+		assert_eq!(interner.intern("ab").index(), 2);
+		interner.extend(["c", "a"]);
+		assert_eq!(interner.len(), 4);
+
+		let strings: Vec<_> = interner.iter().map(|(r, s)| (r.index(), s)).collect();
+		assert_eq!(strings, [(0, "a"), (1, "b"), (2, "ab"), (3, "c")]);
+		assert_eq!(interner.as_str(), "ababc");
+	}
+
+	#[test]
+	fn custom_hasher() {
+		let mut interner: Interner<BuildHasherDefault<DefaultHasher>> = Interner::default();
+		let r = interner.intern("value");
+		assert_eq!(interner.intern("value"), r);
+		assert_eq!(interner.get("value"), Some(r));
+		assert_eq!(
+			interner.hasher().hash_one("value"),
+			BuildHasherDefault::<DefaultHasher>::default().hash_one("value")
+		);
+	}
+}
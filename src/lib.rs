@@ -7,24 +7,74 @@
 //! This library provides a `Storage` implementation for `Vec`, `BTreeMap` and `HashMap`.
 //! In addition, a `Storage` implementation is provided for the `slab::Slab` type by enabling
 //! the `slab-storage` feature.
+//! Dedicated storages are also provided:
+//! - `Generational`, detecting the use of stale references,
+//! - `AppendVec`, allocating values through a shared reference,
+//! - `SyncAppendVec`, its thread-safe counterpart,
+//! - `SparseSet`, packing the values of sparse side tables,
+//! - `FreeList`, reusing the slots of removed values,
+//! - `Monotonic`, allocating the indices of a map storage with a counter,
+//! - `UndoLog`, recording the modifications of a storage to undo them,
+//! - `PersistentVec`, sharing its values between its clones,
+//! - `MmapStorage`, reading fixed-size records from a file mapped in memory, with the
+//!   `mmap-storage` feature.
+//!
+//! A `Map<K, S>` associates values to the references of a shelf of `K` values.
+//! Dictionaries map each value to a unique reference, storing it only once in their shelf.
+//! They come in hash and ordered flavors, and in `Const` variants allowing insertions
+//! through a shared reference.
+//! The `Interner` type is a dedicated string dictionary.
+//! The `arena!` macro groups several shelves in a single struct.
+//!
+//! References can use an index type narrower than `usize`, such as `u32` or `NonZeroU32`,
+//! given as the last type parameter of shelves, maps and dictionaries.
+//!
+//! When the `serde` feature is enabled, shelves, maps and dictionaries are serialized as their
+//! underlying storage.
+//! The `serialization` module provides other serialization forms.
+//! The `validation` module checks that deserialized references resolve in their shelf.
 use derivative::Derivative;
 
+pub mod arena;
 pub mod btree_const_dictionary;
 pub mod btree_dictionary;
+mod dictionary;
 pub mod hash_const_dictionary;
 pub mod hash_dictionary;
-mod map;
+pub mod index;
+pub mod interner;
+pub mod map;
+pub mod remap;
+#[cfg(feature = "serde")]
+pub mod serialization;
 pub mod shelf;
 pub mod storage;
+pub mod sync_hash_const_dictionary;
+#[cfg(feature = "serde")]
+pub mod validation;
 
+pub use arena::{HasShelf, VisitShelf};
+pub use btree_const_dictionary::BTreeConstDictionary;
 pub use btree_dictionary::BTreeDictionary;
+pub use dictionary::{
+	Collision, Comparator, DefaultComparator, DefaultEquivalence, DictionaryReport, Equivalence,
+	InsertError, InsertErrorKind,
+};
 pub use hash_const_dictionary::HashConstDictionary;
 pub use hash_dictionary::HashDictionary;
+pub use index::RefIndex;
+pub use interner::Interner;
 pub use map::Map;
+pub use remap::Remap;
 pub use shelf::Shelf;
 pub use storage::*;
+pub use sync_hash_const_dictionary::SyncHashConstDictionary;
 
 /// Typed reference to a stored value.
+///
+/// The index of the value is stored using the `I` type, which defaults to
+/// `usize`. Narrower index types such as `u32` or `NonZeroU32` can be used to
+/// reduce the size of the reference.
 #[derive(Derivative)]
 #[derivative(
 	Clone(bound = ""),
@@ -36,25 +86,64 @@ pub use storage::*;
 	Ord(bound = ""),
 	Debug(bound = "")
 )]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct Ref<T>(
-	usize,
-	#[cfg_attr(feature = "serde", serde(skip))] std::marker::PhantomData<T>,
-);
+pub struct Ref<T: ?Sized, I: RefIndex = usize>(I, std::marker::PhantomData<T>);
 
-impl<T> Ref<T> {
+impl<T: ?Sized, I: RefIndex> Ref<T, I> {
 	/// Creates a new reference from an index.
+	///
+	/// # Panics
+	///
+	/// Panics if the index does not fit in the index type `I`.
 	pub fn new(index: usize) -> Self {
-		Self(index, std::marker::PhantomData)
+		Self::try_new(index).expect("reference index overflow")
+	}
+
+	/// Creates a new reference from an index, if it fits in the index type
+	/// `I`.
+	pub fn try_new(index: usize) -> Option<Self> {
+		I::from_index(index).map(|i| Self(i, std::marker::PhantomData))
 	}
 
 	/// Returns the underlying index referencing the value.
 	pub fn index(&self) -> usize {
-		self.0
+		self.0.index()
 	}
 
 	/// Changes the reference type.
-	pub fn cast<U>(self) -> Ref<U> {
-		Ref::new(self.0)
+	pub fn cast<U: ?Sized>(self) -> Ref<U, I> {
+		Ref(self.0, std::marker::PhantomData)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized, I: RefIndex> serde::Serialize for Ref<T, I> {
+	/// Serializes the index of the reference, regardless of the index type.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		use serde::ser::SerializeTupleStruct;
+		let mut s = serializer.serialize_tuple_struct("Ref", 1)?;
+		s.serialize_field(&self.index())?;
+		s.end()
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: ?Sized, I: RefIndex> serde::Deserialize<'de> for Ref<T, I> {
+	/// Deserializes a reference.
+	///
+	/// Fails if the index does not fit in the index type `I`.
+	/// When called inside [`validation::check_refs`], the reference is
+	/// recorded to be checked against its shelf.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		#[derive(serde::Deserialize)]
+		#[serde(rename = "Ref")]
+		struct Repr(usize, #[serde(skip)] ());
+
+		let Repr(index, ()) = Repr::deserialize(deserializer)?;
+		let r = Self::try_new(index).ok_or_else(|| {
+			serde::de::Error::custom(format_args!("reference index {index} overflow"))
+		})?;
+
+		validation::record::<T>(index);
+		Ok(r)
 	}
 }
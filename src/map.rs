@@ -1,12 +1,25 @@
-use crate::{Ref, Storage, StorageInsert, StorageInsertConst, StorageMut};
+use crate::shelf::{self, storage_eq, storage_hash};
+use crate::{
+	Ref, RefIndex, Remap, Storage, StorageInsert, StorageInsertConst, StorageIntoIter, StorageIter,
+	StorageIterMut, StorageMut, StorageRemove, StorageSet,
+};
+use derivative::Derivative;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
 
-pub struct Map<K, S> {
+#[derive(Derivative)]
+#[derivative(Clone(bound = "S: Clone"))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Map<K: ?Sized, S, I: RefIndex = usize> {
 	storage: S,
-	key: PhantomData<K>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	key: PhantomData<Ref<K, I>>,
 }
 
-impl<K, S: Default> Default for Map<K, S> {
+impl<K: ?Sized, S: Default, I: RefIndex> Default for Map<K, S, I> {
 	fn default() -> Self {
 		Self {
 			storage: S::default(),
@@ -15,7 +28,7 @@ impl<K, S: Default> Default for Map<K, S> {
 	}
 }
 
-impl<K, S> Map<K, S> {
+impl<K: ?Sized, S, I: RefIndex> Map<K, S, I> {
 	pub fn new(storage: S) -> Self {
 		Self {
 			storage,
@@ -32,7 +45,7 @@ impl<K, S> Map<K, S> {
 	}
 }
 
-impl<K, S: Storage> Map<K, S> {
+impl<K: ?Sized, S: Storage, I: RefIndex> Map<K, S, I> {
 	pub fn len(&self) -> usize {
 		self.storage.len()
 	}
@@ -41,25 +54,631 @@ impl<K, S: Storage> Map<K, S> {
 		self.storage.is_empty()
 	}
 
-	pub fn get(&self, r: Ref<K>) -> Option<&S::Value> {
+	pub fn get(&self, r: Ref<K, I>) -> Option<&S::Value> {
 		self.storage.get(r.index())
 	}
+
+	/// Checks if a value is associated to the given key.
+	pub fn contains(&self, r: Ref<K, I>) -> bool {
+		self.storage.get(r.index()).is_some()
+	}
+
+	/// Gets the entry associated to the given key for in-place manipulation.
+	pub fn entry(&mut self, r: Ref<K, I>) -> Entry<'_, K, S, I> {
+		if self.contains(r) {
+			Entry::Occupied(OccupiedEntry { map: self, key: r })
+		} else {
+			Entry::Vacant(VacantEntry { map: self, key: r })
+		}
+	}
 }
 
-impl<K, S: StorageMut> Map<K, S> {
-	pub fn get_mut(&mut self, r: Ref<K>) -> Option<&mut S::Value> {
+impl<K: ?Sized, S: Storage, I: RefIndex> Index<Ref<K, I>> for Map<K, S, I> {
+	type Output = S::Value;
+
+	/// Returns the value associated to the given key.
+	///
+	/// # Panics
+	///
+	/// Panics if no value is associated to the key.
+	fn index(&self, r: Ref<K, I>) -> &S::Value {
+		self.storage.get(r.index()).expect("invalid reference")
+	}
+}
+
+impl<K: ?Sized, S: StorageMut, I: RefIndex> IndexMut<Ref<K, I>> for Map<K, S, I> {
+	/// Returns the value associated to the given key.
+	///
+	/// # Panics
+	///
+	/// Panics if no value is associated to the key.
+	fn index_mut(&mut self, r: Ref<K, I>) -> &mut S::Value {
+		self.storage.get_mut(r.index()).expect("invalid reference")
+	}
+}
+
+impl<K: ?Sized, S: StorageMut, I: RefIndex> Map<K, S, I> {
+	pub fn get_mut(&mut self, r: Ref<K, I>) -> Option<&mut S::Value> {
 		self.storage.get_mut(r.index())
 	}
+
+	/// Removes all the entries of the map.
+	pub fn clear(&mut self) {
+		self.storage.clear()
+	}
+}
+
+impl<K: ?Sized, S: StorageRemove, I: RefIndex> Map<K, S, I> {
+	/// Removes the value associated to the given key, and returns it.
+	pub fn remove(&mut self, r: Ref<K, I>) -> Option<S::Value> {
+		self.storage.remove(r.index())
+	}
+}
+
+impl<K: ?Sized, S: StorageIter + StorageRemove, I: RefIndex> Map<K, S, I> {
+	/// Removes all the entries of the map, and returns them in an iterator.
+	///
+	/// The entries that are not consumed are removed when the iterator is
+	/// dropped.
+	pub fn drain(&mut self) -> Drain<'_, K, S, I> {
+		Drain {
+			inner: shelf::Drain::new(&mut self.storage),
+			key: PhantomData,
+		}
+	}
+}
+
+impl<K: ?Sized, S: StorageIterMut + StorageRemove, I: RefIndex> Map<K, S, I> {
+	/// Only keeps the entries for which the given predicate returns `true`.
+	pub fn retain(&mut self, f: impl FnMut(Ref<K, I>, &mut S::Value) -> bool) {
+		shelf::retain(&mut self.storage, f)
+	}
+}
+
+impl<K: ?Sized, S: StorageIter, I: RefIndex> Map<K, S, I> {
+	/// Returns an iterator over the keys and values of the map.
+	pub fn iter(&self) -> Iter<'_, K, S, I> {
+		Iter {
+			inner: self.storage.iter(),
+			key: PhantomData,
+		}
+	}
+
+	/// Returns an iterator over the keys of the map.
+	pub fn refs(&self) -> Refs<'_, K, S, I> {
+		Refs {
+			inner: self.storage.iter(),
+			key: PhantomData,
+		}
+	}
+
+	/// Returns an iterator over the values of the map.
+	pub fn values(&self) -> Values<'_, S> {
+		Values {
+			inner: self.storage.iter(),
+		}
+	}
+}
+
+impl<K: ?Sized, S: StorageIterMut, I: RefIndex> Map<K, S, I> {
+	/// Returns an iterator over the keys and mutable values of the map.
+	pub fn iter_mut(&mut self) -> IterMut<'_, K, S, I> {
+		IterMut {
+			inner: self.storage.iter_mut(),
+			key: PhantomData,
+		}
+	}
 }
 
-impl<K, S: StorageInsert> Map<K, S> {
-	pub fn insert(&mut self, r: Ref<K>, value: S::Value) -> Option<S::Value> {
+impl<K: ?Sized, S: StorageInsert, I: RefIndex> Map<K, S, I> {
+	pub fn insert(&mut self, r: Ref<K, I>, value: S::Value) -> Option<S::Value> {
 		self.storage.insert(r.index(), value)
 	}
 }
 
-impl<K, S: StorageInsertConst> Map<K, S> {
-	pub fn insert_const(&self, r: Ref<K>, value: S::Value) -> Option<S::Value> {
+impl<K: ?Sized, S: StorageInsert, I: RefIndex> Extend<(Ref<K, I>, S::Value)> for Map<K, S, I> {
+	fn extend<J: IntoIterator<Item = (Ref<K, I>, S::Value)>>(&mut self, iter: J) {
+		for (r, value) in iter {
+			self.insert(r, value);
+		}
+	}
+}
+
+impl<K: ?Sized, S: StorageInsert + Default, I: RefIndex> FromIterator<(Ref<K, I>, S::Value)>
+	for Map<K, S, I>
+{
+	fn from_iter<J: IntoIterator<Item = (Ref<K, I>, S::Value)>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+impl<K: ?Sized, S: StorageInsert + StorageRemove, I: RefIndex> Map<K, S, I> {
+	/// Moves the values of the map according to the given remapping,
+	/// typically returned by [`Shelf::compact`](crate::Shelf::compact).
+	///
+	/// Values associated to a moved key are associated to its new reference.
+	pub fn remap(&mut self, remap: &Remap<K, I>) {
+		let moved: Vec<_> = remap
+			.iter()
+			.filter_map(|(from, to)| {
+				self.storage
+					.remove(from.index())
+					.map(|value| (to.index(), value))
+			})
+			.collect();
+
+		for (to, value) in moved {
+			self.storage.insert(to, value);
+		}
+	}
+}
+
+impl<K: ?Sized, S: StorageInsertConst, I: RefIndex> Map<K, S, I> {
+	pub fn insert_const(&self, r: Ref<K, I>, value: S::Value) -> Option<S::Value> {
 		self.storage.insert_const(r.index(), value)
 	}
 }
+
+impl<'a, K: ?Sized, S: 'a + StorageIter, I: RefIndex> IntoIterator for &'a Map<K, S, I> {
+	type Item = (Ref<K, I>, &'a S::Value);
+	type IntoIter = Iter<'a, K, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<'a, K: ?Sized, S: 'a + StorageIterMut, I: RefIndex> IntoIterator for &'a mut Map<K, S, I> {
+	type Item = (Ref<K, I>, &'a mut S::Value);
+	type IntoIter = IterMut<'a, K, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter_mut()
+	}
+}
+
+impl<K: ?Sized, S: StorageIntoIter, I: RefIndex> IntoIterator for Map<K, S, I> {
+	type Item = (Ref<K, I>, S::Value);
+	type IntoIter = IntoIter<K, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter {
+			inner: self.storage.into_iter(),
+			key: PhantomData,
+		}
+	}
+}
+
+pub struct Iter<'a, K: ?Sized, S: 'a + StorageIter, I: RefIndex = usize> {
+	inner: S::Iter<'a>,
+	key: PhantomData<Ref<K, I>>,
+}
+
+impl<'a, K: ?Sized, S: 'a + StorageIter, I: RefIndex> Iterator for Iter<'a, K, S, I> {
+	type Item = (Ref<K, I>, &'a S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(i, v)| (Ref::new(i), v))
+	}
+}
+
+pub struct Refs<'a, K: ?Sized, S: 'a + StorageIter, I: RefIndex = usize> {
+	inner: S::Iter<'a>,
+	key: PhantomData<Ref<K, I>>,
+}
+
+impl<'a, K: ?Sized, S: 'a + StorageIter, I: RefIndex> Iterator for Refs<'a, K, S, I> {
+	type Item = Ref<K, I>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(i, _)| Ref::new(i))
+	}
+}
+
+pub struct Values<'a, S: 'a + StorageIter> {
+	inner: S::Iter<'a>,
+}
+
+impl<'a, S: 'a + StorageIter> Iterator for Values<'a, S> {
+	type Item = &'a S::Value;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(_, v)| v)
+	}
+}
+
+pub struct IterMut<'a, K: ?Sized, S: 'a + StorageIterMut, I: RefIndex = usize> {
+	inner: S::IterMut<'a>,
+	key: PhantomData<Ref<K, I>>,
+}
+
+impl<'a, K: ?Sized, S: 'a + StorageIterMut, I: RefIndex> Iterator for IterMut<'a, K, S, I> {
+	type Item = (Ref<K, I>, &'a mut S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(i, v)| (Ref::new(i), v))
+	}
+}
+
+pub struct IntoIter<K: ?Sized, S: StorageIntoIter, I: RefIndex = usize> {
+	inner: S::IntoIter,
+	key: PhantomData<Ref<K, I>>,
+}
+
+impl<K: ?Sized, S: StorageIntoIter, I: RefIndex> Iterator for IntoIter<K, S, I> {
+	type Item = (Ref<K, I>, S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(i, v)| (Ref::new(i), v))
+	}
+}
+
+/// Draining iterator, returned by [`Map::drain`].
+pub struct Drain<'a, K: ?Sized, S: StorageRemove, I: RefIndex = usize> {
+	inner: shelf::Drain<'a, S, I>,
+	key: PhantomData<Ref<K, I>>,
+}
+
+impl<'a, K: ?Sized, S: StorageRemove, I: RefIndex> Iterator for Drain<'a, K, S, I> {
+	type Item = (Ref<K, I>, S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|(r, v)| (r.cast(), v))
+	}
+}
+
+impl<K: ?Sized, S: StorageIter, I: RefIndex> fmt::Debug for Map<K, S, I>
+where
+	S::Value: fmt::Debug,
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.storage.iter()).finish()
+	}
+}
+
+impl<K: ?Sized, S: StorageIter, I: RefIndex> PartialEq for Map<K, S, I>
+where
+	S::Value: PartialEq,
+{
+	/// Checks that both maps associate equal values to the same keys,
+	/// regardless of the iteration order of their storage.
+	fn eq(&self, other: &Self) -> bool {
+		storage_eq(&self.storage, &other.storage)
+	}
+}
+
+impl<K: ?Sized, S: StorageIter, I: RefIndex> Eq for Map<K, S, I> where S::Value: Eq {}
+
+impl<K: ?Sized, S: StorageIter, I: RefIndex> Hash for Map<K, S, I>
+where
+	S::Value: Hash,
+{
+	/// Hashes the keys and values of the map, regardless of the iteration
+	/// order of its storage.
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		storage_hash(&self.storage, state)
+	}
+}
+
+/// View into a single entry of a map, which may either be vacant or occupied.
+pub enum Entry<'a, K: ?Sized, S, I: RefIndex = usize> {
+	Occupied(OccupiedEntry<'a, K, S, I>),
+	Vacant(VacantEntry<'a, K, S, I>),
+}
+
+impl<'a, K: ?Sized, S, I: RefIndex> Entry<'a, K, S, I> {
+	/// Returns the key of this entry.
+	pub fn key(&self) -> Ref<K, I> {
+		match self {
+			Self::Occupied(entry) => entry.key,
+			Self::Vacant(entry) => entry.key,
+		}
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageMut, I: RefIndex> Entry<'a, K, S, I> {
+	/// Provides in-place mutable access to an occupied entry before any
+	/// potential insertion into the map.
+	pub fn and_modify(self, f: impl FnOnce(&mut S::Value)) -> Self {
+		match self {
+			Self::Occupied(mut entry) => {
+				f(entry.get_mut());
+				Self::Occupied(entry)
+			}
+			Self::Vacant(entry) => Self::Vacant(entry),
+		}
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageMut + StorageInsert, I: RefIndex> Entry<'a, K, S, I> {
+	/// Ensures a value is in the entry by inserting the default if empty,
+	/// and returns a mutable reference to the value in the entry.
+	pub fn or_insert(self, default: S::Value) -> &'a mut S::Value {
+		self.or_insert_with(|| default)
+	}
+
+	/// Ensures a value is in the entry by inserting the result of the default
+	/// function if empty, and returns a mutable reference to the value in the
+	/// entry.
+	pub fn or_insert_with(self, default: impl FnOnce() -> S::Value) -> &'a mut S::Value {
+		match self {
+			Self::Occupied(entry) => entry.into_mut(),
+			Self::Vacant(entry) => entry.insert(default()),
+		}
+	}
+
+	/// Ensures a value is in the entry by inserting the result of the default
+	/// function, called with the entry key, if empty,
+	/// and returns a mutable reference to the value in the entry.
+	pub fn or_insert_with_key(
+		self,
+		default: impl FnOnce(Ref<K, I>) -> S::Value,
+	) -> &'a mut S::Value {
+		match self {
+			Self::Occupied(entry) => entry.into_mut(),
+			Self::Vacant(entry) => {
+				let value = default(entry.key);
+				entry.insert(value)
+			}
+		}
+	}
+
+	/// Ensures a value is in the entry by inserting the default value if empty,
+	/// and returns a mutable reference to the value in the entry.
+	pub fn or_default(self) -> &'a mut S::Value
+	where
+		S::Value: Default,
+	{
+		self.or_insert_with(Default::default)
+	}
+}
+
+/// View into an occupied entry of a map.
+pub struct OccupiedEntry<'a, K: ?Sized, S, I: RefIndex = usize> {
+	map: &'a mut Map<K, S, I>,
+	key: Ref<K, I>,
+}
+
+impl<'a, K: ?Sized, S, I: RefIndex> OccupiedEntry<'a, K, S, I> {
+	/// Returns the key of this entry.
+	pub fn key(&self) -> Ref<K, I> {
+		self.key
+	}
+}
+
+impl<'a, K: ?Sized, S: Storage, I: RefIndex> OccupiedEntry<'a, K, S, I> {
+	/// Returns a reference to the value of this entry.
+	pub fn get(&self) -> &S::Value {
+		self.map.storage.get(self.key.index()).unwrap()
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageMut, I: RefIndex> OccupiedEntry<'a, K, S, I> {
+	/// Returns a mutable reference to the value of this entry.
+	pub fn get_mut(&mut self) -> &mut S::Value {
+		self.map.storage.get_mut(self.key.index()).unwrap()
+	}
+
+	/// Converts the entry into a mutable reference to its value,
+	/// with the lifetime of the map.
+	pub fn into_mut(self) -> &'a mut S::Value {
+		self.map.storage.get_mut(self.key.index()).unwrap()
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageSet, I: RefIndex> OccupiedEntry<'a, K, S, I> {
+	/// Sets the value of this entry, and returns the previous value.
+	pub fn insert(&mut self, value: S::Value) -> S::Value {
+		match self.map.storage.set(self.key.index(), value) {
+			Ok(previous) => previous,
+			Err(_) => unreachable!(),
+		}
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageRemove, I: RefIndex> OccupiedEntry<'a, K, S, I> {
+	/// Removes the value of this entry from the map, and returns it.
+	pub fn remove(self) -> S::Value {
+		self.map.storage.remove(self.key.index()).unwrap()
+	}
+}
+
+/// View into a vacant entry of a map.
+pub struct VacantEntry<'a, K: ?Sized, S, I: RefIndex = usize> {
+	map: &'a mut Map<K, S, I>,
+	key: Ref<K, I>,
+}
+
+impl<'a, K: ?Sized, S, I: RefIndex> VacantEntry<'a, K, S, I> {
+	/// Returns the key of this entry.
+	pub fn key(&self) -> Ref<K, I> {
+		self.key
+	}
+}
+
+impl<'a, K: ?Sized, S: StorageMut + StorageInsert, I: RefIndex> VacantEntry<'a, K, S, I> {
+	/// Sets the value of this entry, and returns a mutable reference to it.
+	pub fn insert(self, value: S::Value) -> &'a mut S::Value {
+		self.map.storage.insert(self.key.index(), value);
+		self.map.storage.get_mut(self.key.index()).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{FreeList, Shelf, SparseSet};
+	use std::collections::HashMap;
+
+	fn key(index: usize) -> Ref<String> {
+		Ref::new(index)
+	}
+
+	#[test]
+	fn remove() {
+		let mut map: Map<String, SparseSet<u32>> = (0..5).map(|i| (key(i * 2), i as u32)).collect();
+		assert_eq!(map.remove(key(4)), Some(2));
+		assert_eq!(map.remove(key(4)), None);
+		assert_eq!(map.remove(key(3)), None);
+		assert_eq!(map.len(), 4);
+		assert!(!map.contains(key(4)));
+		assert_eq!(map.get(key(8)), Some(&4));
+		assert_eq!(map[key(6)], 3);
+	}
+
+	#[test]
+	fn entry_or_insert_with() {
+		let mut map: Map<String, SparseSet<u32>> = Map::default();
+		map.insert(key(1), 10);
+
+		let mut calls = 0;
+		let mut default = || {
+			calls += 1;
+			20
+		};
+		assert_eq!(*map.entry(key(1)).or_insert_with(&mut default), 10);
+		assert_eq!(*map.entry(key(2)).or_insert_with(&mut default), 20);
+		assert_eq!(calls, 1);
+
+		*map.entry(key(2)).or_insert_with(|| unreachable!()) += 1;
+		assert_eq!(map.get(key(2)), Some(&21));
+		assert_eq!(
+			*map.entry(key(3)).or_insert_with_key(|r| r.index() as u32),
+			3
+		);
+		assert_eq!(*map.entry(key(4)).or_default(), 0);
+
+		map.entry(key(1))
+			.and_modify(|value| *value += 1)
+			.or_insert(0);
+		map.entry(key(5))
+			.and_modify(|value| *value += 1)
+			.or_insert(50);
+		assert_eq!(map.get(key(1)), Some(&11));
+		assert_eq!(map.get(key(5)), Some(&50));
+
+		match map.entry(key(1)) {
+			Entry::Occupied(mut entry) => {
+				assert_eq!(entry.key(), key(1));
+				assert_eq!(entry.insert(12), 11);
+				assert_eq!(entry.remove(), 12);
+			}
+			Entry::Vacant(_) => panic!("entry should be occupied"),
+		}
+
+		match map.entry(key(1)) {
+			Entry::Occupied(_) => panic!("entry should be vacant"),
+			Entry::Vacant(entry) => assert_eq!(entry.key(), key(1)),
+		}
+
+		assert_eq!(map.len(), 4);
+	}
+
+	#[test]
+	fn iter_mut() {
+		let mut map: Map<String, SparseSet<u32>> = (0..5).map(|i| (key(i * 2), i as u32)).collect();
+		for (r, value) in map.iter_mut() {
+			*value += r.index() as u32
+		}
+
+		for (r, value) in &mut map {
+			*value *= 2;
+			assert_eq!(*value, r.index() as u32 * 3);
+		}
+
+		let mut entries: Vec<_> = map.iter().map(|(r, value)| (r.index(), *value)).collect();
+		entries.sort_unstable();
+		assert_eq!(entries, [(0, 0), (2, 6), (4, 12), (6, 18), (8, 24)]);
+	}
+
+	#[test]
+	fn drain() {
+		let mut map: Map<String, SparseSet<u32>> = (0..5).map(|i| (key(i * 2), i as u32)).collect();
+		let mut drain = map.drain();
+		let (r, value) = drain.next().unwrap();
+		assert_eq!(value, r.index() as u32 / 2);
+		drop(drain);
+
+		assert!(map.is_empty());
+		assert_eq!(map.iter().count(), 0);
+		assert!((0..10).all(|i| !map.contains(key(i))));
+
+		map.insert(key(3), 3);
+		assert_eq!(map.len(), 1);
+		assert_eq!(map.get(key(3)), Some(&3));
+		assert_eq!(map.as_storage().as_slice(), [3]);
+	}
+
+	#[test]
+	fn retain() {
+		let mut map: Map<String, SparseSet<u32>> = (0..10).map(|i| (key(i), i as u32)).collect();
+		map.retain(|r, value| {
+			*value *= 10;
+			r.index() % 3 == 0
+		});
+
+		assert_eq!(map.len(), 4);
+		for i in 0..10 {
+			let expected = (i % 3 == 0).then_some(i as u32 * 10);
+			assert_eq!(map.get(key(i)).copied(), expected);
+		}
+
+		let mut refs: Vec<_> = map.refs().map(|r| r.index()).collect();
+		refs.sort_unstable();
+		assert_eq!(refs, [0, 3, 6, 9]);
+		assert_eq!(map.values().count(), 4);
+
+		map.insert(key(1), 1);
+		assert_eq!(map.get(key(1)), Some(&1));
+		assert_eq!(map.get(key(9)), Some(&90));
+	}
+
+	#[test]
+	fn remap_compacted_shelf() {
+		let mut shelf: Shelf<FreeList<String>> = Shelf::default();
+		let refs: Vec<_> = (0..8).map(|i| shelf.insert(i.to_string())).collect();
+		let mut map: Map<String, HashMap<usize, usize>> = refs
+			.as_slice()
+			.iter()
+			.enumerate()
+			.map(|(i, &r)| (r, i))
+			.collect();
+
+		for i in [1, 2, 5] {
+			shelf.remove(refs[i]);
+			map.remove(refs[i]);
+		}
+
+		let remap = shelf.compact();
+		assert_eq!(remap.len(), 2);
+		map.remap(&remap);
+
+		assert_eq!(map.len(), 5);
+		for (r, value) in shelf.iter() {
+			assert!(r.index() < 5);
+			assert_eq!(map[r].to_string(), *value);
+		}
+	}
+
+	#[test]
+	fn remap_chain() {
+		let mut map: Map<String, HashMap<usize, char>> =
+			[(key(0), 'a'), (key(1), 'b'), (key(2), 'c'), (key(4), 'e')]
+				.into_iter()
+				.collect();
+
+		// 2 moves to 1 while 1 moves to 0, and 4 to 3.
+		let mut remap = Remap::new();
+		remap.insert(key(2), key(1));
+		remap.insert(key(1), key(0));
+		remap.insert(key(4), key(3));
+		map.remove(key(0));
+		map.remap(&remap);
+
+		let mut entries: Vec<_> = map.iter().map(|(r, value)| (r.index(), *value)).collect();
+		entries.sort_unstable();
+		assert_eq!(entries, [(0, 'b'), (1, 'c'), (3, 'e')]);
+	}
+}
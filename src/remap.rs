@@ -0,0 +1,92 @@
+use crate::{Ref, RefIndex};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// Table mapping the old references of moved values to their new references.
+///
+/// It is returned by [`Shelf::compact`](crate::Shelf::compact), and can be
+/// applied to the references held elsewhere, or to the side tables indexed by
+/// the compacted shelf using [`Map::remap`](crate::Map::remap).
+/// References to values that did not move are not recorded.
+pub struct Remap<T: ?Sized, I: RefIndex = usize> {
+	moved: HashMap<usize, usize>,
+	value: PhantomData<Ref<T, I>>,
+}
+
+impl<T: ?Sized, I: RefIndex> Default for Remap<T, I> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: ?Sized, I: RefIndex> Remap<T, I> {
+	/// Creates a new empty remapping.
+	pub fn new() -> Self {
+		Self {
+			moved: HashMap::new(),
+			value: PhantomData,
+		}
+	}
+
+	/// Records that the value referenced by `from` is now referenced by `to`.
+	pub fn insert(&mut self, from: Ref<T, I>, to: Ref<T, I>) {
+		self.moved.insert(from.index(), to.index());
+	}
+
+	/// Returns the number of moved values.
+	pub fn len(&self) -> usize {
+		self.moved.len()
+	}
+
+	/// Checks if no value has been moved.
+	pub fn is_empty(&self) -> bool {
+		self.moved.is_empty()
+	}
+
+	/// Returns the new reference of the given reference, if the referenced
+	/// value has been moved.
+	pub fn get(&self, r: Ref<T, I>) -> Option<Ref<T, I>> {
+		self.moved.get(&r.index()).copied().map(Ref::new)
+	}
+
+	/// Returns the new reference of the given reference.
+	///
+	/// The reference is returned unchanged if the referenced value has not
+	/// been moved.
+	pub fn apply(&self, r: Ref<T, I>) -> Ref<T, I> {
+		self.get(r).unwrap_or(r)
+	}
+
+	/// Returns an iterator over the old and new references of the moved
+	/// values, in no particular order.
+	pub fn iter(&self) -> Iter<'_, T, I> {
+		Iter {
+			inner: self.moved.iter(),
+			value: PhantomData,
+		}
+	}
+}
+
+impl<'a, T: ?Sized, I: RefIndex> IntoIterator for &'a Remap<T, I> {
+	type Item = (Ref<T, I>, Ref<T, I>);
+	type IntoIter = Iter<'a, T, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+pub struct Iter<'a, T: ?Sized, I: RefIndex = usize> {
+	inner: std::collections::hash_map::Iter<'a, usize, usize>,
+	value: PhantomData<Ref<T, I>>,
+}
+
+impl<T: ?Sized, I: RefIndex> Iterator for Iter<'_, T, I> {
+	type Item = (Ref<T, I>, Ref<T, I>);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner
+			.next()
+			.map(|(&from, &to)| (Ref::new(from), Ref::new(to)))
+	}
+}
@@ -0,0 +1,272 @@
+//! Serialization forms for shelves and maps.
+//!
+//! By default, [`Shelf`] and [`Map`] are serialized as their underlying
+//! storage. Every storage provided by this library preserves the index of its
+//! values, but the layout differs from one storage to another.
+//! The [`indexed`] and [`dense`] modules can be used with the
+//! `#[serde(with = "...")]` attribute to choose a specific form instead:
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use shelves::{FreeList, Ref, Shelf};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Node {
+//!     name: Ref<String>,
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Document {
+//!     #[serde(with = "shelves::serialization::indexed")]
+//!     nodes: Shelf<FreeList<Node>>,
+//!
+//!     #[serde(with = "shelves::serialization::dense")]
+//!     names: Shelf<Vec<String>>,
+//! }
+//!
+//! let mut document = Document {
+//!     nodes: Shelf::default(),
+//!     names: Shelf::default(),
+//! };
+//!
+//! let a = document.nodes.insert(Node {
+//!     name: document.names.insert("a".to_string()),
+//! });
+//! let b = document.nodes.insert(Node {
+//!     name: document.names.insert("b".to_string()),
+//! });
+//! document.nodes.remove(a);
+//!
+//! let json = serde_json::to_string(&document).unwrap();
+//! let document: Document = serde_json::from_str(&json).unwrap();
+//! assert_eq!(document.names[document.nodes[b].name], "b");
+//! ```
+use crate::{Map, RefIndex, Shelf, Storage, StorageAllocate, StorageInsert, StorageIter};
+use serde::de::{Deserialize, Deserializer, Error as _, SeqAccess, Visitor};
+use serde::ser::{Error as _, Serialize, SerializeSeq, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Type wrapping a storage, such as [`Shelf`] and [`Map`].
+pub trait StorageWrapper {
+	type Storage: Storage;
+
+	fn as_storage(&self) -> &Self::Storage;
+
+	fn from_storage(storage: Self::Storage) -> Self;
+}
+
+impl<S: Storage, I: RefIndex> StorageWrapper for Shelf<S, I> {
+	type Storage = S;
+
+	fn as_storage(&self) -> &S {
+		self.as_storage()
+	}
+
+	fn from_storage(storage: S) -> Self {
+		Self::new(storage)
+	}
+}
+
+impl<K: ?Sized, S: Storage, I: RefIndex> StorageWrapper for Map<K, S, I> {
+	type Storage = S;
+
+	fn as_storage(&self) -> &S {
+		self.as_storage()
+	}
+
+	fn from_storage(storage: S) -> Self {
+		Self::new(storage)
+	}
+}
+
+/// Indexed form, as a sequence of `(index, value)` pairs.
+///
+/// This form preserves the index of every value regardless of the holes in
+/// the storage. The storage must support insertion at arbitrary indices to
+/// be deserialized. Since [dense](StorageInsert::DENSE) storages allocate
+/// every index below the inserted ones, their deserialization rejects inputs
+/// with more than [`MAX_DENSE_HOLES`] holes beyond the number of values.
+/// Compact such storages before serializing them if necessary.
+pub mod indexed {
+	use super::*;
+
+	pub fn serialize<W, E>(wrapper: &W, serializer: E) -> Result<E::Ok, E::Error>
+	where
+		W: StorageWrapper,
+		W::Storage: StorageIter,
+		<W::Storage as Storage>::Value: Serialize,
+		E: Serializer,
+	{
+		serialize_indexed(wrapper.as_storage(), serializer)
+	}
+
+	pub fn deserialize<'de, W, D>(deserializer: D) -> Result<W, D::Error>
+	where
+		W: StorageWrapper,
+		W::Storage: Default + StorageInsert,
+		<W::Storage as Storage>::Value: Deserialize<'de>,
+		D: Deserializer<'de>,
+	{
+		deserialize_indexed(deserializer).map(W::from_storage)
+	}
+}
+
+/// Dense form, as the sequence of values ordered by index.
+///
+/// This form is more compact than the indexed form, but only applies to
+/// storages without holes, whose values occupy exactly the indices
+/// `0..len()`. Serialization fails otherwise.
+/// The storage must allocate indices sequentially to be deserialized.
+pub mod dense {
+	use super::*;
+
+	pub fn serialize<W, E>(wrapper: &W, serializer: E) -> Result<E::Ok, E::Error>
+	where
+		W: StorageWrapper,
+		<W::Storage as Storage>::Value: Serialize,
+		E: Serializer,
+	{
+		let storage = wrapper.as_storage();
+		let mut seq = serializer.serialize_seq(Some(storage.len()))?;
+		for index in 0..storage.len() {
+			match storage.get(index) {
+				Some(value) => seq.serialize_element(value)?,
+				None => {
+					return Err(E::Error::custom(format_args!(
+						"missing value at index {index} in dense storage"
+					)))
+				}
+			}
+		}
+
+		seq.end()
+	}
+
+	pub fn deserialize<'de, W, D>(deserializer: D) -> Result<W, D::Error>
+	where
+		W: StorageWrapper,
+		W::Storage: Default + StorageAllocate,
+		<W::Storage as Storage>::Value: Deserialize<'de>,
+		D: Deserializer<'de>,
+	{
+		deserializer
+			.deserialize_seq(DenseVisitor(PhantomData))
+			.map(W::from_storage)
+	}
+}
+
+/// Serializes the given storage as a sequence of `(index, value)` pairs.
+pub(crate) fn serialize_indexed<S, E>(storage: &S, serializer: E) -> Result<E::Ok, E::Error>
+where
+	S: StorageIter,
+	S::Value: Serialize,
+	E: Serializer,
+{
+	let mut seq = serializer.serialize_seq(Some(storage.len()))?;
+	for (index, value) in storage.iter() {
+		seq.serialize_element(&(index, value))?;
+	}
+
+	seq.end()
+}
+
+/// Number of holes a dense storage may have beyond its number of values when
+/// deserialized from the indexed form.
+pub const MAX_DENSE_HOLES: usize = 1 << 16;
+
+/// Greatest index accepted when deserializing `len` values into a dense
+/// storage.
+fn max_dense_index(len: usize) -> usize {
+	len.saturating_mul(2).saturating_add(MAX_DENSE_HOLES)
+}
+
+/// Deserializes a storage from a sequence of `(index, value)` pairs.
+///
+/// Fails if the same index appears twice, or if the storage is
+/// [dense](StorageInsert::DENSE) and an index leaves more than
+/// [`MAX_DENSE_HOLES`] holes beyond the number of values.
+pub(crate) fn deserialize_indexed<'de, S, D>(deserializer: D) -> Result<S, D::Error>
+where
+	S: Default + StorageInsert,
+	S::Value: Deserialize<'de>,
+	D: Deserializer<'de>,
+{
+	deserializer.deserialize_seq(IndexedVisitor(PhantomData))
+}
+
+struct IndexedVisitor<S>(PhantomData<S>);
+
+impl<'de, S> Visitor<'de> for IndexedVisitor<S>
+where
+	S: Default + StorageInsert,
+	S::Value: Deserialize<'de>,
+{
+	type Value = S;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a sequence of index-value pairs")
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<S, A::Error> {
+		let mut storage = S::default();
+		if S::DENSE {
+			let mut pairs = Vec::new();
+			while let Some(pair) = seq.next_element::<(usize, S::Value)>()? {
+				pairs.push(pair)
+			}
+
+			let bound = max_dense_index(pairs.len());
+			for (index, value) in pairs {
+				if index > bound {
+					return Err(A::Error::custom(format_args!(
+						"index {index} is too far beyond the values of a dense storage"
+					)));
+				}
+
+				if storage.insert(index, value).is_some() {
+					return Err(A::Error::custom(format_args!("duplicate index {index}")));
+				}
+			}
+		} else {
+			while let Some((index, value)) = seq.next_element::<(usize, S::Value)>()? {
+				if storage.insert(index, value).is_some() {
+					return Err(A::Error::custom(format_args!("duplicate index {index}")));
+				}
+			}
+		}
+
+		Ok(storage)
+	}
+}
+
+struct DenseVisitor<S>(PhantomData<S>);
+
+impl<'de, S> Visitor<'de> for DenseVisitor<S>
+where
+	S: Default + StorageAllocate,
+	S::Value: Deserialize<'de>,
+{
+	type Value = S;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a sequence of values")
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<S, A::Error> {
+		let mut storage = S::default();
+		let mut expected = 0;
+		while let Some(value) = seq.next_element()? {
+			let index = storage.allocate(value);
+			if index != expected {
+				return Err(A::Error::custom(format_args!(
+					"value allocated at index {index} instead of {expected}"
+				)));
+			}
+
+			expected += 1
+		}
+
+		Ok(storage)
+	}
+}
@@ -1,18 +1,36 @@
 use crate::{
-	Ref, Storage, StorageAllocate, StorageAllocateConst, StorageIter, StorageIterMut, StorageMut,
-	StorageRemove, StorageSet,
+	Ref, RefIndex, Remap, Snapshot, Storage, StorageAllocate, StorageAllocateConst, StorageCompact,
+	StorageIntoIter, StorageIter, StorageIterMut, StorageMut, StorageRemove, StorageSet, UndoLog,
 };
+use derivative::Derivative;
 use std::borrow::{Borrow, BorrowMut};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
 
+#[derive(Derivative)]
+#[derivative(Clone(bound = "S: Clone"))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-pub struct Shelf<S> {
-	#[cfg_attr(feature = "serde", serde(flatten))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Shelf<S, I: RefIndex = usize> {
 	storage: S,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	index: PhantomData<I>,
 }
 
-impl<S> Shelf<S> {
+impl<S, I: RefIndex> Shelf<S, I> {
+	/// Creates a shelf holding the values of the given storage.
+	///
+	/// The indices of the values must fit in the index type `I`, otherwise
+	/// iterating over the shelf panics. This always holds for the values
+	/// inserted through a shelf.
 	pub fn new(storage: S) -> Self {
-		Self { storage }
+		Self {
+			storage,
+			index: PhantomData,
+		}
 	}
 
 	pub fn into_storage(self) -> S {
@@ -24,56 +42,115 @@ impl<S> Shelf<S> {
 	}
 }
 
-impl<S: StorageIter> Shelf<S> {
-	pub fn iter(&self) -> Iter<S> {
-		Iter(self.storage.iter())
+impl<S: StorageIter, I: RefIndex> Shelf<S, I> {
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		Iter(self.storage.iter(), PhantomData)
+	}
+
+	/// Returns an iterator over the references of the shelf.
+	pub fn refs(&self) -> Refs<'_, S, I> {
+		Refs(self.storage.iter(), PhantomData)
+	}
+
+	/// Returns an iterator over the values of the shelf.
+	pub fn values(&self) -> Values<'_, S> {
+		Values(self.storage.iter())
 	}
 }
 
-impl<'a, S: 'a + StorageIter> IntoIterator for &'a Shelf<S> {
-	type Item = (Ref<S::Value>, &'a S::Value);
-	type IntoIter = Iter<'a, S>;
+impl<'a, S: 'a + StorageIter, I: RefIndex> IntoIterator for &'a Shelf<S, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
 
 	fn into_iter(self) -> Self::IntoIter {
 		self.iter()
 	}
 }
 
-pub struct Iter<'a, S: 'a + StorageIter>(S::Iter<'a>);
+pub struct Iter<'a, S: 'a + StorageIter, I: RefIndex = usize>(S::Iter<'a>, PhantomData<I>);
 
-impl<'a, S: 'a + StorageIter> Iterator for Iter<'a, S> {
-	type Item = (Ref<S::Value>, &'a S::Value);
+impl<'a, S: 'a + StorageIter, I: RefIndex> Iterator for Iter<'a, S, I> {
+	type Item = (Ref<S::Value, I>, &'a S::Value);
 
 	fn next(&mut self) -> Option<Self::Item> {
 		self.0.next().map(|(i, v)| (Ref::new(i), v))
 	}
 }
 
-impl<S: StorageIterMut> Shelf<S> {
-	pub fn iter_mut(&mut self) -> IterMut<S> {
-		IterMut(self.storage.iter_mut())
+pub struct Refs<'a, S: 'a + StorageIter, I: RefIndex = usize>(S::Iter<'a>, PhantomData<I>);
+
+impl<'a, S: 'a + StorageIter, I: RefIndex> Iterator for Refs<'a, S, I> {
+	type Item = Ref<S::Value, I>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next().map(|(i, _)| Ref::new(i))
+	}
+}
+
+pub struct Values<'a, S: 'a + StorageIter>(S::Iter<'a>);
+
+impl<'a, S: 'a + StorageIter> Iterator for Values<'a, S> {
+	type Item = &'a S::Value;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next().map(|(_, v)| v)
+	}
+}
+
+impl<S: StorageIntoIter, I: RefIndex> IntoIterator for Shelf<S, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter(self.storage.into_iter(), PhantomData)
 	}
 }
 
-pub struct IterMut<'a, S: 'a + StorageIterMut>(S::IterMut<'a>);
+pub struct IntoIter<S: StorageIntoIter, I: RefIndex = usize>(S::IntoIter, PhantomData<I>);
 
-impl<'a, S: 'a + StorageIterMut> Iterator for IterMut<'a, S> {
-	type Item = (Ref<S::Value>, &'a mut S::Value);
+impl<S: StorageIntoIter, I: RefIndex> Iterator for IntoIter<S, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
 
 	fn next(&mut self) -> Option<Self::Item> {
 		self.0.next().map(|(i, v)| (Ref::new(i), v))
 	}
 }
 
-impl<S: Default> Default for Shelf<S> {
+impl<S: StorageIterMut, I: RefIndex> Shelf<S, I> {
+	pub fn iter_mut(&mut self) -> IterMut<'_, S, I> {
+		IterMut(self.storage.iter_mut(), PhantomData)
+	}
+}
+
+impl<'a, S: 'a + StorageIterMut, I: RefIndex> IntoIterator for &'a mut Shelf<S, I> {
+	type Item = (Ref<S::Value, I>, &'a mut S::Value);
+	type IntoIter = IterMut<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter_mut()
+	}
+}
+
+pub struct IterMut<'a, S: 'a + StorageIterMut, I: RefIndex = usize>(S::IterMut<'a>, PhantomData<I>);
+
+impl<'a, S: 'a + StorageIterMut, I: RefIndex> Iterator for IterMut<'a, S, I> {
+	type Item = (Ref<S::Value, I>, &'a mut S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next().map(|(i, v)| (Ref::new(i), v))
+	}
+}
+
+impl<S: Default, I: RefIndex> Default for Shelf<S, I> {
 	fn default() -> Self {
 		Self {
 			storage: S::default(),
+			index: PhantomData,
 		}
 	}
 }
 
-impl<S: Storage> Shelf<S> {
+impl<S: Storage, I: RefIndex> Shelf<S, I> {
 	pub fn len(&self) -> usize {
 		self.storage.len()
 	}
@@ -82,51 +159,159 @@ impl<S: Storage> Shelf<S> {
 		self.storage.is_empty()
 	}
 
-	pub fn borrow<T>(&self, r: Ref<T>) -> Option<&T>
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.storage.get(r.index()).map(|v| v.borrow())
 	}
 
-	pub fn get<T>(&self, r: Ref<T>) -> Option<&S::Value>
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
 		self.storage.get(r.index())
 	}
+
+	/// Checks if a value is stored behind the given reference.
+	pub fn contains<T>(&self, r: Ref<T, I>) -> bool
+	where
+		S::Value: Borrow<T>,
+	{
+		self.storage.get(r.index()).is_some()
+	}
+}
+
+impl<S: Storage, T, I: RefIndex> Index<Ref<T, I>> for Shelf<S, I>
+where
+	S::Value: Borrow<T>,
+{
+	type Output = S::Value;
+
+	/// Returns the value behind the given reference.
+	///
+	/// # Panics
+	///
+	/// Panics if no value is stored behind the reference.
+	fn index(&self, r: Ref<T, I>) -> &S::Value {
+		self.storage.get(r.index()).expect("invalid reference")
+	}
 }
 
-impl<S: StorageMut> Shelf<S> {
-	pub fn borrow_mut<T>(&mut self, r: Ref<T>) -> Option<&mut T>
+impl<S: StorageMut, I: RefIndex> Shelf<S, I> {
+	pub fn borrow_mut<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.storage.get_mut(r.index()).map(|v| v.borrow_mut())
 	}
 
-	pub fn get_mut<T>(&mut self, r: Ref<T>) -> Option<&mut S::Value>
+	pub fn get_mut<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
 	where
 		S::Value: BorrowMut<T>,
 	{
 		self.storage.get_mut(r.index())
 	}
+
+	/// Removes all the values of the shelf.
+	pub fn clear(&mut self) {
+		self.storage.clear()
+	}
+}
+
+impl<S: StorageMut, T, I: RefIndex> IndexMut<Ref<T, I>> for Shelf<S, I>
+where
+	S::Value: BorrowMut<T>,
+{
+	/// Returns the value behind the given reference.
+	///
+	/// # Panics
+	///
+	/// Panics if no value is stored behind the reference.
+	fn index_mut(&mut self, r: Ref<T, I>) -> &mut S::Value {
+		self.storage.get_mut(r.index()).expect("invalid reference")
+	}
 }
 
-impl<S: StorageAllocate> Shelf<S> {
-	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value> {
-		Ref::new(self.storage.allocate(value))
+impl<S: StorageAllocate, I: RefIndex> Shelf<S, I> {
+	/// Inserts a new value and returns its reference.
+	///
+	/// # Panics
+	///
+	/// Panics if the index allocated by the storage does not fit in the
+	/// index type `I`. The value is not inserted in this case.
+	/// See [`Self::try_insert`].
+	pub fn insert(&mut self, value: S::Value) -> Ref<S::Value, I> {
+		match self.try_insert(value) {
+			Ok(r) => r,
+			Err(_) => panic!("reference index overflow"),
+		}
+	}
+
+	/// Inserts a new value and returns its reference, or returns the value
+	/// back if the index allocated by the storage does not fit in the index
+	/// type `I`.
+	///
+	/// The index is checked before allocating it, so that the storage is left
+	/// unchanged on failure.
+	pub fn try_insert(&mut self, value: S::Value) -> Result<Ref<S::Value, I>, S::Value> {
+		match Ref::try_new(self.storage.next_index()) {
+			Some(r) => {
+				self.storage.allocate(value);
+				Ok(r)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<S: StorageAllocate, I: RefIndex> Extend<S::Value> for Shelf<S, I> {
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
 	}
 }
 
-impl<S: StorageAllocateConst> Shelf<S> {
-	pub fn insert_const(&self, value: S::Value) -> Ref<S::Value> {
-		Ref::new(self.storage.allocate_const(value))
+impl<S: StorageAllocate + Default, I: RefIndex> FromIterator<S::Value> for Shelf<S, I> {
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
 	}
 }
 
-impl<S: StorageSet> Shelf<S> {
-	pub fn set<T>(&mut self, r: Ref<T>, value: S::Value) -> Result<S::Value, S::Value>
+impl<S: StorageAllocateConst, I: RefIndex> Shelf<S, I> {
+	/// Inserts a new value using interior mutability only, and returns its
+	/// reference.
+	///
+	/// # Panics
+	///
+	/// Panics if the index allocated by the storage does not fit in the
+	/// index type `I`. The value is not inserted in this case.
+	/// See [`Self::try_insert_const`].
+	pub fn insert_const(&self, value: S::Value) -> Ref<S::Value, I> {
+		match self.try_insert_const(value) {
+			Ok(r) => r,
+			Err(_) => panic!("reference index overflow"),
+		}
+	}
+
+	/// Inserts a new value using interior mutability only, and returns its
+	/// reference, or returns the value back if the index allocated by the
+	/// storage does not fit in the index type `I`.
+	///
+	/// The storage is given the greatest index fitting in `I`, and left
+	/// unchanged on failure.
+	pub fn try_insert_const(&self, value: S::Value) -> Result<Ref<S::Value, I>, S::Value> {
+		self.storage
+			.try_allocate_const(value, I::MAX_INDEX)
+			.map(Ref::new)
+	}
+}
+
+impl<S: StorageSet, I: RefIndex> Shelf<S, I> {
+	pub fn set<T>(&mut self, r: Ref<T, I>, value: S::Value) -> Result<S::Value, S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
@@ -134,8 +319,8 @@ impl<S: StorageSet> Shelf<S> {
 	}
 }
 
-impl<S: StorageRemove> Shelf<S> {
-	pub fn remove<T>(&mut self, r: Ref<T>) -> Option<S::Value>
+impl<S: StorageRemove, I: RefIndex> Shelf<S, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
 	where
 		S::Value: Borrow<T>,
 	{
@@ -143,10 +328,138 @@ impl<S: StorageRemove> Shelf<S> {
 	}
 }
 
-impl<S: StorageSet + StorageRemove> Shelf<S> {
+impl<S: StorageIter + StorageRemove, I: RefIndex> Shelf<S, I> {
+	/// Removes all the values of the shelf, and returns them in an iterator
+	/// along with their former reference.
+	///
+	/// The values that are not consumed are removed when the iterator is
+	/// dropped.
+	pub fn drain(&mut self) -> Drain<'_, S, I> {
+		Drain::new(&mut self.storage)
+	}
+}
+
+impl<S: StorageIterMut + StorageRemove, I: RefIndex> Shelf<S, I> {
+	/// Only keeps the values for which the given predicate returns `true`.
+	pub fn retain(&mut self, f: impl FnMut(Ref<S::Value, I>, &mut S::Value) -> bool) {
+		retain(&mut self.storage, f)
+	}
+}
+
+/// Removes the values of the given storage for which `f` returns `false`.
+pub(crate) fn retain<S, T: ?Sized, I: RefIndex>(
+	storage: &mut S,
+	mut f: impl FnMut(Ref<T, I>, &mut S::Value) -> bool,
+) where
+	S: StorageIterMut + StorageRemove,
+{
+	let removed: Vec<usize> = storage
+		.iter_mut()
+		.filter_map(|(i, v)| (!f(Ref::new(i), v)).then_some(i))
+		.collect();
+
+	for i in removed {
+		storage.remove(i);
+	}
+}
+
+/// Draining iterator, returned by [`Shelf::drain`].
+pub struct Drain<'a, S: StorageRemove, I: RefIndex = usize> {
+	indices: std::vec::IntoIter<usize>,
+	storage: &'a mut S,
+	index: PhantomData<I>,
+}
+
+impl<'a, S: StorageIter + StorageRemove, I: RefIndex> Drain<'a, S, I> {
+	pub(crate) fn new(storage: &'a mut S) -> Self {
+		let indices: Vec<usize> = storage.iter().map(|(i, _)| i).collect();
+		Self {
+			indices: IntoIterator::into_iter(indices),
+			storage,
+			index: PhantomData,
+		}
+	}
+}
+
+impl<'a, S: StorageRemove, I: RefIndex> Iterator for Drain<'a, S, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		for i in self.indices.by_ref() {
+			if let Some(value) = self.storage.remove(i) {
+				return Some((Ref::new(i), value));
+			}
+		}
+
+		None
+	}
+}
+
+impl<'a, S: StorageRemove, I: RefIndex> Drop for Drain<'a, S, I> {
+	fn drop(&mut self) {
+		self.for_each(drop)
+	}
+}
+
+impl<S: StorageCompact, I: RefIndex> Shelf<S, I> {
+	/// Moves the values of the shelf so that they are densely packed.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let mut remap = Remap::new();
+		self.storage
+			.compact(|from, to| remap.insert(Ref::new(from), Ref::new(to)));
+		remap
+	}
+}
+
+impl<S: Storage, I: RefIndex> Shelf<UndoLog<S>, I> {
+	/// Takes a snapshot of the shelf.
+	///
+	/// The modifications made from now on can be undone using
+	/// [`Self::rollback_to`], or kept using [`Self::commit`].
+	///
+	/// Only shelves of an [`UndoLog`] storage can be snapshotted: use a
+	/// `Shelf<UndoLog<Vec<T>>>` instead of a `Shelf<Vec<T>>`.
+	pub fn snapshot(&mut self) -> Snapshot {
+		self.storage.snapshot()
+	}
+
+	/// Undoes the insertions, modifications and removals made since the given
+	/// snapshot was taken.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn rollback_to(&mut self, snapshot: Snapshot) {
+		self.storage.rollback_to(snapshot)
+	}
+
+	/// Keeps the modifications made since the given snapshot was taken.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn commit(&mut self, snapshot: Snapshot) {
+		self.storage.commit(snapshot)
+	}
+}
+
+impl<S: StorageIter, I: RefIndex> Shelf<UndoLog<S>, I> {
+	/// Returns the references of the values inserted, modified or removed
+	/// since the given snapshot was taken, in index order.
+	pub fn modified_since(&self, snapshot: &Snapshot) -> Vec<Ref<S::Value, I>> {
+		IntoIterator::into_iter(self.storage.modified_since(snapshot))
+			.map(Ref::new)
+			.collect()
+	}
+}
+
+impl<S: StorageSet + StorageRemove, I: RefIndex> Shelf<S, I> {
 	pub fn set_or_remove<T>(
 		&mut self,
-		r: Ref<T>,
+		r: Ref<T, I>,
 		value: Option<S::Value>,
 	) -> Result<Option<S::Value>, S::Value>
 	where
@@ -158,3 +471,192 @@ impl<S: StorageSet + StorageRemove> Shelf<S> {
 		}
 	}
 }
+
+impl<S: StorageIter, I: RefIndex> fmt::Debug for Shelf<S, I>
+where
+	S::Value: fmt::Debug,
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.storage.iter()).finish()
+	}
+}
+
+impl<S: StorageIter, I: RefIndex> PartialEq for Shelf<S, I>
+where
+	S::Value: PartialEq,
+{
+	/// Checks that both shelves store equal values behind the same
+	/// references, regardless of the iteration order of their storage.
+	fn eq(&self, other: &Self) -> bool {
+		storage_eq(&self.storage, &other.storage)
+	}
+}
+
+impl<S: StorageIter, I: RefIndex> Eq for Shelf<S, I> where S::Value: Eq {}
+
+impl<S: StorageIter, I: RefIndex> Hash for Shelf<S, I>
+where
+	S::Value: Hash,
+{
+	/// Hashes the references and values of the shelf, regardless of the
+	/// iteration order of its storage.
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		storage_hash(&self.storage, state)
+	}
+}
+
+/// Checks that both storages store equal values at the same indices.
+pub(crate) fn storage_eq<S: StorageIter>(a: &S, b: &S) -> bool
+where
+	S::Value: PartialEq,
+{
+	a.len() == b.len() && a.iter().all(|(i, v)| b.get(i) == Some(v))
+}
+
+/// Hashes the indices and values of the given storage.
+///
+/// Each entry is hashed separately and the results are summed up, so that
+/// storages iterating in different orders have the same hash.
+pub(crate) fn storage_hash<S: StorageIter, H: Hasher>(storage: &S, state: &mut H)
+where
+	S::Value: Hash,
+{
+	let sum = storage.iter().fold(0u64, |sum, entry| {
+		let mut hasher = DefaultHasher::new();
+		entry.hash(&mut hasher);
+		sum.wrapping_add(hasher.finish())
+	});
+
+	state.write_usize(storage.len());
+	state.write_u64(sum)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{AppendVec, FreeList, SparseSet, StorageInsert, SyncAppendVec};
+	use std::collections::{BTreeMap, HashMap};
+
+	fn fill_const<S: StorageAllocateConst<Value = u16> + StorageIter>(shelf: &Shelf<S, u16>) {
+		for i in 0..=u16::MAX {
+			assert_eq!(shelf.try_insert_const(i).map(|r| r.index()), Ok(i as usize));
+		}
+
+		assert_eq!(shelf.try_insert_const(0), Err(0));
+		assert_eq!(shelf.len(), u16::MAX as usize + 1);
+		assert_eq!(
+			shelf.refs().last().map(|r| r.index()),
+			Some(u16::MAX as usize)
+		);
+	}
+
+	#[test]
+	fn const_index_overflow() {
+		fill_const(&Shelf::<AppendVec<u16>, u16>::default());
+		fill_const(&Shelf::<SyncAppendVec<u16>, u16>::default())
+	}
+
+	fn retain_and_drain<S>()
+	where
+		S: StorageAllocate<Value = u32> + StorageIter + StorageIterMut + StorageRemove + Default,
+	{
+		let mut shelf: Shelf<S> = (0..10).collect();
+		shelf.retain(|r, value| {
+			*value += 100;
+			r.index() % 3 != 0
+		});
+
+		let mut values: Vec<_> = shelf.iter().map(|(r, v)| (r.index(), *v)).collect();
+		values.sort_unstable();
+		assert_eq!(
+			values,
+			[(1, 101), (2, 102), (4, 104), (5, 105), (7, 107), (8, 108)]
+		);
+
+		let mut drain = shelf.drain();
+		let (r, value) = drain.next().unwrap();
+		assert_eq!(value, r.index() as u32 + 100);
+		drop(drain);
+		assert!(shelf.is_empty());
+		assert_eq!(shelf.drain().count(), 0);
+
+		shelf.extend([1, 2]);
+		let mut drained: Vec<_> = shelf.drain().map(|(_, v)| v).collect();
+		drained.sort_unstable();
+		assert_eq!(drained, [1, 2]);
+		assert!(shelf.is_empty());
+	}
+
+	#[test]
+	fn retain_and_drain_storages() {
+		retain_and_drain::<FreeList<u32>>();
+		retain_and_drain::<BTreeMap<usize, u32>>();
+		retain_and_drain::<HashMap<usize, u32>>()
+	}
+
+	#[test]
+	fn index() {
+		let mut shelf: Shelf<Vec<String>> = Shelf::default();
+		let a = shelf.insert("a".to_string());
+		let b = shelf.insert("b".to_string());
+		shelf[b].push('c');
+		assert_eq!(shelf[a], "a");
+		assert_eq!(shelf[Ref::<String>::new(1)], "bc");
+	}
+
+	#[test]
+	#[should_panic(expected = "invalid reference")]
+	fn index_invalid_reference() {
+		let shelf: Shelf<Vec<u32>> = (0..3).collect();
+		let _ = shelf[Ref::<u32>::new(3)];
+	}
+
+	#[test]
+	fn into_iter() {
+		let mut shelf: Shelf<FreeList<u32>> = (0..4).collect();
+		shelf.remove(Ref::<u32>::new(1));
+
+		for (r, value) in &mut shelf {
+			*value += r.index() as u32
+		}
+
+		let borrowed: Vec<_> = (&shelf).into_iter().map(|(r, v)| (r.index(), *v)).collect();
+		assert_eq!(borrowed, [(0, 0), (2, 4), (3, 6)]);
+		let owned: Vec<_> = shelf.into_iter().map(|(r, v)| (r.index(), v)).collect();
+		assert_eq!(owned, borrowed);
+	}
+
+	fn hash_of<T: Hash>(value: &T) -> u64 {
+		let mut hasher = DefaultHasher::new();
+		value.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	#[test]
+	fn eq_and_hash_ignore_order() {
+		let mut a = SparseSet::default();
+		let mut b = SparseSet::default();
+		for i in [1, 2, 3] {
+			StorageInsert::insert(&mut a, i, i * 10);
+		}
+		for i in [3, 1, 2] {
+			StorageInsert::insert(&mut b, i, i * 10);
+		}
+
+		let a: Shelf<SparseSet<usize>> = Shelf::new(a);
+		let mut b: Shelf<SparseSet<usize>> = Shelf::new(b);
+		assert!(a.values().ne(b.values()));
+		assert_eq!(a, b);
+		assert_eq!(hash_of(&a), hash_of(&b));
+
+		b[Ref::<usize>::new(2)] = 0;
+		assert_ne!(a, b);
+		assert_ne!(hash_of(&a), hash_of(&b));
+
+		b[Ref::<usize>::new(2)] = 20;
+		b.remove(Ref::<usize>::new(3));
+		assert_ne!(a, b);
+		assert_ne!(b, a);
+		assert_ne!(hash_of(&a), hash_of(&b));
+	}
+}
@@ -53,11 +53,22 @@ pub trait StorageIterMut: Storage {
 pub trait StorageAllocate: Storage {
 	/// Allocates an index for the given value.
 	fn allocate(&mut self, value: Self::Value) -> usize;
+
+	/// Returns the index allocated by the next call to
+	/// [`allocate`](Self::allocate), without allocating it.
+	fn next_index(&self) -> usize;
 }
 
 pub trait StorageAllocateConst: Storage {
 	/// Allocates an index for the given value using interior mutability only.
 	fn allocate_const(&self, value: Self::Value) -> usize;
+
+	/// Allocates an index not greater than `max` for the given value using
+	/// interior mutability only.
+	///
+	/// Returns `Err(value)`, leaving the storage unchanged, if the next index
+	/// allocated by the storage would be greater than `max`.
+	fn try_allocate_const(&self, value: Self::Value, max: usize) -> Result<usize, Self::Value>;
 }
 
 pub trait StorageSet: Storage {
@@ -76,6 +87,12 @@ pub trait StorageInsert: Storage {
 	/// Contrarily to `StorageSet`, this function does allocate
 	/// the given index if necessary, and stores the value there.
 	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value>;
+
+	/// Whether inserting at some index allocates every lower index.
+	///
+	/// Deserialization bounds the indices inserted into dense storages so
+	/// that a crafted input cannot allocate unbounded memory.
+	const DENSE: bool = true;
 }
 
 pub trait StorageInsertConst: Storage {
@@ -91,3 +108,24 @@ pub trait StorageInsertConst: Storage {
 pub trait StorageRemove: Storage {
 	fn remove(&mut self, index: usize) -> Option<Self::Value>;
 }
+
+pub trait StorageDeallocate: StorageAllocate {
+	/// Undoes the allocation of the value at the given index, which must be the
+	/// last value allocated by the storage, and returns it.
+	fn deallocate(&mut self, index: usize) -> Option<Self::Value>;
+}
+
+impl<S: StorageAllocate + StorageRemove> StorageDeallocate for S {
+	fn deallocate(&mut self, index: usize) -> Option<Self::Value> {
+		self.remove(index)
+	}
+}
+
+pub trait StorageCompact: Storage {
+	/// Moves the stored values so that they occupy the indices `0..len()`.
+	///
+	/// The `moved` function is called with the old and new index of every
+	/// value whose index changed.
+	/// The relative order of the values is not necessarily preserved.
+	fn compact(&mut self, moved: impl FnMut(usize, usize));
+}
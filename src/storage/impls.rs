@@ -1,10 +1,32 @@
+pub mod append_vec;
 mod btree_map;
+mod chunks;
+pub mod free_list;
+pub mod generational;
 mod hash_map;
+mod monotonic;
+pub mod persistent_vec;
+mod sparse_set;
+pub mod sync_append_vec;
+pub mod undo_log;
 mod vec;
 
+#[cfg(feature = "mmap-storage")]
+pub mod mmap;
 #[cfg(feature = "slab-storage")]
 mod slab;
 
+pub use append_vec::AppendVec;
+pub use free_list::FreeList;
+pub use generational::{Generational, GenerationalIndex};
+#[cfg(feature = "mmap-storage")]
+pub use mmap::{MmapStorage, MmapWriter};
+pub use monotonic::Monotonic;
+pub use persistent_vec::PersistentVec;
+pub use sparse_set::SparseSet;
+pub use sync_append_vec::SyncAppendVec;
+pub use undo_log::{Snapshot, UndoLog};
+
 pub struct MapStorageIter<I> {
 	inner: I,
 }
@@ -0,0 +1,217 @@
+use super::chunks::{self, Chunks, Local};
+use crate::storage::*;
+use std::cell::Cell;
+use std::fmt;
+
+/// Append-only storage with stable addresses.
+///
+/// Values are stored in chunks of increasing size that are never
+/// reallocated, so a value never moves once it has been allocated.
+/// This allows the storage to allocate new values using interior mutability
+/// only (through [`StorageAllocateConst`] and [`StorageInsertConst`]) while
+/// still handing out references to the values already stored.
+///
+/// Values can only be replaced or removed through a mutable reference to the
+/// storage.
+#[derive(Clone)]
+pub struct AppendVec<T> {
+	chunks: Chunks<T, Local>,
+	len: Cell<usize>,
+	end: Cell<usize>,
+}
+
+impl<T> Default for AppendVec<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for AppendVec<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<T> AppendVec<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			chunks: Chunks::default(),
+			len: Cell::new(0),
+			end: Cell::new(0),
+		}
+	}
+
+	/// Stores the given value at the given index if it is vacant.
+	///
+	/// Returns `Err(value)` if the index is already occupied.
+	fn init(&self, index: usize, value: T) -> Result<(), T> {
+		self.chunks.allocated_slot(index).set(value)?;
+		self.len.set(self.len.get() + 1);
+		if index >= self.end.get() {
+			self.end.set(index + 1)
+		}
+		Ok(())
+	}
+}
+
+impl<T> Storage for AppendVec<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.chunks.get(index)
+	}
+
+	fn capacity(&self) -> usize {
+		self.chunks.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.len.get()
+	}
+}
+
+impl<T> StorageIter for AppendVec<T> {
+	type Iter<'a>
+		= Iter<'a, T>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.chunks.iter(self.end.get())
+	}
+}
+
+impl<T> StorageIntoIter for AppendVec<T> {
+	type IntoIter = IntoIter<T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.chunks.into_iter()
+	}
+}
+
+impl<T> StorageMut for AppendVec<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		self.chunks.get_mut(index)
+	}
+
+	fn clear(&mut self) {
+		self.chunks.clear();
+		self.len.set(0);
+		self.end.set(0)
+	}
+}
+
+impl<T> StorageIterMut for AppendVec<T> {
+	type IterMut<'a>
+		= IterMut<'a, T>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		self.chunks.iter_mut()
+	}
+}
+
+impl<T> StorageAllocate for AppendVec<T> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		self.allocate_const(value)
+	}
+
+	fn next_index(&self) -> usize {
+		self.end.get()
+	}
+}
+
+impl<T> StorageAllocateConst for AppendVec<T> {
+	fn allocate_const(&self, value: Self::Value) -> usize {
+		let index = self.end.get();
+		if self.init(index, value).is_err() {
+			unreachable!()
+		}
+		index
+	}
+
+	fn try_allocate_const(&self, value: Self::Value, max: usize) -> Result<usize, Self::Value> {
+		if self.end.get() > max {
+			Err(value)
+		} else {
+			Ok(self.allocate_const(value))
+		}
+	}
+}
+
+impl<T> StorageSet for AppendVec<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T> StorageInsert for AppendVec<T> {
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		match self.set(index, value) {
+			Ok(previous) => Some(previous),
+			Err(value) => {
+				if self.init(index, value).is_err() {
+					unreachable!()
+				}
+				None
+			}
+		}
+	}
+}
+
+impl<T> StorageInsertConst for AppendVec<T> {
+	/// Inserts the given value at the given index using interior mutability only.
+	///
+	/// Since stored values can be borrowed through a shared reference to the
+	/// storage, an occupied index is never overwritten:
+	/// if the index is already occupied, the storage is left unchanged and
+	/// the given `value` is returned instead.
+	fn insert_const(&self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		self.init(index, value).err()
+	}
+}
+
+impl<T> StorageCompact for AppendVec<T> {
+	fn compact(&mut self, moved: impl FnMut(usize, usize)) {
+		let len = self.len();
+		self.chunks.compact(len, self.end.get(), moved);
+		self.end.set(len)
+	}
+}
+
+impl<T> StorageRemove for AppendVec<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		let value = self.chunks.take(index)?;
+		self.len.set(self.len.get() - 1);
+		Some(value)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for AppendVec<T> {
+	/// Serializes the storage as a sequence of `(index, value)` pairs.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		crate::serialization::serialize_indexed(self, serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for AppendVec<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		crate::serialization::deserialize_indexed(deserializer)
+	}
+}
+
+pub type Iter<'a, T> = chunks::Iter<'a, T, Local>;
+
+pub type IterMut<'a, T> = chunks::IterMut<'a, T, Local>;
+
+pub type IntoIter<T> = chunks::IntoIter<T, Local>;
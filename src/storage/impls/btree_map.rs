@@ -55,6 +55,34 @@ impl<T> StorageIterMut for BTreeMap<usize, T> {
 	}
 }
 
+impl<T> StorageAllocate for BTreeMap<usize, T> {
+	/// Allocates the smallest free index for the given value.
+	///
+	/// This takes logarithmic time when the indices are contiguous,
+	/// and is otherwise linear in the position of the first free index.
+	/// Contrarily to `HashMap<usize, T>`, which allocates its length whenever
+	/// it is free, the smallest free index is always allocated.
+	/// Use the [`Monotonic`] adapter to allocate indices using a counter instead.
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = self.next_index();
+		self.insert(index, value);
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		match self.last_key_value() {
+			None => 0,
+			Some((&last, _)) if last.checked_add(1) == Some(self.len()) => self.len(),
+			Some(_) => self
+				.keys()
+				.enumerate()
+				.find(|(i, key)| i != *key)
+				.map(|(i, _)| i)
+				.unwrap(),
+		}
+	}
+}
+
 impl<T> StorageSet for BTreeMap<usize, T> {
 	fn set(&mut self, index: usize, value: Self::Value) -> Result<Self::Value, Self::Value> {
 		match self.entry(index) {
@@ -68,6 +96,22 @@ impl<T> StorageInsert for BTreeMap<usize, T> {
 	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
 		self.insert(index, value)
 	}
+
+	const DENSE: bool = false;
+}
+
+impl<T> StorageCompact for BTreeMap<usize, T> {
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		let len = self.len();
+		let holes: Vec<usize> = (0..len).filter(|i| !self.contains_key(i)).collect();
+		let movers: Vec<usize> = self.keys().copied().filter(|i| *i >= len).collect();
+
+		for (from, to) in std::iter::zip(movers, holes) {
+			let value = self.remove(&from).unwrap();
+			self.insert(to, value);
+			moved(from, to)
+		}
+	}
 }
 
 impl<T> StorageRemove for BTreeMap<usize, T> {
@@ -75,3 +119,27 @@ impl<T> StorageRemove for BTreeMap<usize, T> {
 		self.remove(&index)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn allocate() {
+		let mut storage: BTreeMap<usize, char> = BTreeMap::new();
+		for (i, c) in ('a'..='e').enumerate() {
+			assert_eq!(storage.allocate(c), i);
+		}
+
+		storage.remove(&1);
+		storage.remove(&3);
+		assert_eq!(storage.allocate('f'), 1);
+		assert_eq!(storage.allocate('g'), 3);
+		assert_eq!(storage.allocate('h'), 5);
+
+		let mut storage = BTreeMap::from([(5, 'a')]);
+		for i in [0, 1, 2, 3, 4, 6] {
+			assert_eq!(storage.allocate('b'), i);
+		}
+	}
+}
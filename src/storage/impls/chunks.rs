@@ -0,0 +1,313 @@
+use std::cell::OnceCell;
+use std::sync::OnceLock;
+
+/// Number of bits used to index the first chunk.
+const FIRST_CHUNK_BITS: u32 = 5;
+
+/// Number of chunks needed to cover every `usize` index.
+pub const CHUNKS: usize = (usize::BITS - FIRST_CHUNK_BITS) as usize;
+
+/// Returns the chunk containing the given index, and the offset of the index
+/// in this chunk.
+///
+/// The chunk `k` holds `2^(FIRST_CHUNK_BITS + k)` values.
+fn locate(index: usize) -> Option<(usize, usize)> {
+	let n = index.checked_add(1 << FIRST_CHUNK_BITS)?;
+	let bits = usize::BITS - 1 - n.leading_zeros();
+	Some(((bits - FIRST_CHUNK_BITS) as usize, n - (1 << bits)))
+}
+
+/// Returns the first index of the given chunk.
+fn chunk_start(chunk: usize) -> usize {
+	(1 << (FIRST_CHUNK_BITS as usize + chunk)) - (1 << FIRST_CHUNK_BITS)
+}
+
+fn chunk_len(chunk: usize) -> usize {
+	1 << (FIRST_CHUNK_BITS as usize + chunk)
+}
+
+/// Cell that can be written once through a shared reference.
+pub trait OnceSlot<T>: Default {
+	fn get(&self) -> Option<&T>;
+
+	fn get_mut(&mut self) -> Option<&mut T>;
+
+	fn get_or_init(&self, f: impl FnOnce() -> T) -> &T;
+
+	fn set(&self, value: T) -> Result<(), T>;
+
+	fn take(&mut self) -> Option<T>;
+
+	fn into_inner(self) -> Option<T>;
+}
+
+macro_rules! once_slot {
+	($($ty:ident),*) => {
+		$(
+			impl<T> OnceSlot<T> for $ty<T> {
+				fn get(&self) -> Option<&T> {
+					$ty::get(self)
+				}
+
+				fn get_mut(&mut self) -> Option<&mut T> {
+					$ty::get_mut(self)
+				}
+
+				fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+					$ty::get_or_init(self, f)
+				}
+
+				fn set(&self, value: T) -> Result<(), T> {
+					$ty::set(self, value)
+				}
+
+				fn take(&mut self) -> Option<T> {
+					$ty::take(self)
+				}
+
+				fn into_inner(self) -> Option<T> {
+					$ty::into_inner(self)
+				}
+			}
+		)*
+	};
+}
+
+once_slot!(OnceCell, OnceLock);
+
+/// Kind of cell used by chunks, either thread-local or thread-safe.
+pub trait OnceKind {
+	type Cell<T>: OnceSlot<T>;
+}
+
+/// Chunks of [`OnceCell`]s.
+pub struct Local;
+
+impl OnceKind for Local {
+	type Cell<T> = OnceCell<T>;
+}
+
+/// Chunks of [`OnceLock`]s.
+pub struct Shared;
+
+impl OnceKind for Shared {
+	type Cell<T> = OnceLock<T>;
+}
+
+type Chunk<T, K> = Box<[<K as OnceKind>::Cell<T>]>;
+
+/// Slots stored in chunks of increasing size that are never reallocated.
+///
+/// The number of values and the end of the slots are tracked by the owner.
+pub struct Chunks<T, K: OnceKind>([K::Cell<Chunk<T, K>>; CHUNKS]);
+
+impl<T, K: OnceKind> Default for Chunks<T, K> {
+	fn default() -> Self {
+		Self(std::array::from_fn(|_| K::Cell::default()))
+	}
+}
+
+impl<T: Clone, K: OnceKind> Clone for Chunks<T, K> {
+	fn clone(&self) -> Self {
+		Self(std::array::from_fn(|i| {
+			let cell = K::Cell::default();
+			if let Some(chunk) = self.0[i].get() {
+				let chunk = chunk
+					.iter()
+					.map(|slot| {
+						let cell = K::Cell::default();
+						if let Some(value) = slot.get() {
+							let _ = cell.set(value.clone());
+						}
+						cell
+					})
+					.collect();
+				let _ = cell.set(chunk);
+			}
+			cell
+		}))
+	}
+}
+
+impl<T, K: OnceKind> Chunks<T, K> {
+	pub fn slot(&self, index: usize) -> Option<&K::Cell<T>> {
+		let (chunk, offset) = locate(index)?;
+		self.0[chunk].get().map(|chunk| &chunk[offset])
+	}
+
+	pub fn slot_mut(&mut self, index: usize) -> Option<&mut K::Cell<T>> {
+		let (chunk, offset) = locate(index)?;
+		self.0[chunk].get_mut().map(|chunk| &mut chunk[offset])
+	}
+
+	/// Returns the slot of the given index, allocating its chunk if needed.
+	pub fn allocated_slot(&self, index: usize) -> &K::Cell<T> {
+		let (chunk, offset) = locate(index).expect("append vec index overflow");
+		let chunk = self.0[chunk]
+			.get_or_init(|| (0..chunk_len(chunk)).map(|_| K::Cell::default()).collect());
+		&chunk[offset]
+	}
+
+	pub fn get(&self, index: usize) -> Option<&T> {
+		self.slot(index).and_then(OnceSlot::get)
+	}
+
+	pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+		self.slot_mut(index).and_then(OnceSlot::get_mut)
+	}
+
+	pub fn take(&mut self, index: usize) -> Option<T> {
+		self.slot_mut(index).and_then(OnceSlot::take)
+	}
+
+	pub fn capacity(&self) -> usize {
+		self.0
+			.iter()
+			.enumerate()
+			.filter(|(_, chunk)| chunk.get().is_some())
+			.map(|(i, _)| chunk_len(i))
+			.sum()
+	}
+
+	pub fn clear(&mut self) {
+		for chunk in &mut self.0 {
+			if let Some(chunk) = chunk.get_mut() {
+				for slot in chunk.iter_mut() {
+					slot.take();
+				}
+			}
+		}
+	}
+
+	/// Moves the `len` values found before `end` to the first `len` slots.
+	pub fn compact(&mut self, len: usize, end: usize, mut moved: impl FnMut(usize, usize)) {
+		let mut hole = 0;
+		let mut mover = end;
+
+		loop {
+			while hole < len && self.get(hole).is_some() {
+				hole += 1
+			}
+
+			if hole >= len {
+				break;
+			}
+
+			// There are as many holes before `len` as values after it.
+			mover -= 1;
+			while self.get(mover).is_none() {
+				mover -= 1
+			}
+
+			let value = self.take(mover).unwrap();
+			if self.allocated_slot(hole).set(value).is_err() {
+				unreachable!()
+			}
+
+			moved(mover, hole)
+		}
+	}
+
+	/// Iterates over the values found before `end`.
+	pub fn iter(&self, end: usize) -> Iter<'_, T, K> {
+		Iter {
+			chunks: self,
+			index: 0,
+			end,
+		}
+	}
+
+	pub fn iter_mut(&mut self) -> IterMut<'_, T, K> {
+		IterMut {
+			chunks: self.0.iter_mut().enumerate(),
+			current: None,
+		}
+	}
+
+	pub fn into_iter(self) -> IntoIter<T, K> {
+		IntoIter {
+			chunks: IntoIterator::into_iter(self.0).enumerate(),
+			current: None,
+		}
+	}
+}
+
+pub struct Iter<'a, T, K: OnceKind> {
+	chunks: &'a Chunks<T, K>,
+	index: usize,
+	end: usize,
+}
+
+impl<'a, T: 'a, K: OnceKind> Iterator for Iter<'a, T, K> {
+	type Item = (usize, &'a T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while self.index < self.end {
+			let index = self.index;
+			self.index += 1;
+			if let Some(value) = self.chunks.get(index) {
+				return Some((index, value));
+			}
+		}
+
+		None
+	}
+}
+
+/// Slots of the current chunk, with the index of the first one.
+type Slots<I> = Option<(usize, std::iter::Enumerate<I>)>;
+
+pub struct IterMut<'a, T, K: OnceKind> {
+	chunks: std::iter::Enumerate<std::slice::IterMut<'a, K::Cell<Chunk<T, K>>>>,
+	current: Slots<std::slice::IterMut<'a, K::Cell<T>>>,
+}
+
+impl<'a, T: 'a, K: OnceKind> Iterator for IterMut<'a, T, K> {
+	type Item = (usize, &'a mut T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some((start, slots)) = &mut self.current {
+				for (offset, slot) in slots.by_ref() {
+					if let Some(value) = slot.get_mut() {
+						return Some((*start + offset, value));
+					}
+				}
+			}
+
+			let (i, chunk) = self.chunks.next()?;
+			self.current = chunk
+				.get_mut()
+				.map(|chunk| (chunk_start(i), chunk.iter_mut().enumerate()));
+		}
+	}
+}
+
+pub struct IntoIter<T, K: OnceKind> {
+	chunks: std::iter::Enumerate<std::array::IntoIter<K::Cell<Chunk<T, K>>, CHUNKS>>,
+	current: Slots<std::vec::IntoIter<K::Cell<T>>>,
+}
+
+impl<T, K: OnceKind> Iterator for IntoIter<T, K> {
+	type Item = (usize, T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some((start, slots)) = &mut self.current {
+				for (offset, slot) in slots.by_ref() {
+					if let Some(value) = slot.into_inner() {
+						return Some((*start + offset, value));
+					}
+				}
+			}
+
+			let (i, chunk) = self.chunks.next()?;
+			self.current = chunk.into_inner().map(|chunk| {
+				(
+					chunk_start(i),
+					IntoIterator::into_iter(chunk.into_vec()).enumerate(),
+				)
+			});
+		}
+	}
+}
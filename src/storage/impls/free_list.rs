@@ -0,0 +1,383 @@
+use crate::storage::*;
+use std::fmt;
+
+#[derive(Clone)]
+enum Slot<T> {
+	Occupied(T),
+	Vacant {
+		prev: Option<usize>,
+		next: Option<usize>,
+	},
+}
+
+impl<T> Slot<T> {
+	fn as_ref(&self) -> Option<&T> {
+		match self {
+			Self::Occupied(value) => Some(value),
+			Self::Vacant { .. } => None,
+		}
+	}
+
+	fn as_mut(&mut self) -> Option<&mut T> {
+		match self {
+			Self::Occupied(value) => Some(value),
+			Self::Vacant { .. } => None,
+		}
+	}
+
+	fn into_value(self) -> Option<T> {
+		match self {
+			Self::Occupied(value) => Some(value),
+			Self::Vacant { .. } => None,
+		}
+	}
+}
+
+/// Vector of optional slots reusing the vacant ones.
+///
+/// Removing a value leaves a hole in the vector. Holes are linked together
+/// in a vacancy chain so that they can be reused by the next allocations,
+/// most recently freed first. The chain is doubly linked so that a hole
+/// filled by `StorageInsert::insert` is taken out of it in constant time.
+/// Contrarily to `Vec<T>`, values can be removed without changing the index
+/// of the other values, and inserted at any index.
+#[derive(Clone)]
+pub struct FreeList<T> {
+	slots: Vec<Slot<T>>,
+	/// First slot of the vacancy chain.
+	vacant: Option<usize>,
+	len: usize,
+}
+
+impl<T> Default for FreeList<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for FreeList<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<T> FreeList<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			slots: Vec::new(),
+			vacant: None,
+			len: 0,
+		}
+	}
+
+	/// Creates a new empty storage with the given slot capacity.
+	pub fn with_capacity(capacity: usize) -> Self {
+		Self {
+			slots: Vec::with_capacity(capacity),
+			vacant: None,
+			len: 0,
+		}
+	}
+
+	/// Makes the given slot vacant, at the head of the vacancy chain.
+	fn link(&mut self, index: usize) {
+		let next = self.vacant.replace(index);
+		if let Some(Slot::Vacant { prev, .. }) = next.map(|next| &mut self.slots[next]) {
+			*prev = Some(index)
+		}
+
+		self.slots[index] = Slot::Vacant { prev: None, next }
+	}
+
+	/// Takes the given vacant slot out of the vacancy chain.
+	fn unlink(&mut self, index: usize) {
+		let (prev, next) = match self.slots[index] {
+			Slot::Vacant { prev, next } => (prev, next),
+			Slot::Occupied(_) => unreachable!(),
+		};
+
+		match prev {
+			Some(prev) => {
+				if let Slot::Vacant {
+					next: prev_next, ..
+				} = &mut self.slots[prev]
+				{
+					*prev_next = next
+				}
+			}
+			None => self.vacant = next,
+		}
+
+		if let Some(Slot::Vacant {
+			prev: next_prev, ..
+		}) = next.map(|next| &mut self.slots[next])
+		{
+			*next_prev = prev
+		}
+	}
+}
+
+impl<T> Storage for FreeList<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.slots.as_slice().get(index).and_then(Slot::as_ref)
+	}
+
+	fn capacity(&self) -> usize {
+		self.slots.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+}
+
+impl<T> StorageIter for FreeList<T> {
+	type Iter<'a>
+		= Iter<'a, T>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		Iter(self.slots.as_slice().iter().enumerate())
+	}
+}
+
+impl<T> StorageIntoIter for FreeList<T> {
+	type IntoIter = IntoIter<T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter(std::iter::IntoIterator::into_iter(self.slots).enumerate())
+	}
+}
+
+impl<T> StorageMut for FreeList<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		self.slots
+			.as_mut_slice()
+			.get_mut(index)
+			.and_then(Slot::as_mut)
+	}
+
+	fn clear(&mut self) {
+		self.slots.clear();
+		self.vacant = None;
+		self.len = 0
+	}
+}
+
+impl<T> StorageIterMut for FreeList<T> {
+	type IterMut<'a>
+		= IterMut<'a, T>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		IterMut(self.slots.as_mut_slice().iter_mut().enumerate())
+	}
+}
+
+impl<T> StorageAllocate for FreeList<T> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		self.len += 1;
+		match self.vacant {
+			Some(index) => {
+				self.unlink(index);
+				self.slots[index] = Slot::Occupied(value);
+				index
+			}
+			None => {
+				let index = self.slots.len();
+				self.slots.push(Slot::Occupied(value));
+				index
+			}
+		}
+	}
+
+	fn next_index(&self) -> usize {
+		self.vacant.unwrap_or(self.slots.len())
+	}
+}
+
+impl<T> StorageSet for FreeList<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T> StorageInsert for FreeList<T> {
+	/// Inserts the given value at the given index.
+	///
+	/// The slots between the end of the vector and the index become vacant,
+	/// the lowest one being reused first.
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		if index >= self.slots.len() {
+			let end = self.slots.len();
+			self.slots.resize_with(index, || Slot::Vacant {
+				prev: None,
+				next: None,
+			});
+
+			for hole in (end..index).rev() {
+				self.link(hole)
+			}
+
+			self.slots.push(Slot::Occupied(value));
+			self.len += 1;
+			return None;
+		}
+
+		match &mut self.slots[index] {
+			Slot::Occupied(current) => Some(std::mem::replace(current, value)),
+			Slot::Vacant { .. } => {
+				self.unlink(index);
+				self.slots[index] = Slot::Occupied(value);
+				self.len += 1;
+				None
+			}
+		}
+	}
+}
+
+impl<T> StorageRemove for FreeList<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		self.slots.as_slice().get(index)?.as_ref()?;
+		let slot = std::mem::replace(
+			&mut self.slots[index],
+			Slot::Vacant {
+				prev: None,
+				next: None,
+			},
+		);
+
+		self.link(index);
+		self.len -= 1;
+		slot.into_value()
+	}
+}
+
+impl<T> StorageCompact for FreeList<T> {
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		let len = self.len;
+		let mut hole = 0;
+		let mut mover = self.slots.len();
+
+		loop {
+			while hole < len && matches!(self.slots[hole], Slot::Occupied(_)) {
+				hole += 1
+			}
+
+			if hole >= len {
+				break;
+			}
+
+			// There are as many holes before `len` as values after it.
+			mover -= 1;
+			while matches!(self.slots[mover], Slot::Vacant { .. }) {
+				mover -= 1
+			}
+
+			self.slots.swap(hole, mover);
+			moved(mover, hole)
+		}
+
+		self.slots.truncate(len);
+		self.vacant = None
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for FreeList<T> {
+	/// Serializes the storage as a sequence of `(index, value)` pairs.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		crate::serialization::serialize_indexed(self, serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for FreeList<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		crate::serialization::deserialize_indexed(deserializer)
+	}
+}
+
+pub struct Iter<'a, T>(std::iter::Enumerate<std::slice::Iter<'a, Slot<T>>>);
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = (usize, &'a T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0
+			.by_ref()
+			.find_map(|(i, slot)| slot.as_ref().map(|v| (i, v)))
+	}
+}
+
+pub struct IterMut<'a, T>(std::iter::Enumerate<std::slice::IterMut<'a, Slot<T>>>);
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+	type Item = (usize, &'a mut T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0
+			.by_ref()
+			.find_map(|(i, slot)| slot.as_mut().map(|v| (i, v)))
+	}
+}
+
+pub struct IntoIter<T>(std::iter::Enumerate<std::vec::IntoIter<Slot<T>>>);
+
+impl<T> Iterator for IntoIter<T> {
+	type Item = (usize, T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0
+			.by_ref()
+			.find_map(|(i, slot)| slot.into_value().map(|v| (i, v)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn insert_in_vacancy_chain() {
+		let mut storage = FreeList::new();
+		for i in 0..5 {
+			storage.allocate(i);
+		}
+
+		for i in 1..4 {
+			storage.remove(i);
+		}
+
+		assert_eq!(StorageInsert::insert(&mut storage, 2, 20), None);
+		assert_eq!(storage.len(), 3);
+
+		let mut allocated = vec![storage.allocate(30), storage.allocate(10)];
+		allocated.sort_unstable();
+		assert_eq!(allocated, [1, 3]);
+		assert_eq!(storage.allocate(50), 5);
+		assert_eq!(storage.get(2), Some(&20));
+		assert_eq!(storage.get(3), Some(&30));
+	}
+
+	#[test]
+	fn insert_past_the_end() {
+		let mut storage = FreeList::new();
+		storage.allocate(0);
+		assert_eq!(StorageInsert::insert(&mut storage, 3, 3), None);
+		assert_eq!(storage.allocate(1), 1);
+		assert_eq!(storage.allocate(2), 2);
+		assert_eq!(storage.allocate(4), 4);
+	}
+}
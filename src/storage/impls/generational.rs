@@ -0,0 +1,595 @@
+use crate::storage::*;
+
+/// Number of bits of a packed index used to store the slot.
+const SLOT_BITS: u32 = usize::BITS / 2;
+
+/// Mask selecting the slot in a packed index.
+const SLOT_MASK: usize = (1 << SLOT_BITS) - 1;
+
+/// Greatest generation that can be stored in a packed index.
+const MAX_GENERATION: usize = usize::MAX >> SLOT_BITS;
+
+/// Index of a value stored in a [`Generational`] storage.
+///
+/// A generational index is made of a slot and the generation of this slot
+/// at the time the value was allocated.
+/// It is packed into a single `usize` so that it can be carried by a regular
+/// `Ref<T>`: the lower half of the bits stores the slot, and the upper half
+/// stores the generation.
+///
+/// Both halves are limited to [`Self::MAX_SLOT`] and [`Self::MAX_GENERATION`],
+/// that is `2^32 - 1` on 64-bit targets, but only `65535` on 32-bit targets.
+/// Since packed indices use the upper bits of a `usize`, they do not fit in
+/// narrower reference index types such as `u32` once a generation is not zero.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct GenerationalIndex {
+	slot: usize,
+	generation: usize,
+}
+
+impl GenerationalIndex {
+	/// Greatest slot that can be stored in a packed index.
+	pub const MAX_SLOT: usize = SLOT_MASK;
+
+	/// Greatest generation that can be stored in a packed index.
+	pub const MAX_GENERATION: usize = MAX_GENERATION;
+
+	/// Creates a new generational index.
+	///
+	/// Returns `None` if the slot or the generation does not fit in its half
+	/// of a packed index.
+	pub fn new(slot: usize, generation: usize) -> Option<Self> {
+		if slot <= SLOT_MASK && generation <= MAX_GENERATION {
+			Some(Self { slot, generation })
+		} else {
+			None
+		}
+	}
+
+	/// Returns the slot of the index.
+	pub fn slot(&self) -> usize {
+		self.slot
+	}
+
+	/// Returns the generation of the slot when the value was allocated.
+	pub fn generation(&self) -> usize {
+		self.generation
+	}
+}
+
+impl From<usize> for GenerationalIndex {
+	fn from(index: usize) -> Self {
+		Self {
+			slot: index & SLOT_MASK,
+			generation: index >> SLOT_BITS,
+		}
+	}
+}
+
+impl From<GenerationalIndex> for usize {
+	fn from(index: GenerationalIndex) -> Self {
+		index.slot | (index.generation << SLOT_BITS)
+	}
+}
+
+#[derive(Clone, Debug)]
+enum Slot<T> {
+	Occupied {
+		generation: usize,
+		value: T,
+	},
+	Vacant {
+		generation: usize,
+		next: Option<usize>,
+	},
+}
+
+/// Storage detecting the use of stale indices.
+///
+/// Each slot of the storage is associated to a generation counter that is
+/// incremented every time the value it holds is removed.
+/// The generation is packed in the index returned by
+/// [`allocate`](StorageAllocate::allocate) (see [`GenerationalIndex`]),
+/// so that any index referring to a removed value is ignored by this storage,
+/// even if its slot has been reused in the meantime.
+///
+/// # Limits
+///
+/// The storage holds at most [`GenerationalIndex::MAX_SLOT`]` + 1` slots:
+/// allocating a new slot past this limit panics. A slot whose generation
+/// counter reaches [`GenerationalIndex::MAX_GENERATION`] is retired instead
+/// of being reused, so that its stale indices never become valid again.
+/// On 32-bit targets, both limits are `65535`, which can be reached by
+/// long-running programs.
+///
+/// Packed indices only fit in the default `usize` reference index type.
+/// With a narrower index type, such as in a `Shelf<Generational<T>, u32>`,
+/// a vacant slot cannot be reused once its generation is not zero:
+/// [`Shelf::try_insert`](crate::Shelf::try_insert) then fails, and
+/// [`Shelf::insert`](crate::Shelf::insert) panics, without modifying the
+/// storage.
+#[derive(Clone, Debug)]
+pub struct Generational<T> {
+	slots: Vec<Slot<T>>,
+	next_vacant: Option<usize>,
+	len: usize,
+}
+
+impl<T> Default for Generational<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> Generational<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			slots: Vec::new(),
+			next_vacant: None,
+			len: 0,
+		}
+	}
+
+	/// Creates a new empty storage with the given slot capacity.
+	pub fn with_capacity(capacity: usize) -> Self {
+		Self {
+			slots: Vec::with_capacity(capacity),
+			next_vacant: None,
+			len: 0,
+		}
+	}
+
+	/// Checks if the given slot is vacant and can be reused.
+	fn is_reusable(&self, slot: usize) -> bool {
+		matches!(self.slots[slot], Slot::Vacant { generation, .. } if generation <= MAX_GENERATION)
+	}
+
+	/// Rebuilds the chain of reusable vacant slots, lowest slot first.
+	fn link_vacant(&mut self) {
+		self.next_vacant = None;
+		for slot in (0..self.slots.len()).rev() {
+			if self.is_reusable(slot) {
+				if let Slot::Vacant { next, .. } = &mut self.slots[slot] {
+					*next = self.next_vacant.replace(slot)
+				}
+			}
+		}
+	}
+
+	fn vacate(&mut self, slot: usize) -> Option<T> {
+		let generation = match &self.slots[slot] {
+			Slot::Occupied { generation, .. } => *generation,
+			Slot::Vacant { .. } => return None,
+		};
+
+		let next = if generation < MAX_GENERATION {
+			self.next_vacant.replace(slot)
+		} else {
+			None
+		};
+
+		let vacant = Slot::Vacant {
+			generation: generation + 1,
+			next,
+		};
+
+		self.len -= 1;
+		match std::mem::replace(&mut self.slots[slot], vacant) {
+			Slot::Occupied { value, .. } => Some(value),
+			Slot::Vacant { .. } => unreachable!(),
+		}
+	}
+}
+
+impl<T> Storage for Generational<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		let index = GenerationalIndex::from(index);
+		match self.slots.get(index.slot) {
+			Some(Slot::Occupied { generation, value }) if *generation == index.generation => {
+				Some(value)
+			}
+			_ => None,
+		}
+	}
+
+	fn capacity(&self) -> usize {
+		self.slots.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+}
+
+impl<T> StorageIter for Generational<T> {
+	type Iter<'a>
+		= Iter<'a, T>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		Iter(self.slots.as_slice().iter().enumerate())
+	}
+}
+
+impl<T> StorageIntoIter for Generational<T> {
+	type IntoIter = IntoIter<T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		IntoIter(std::iter::IntoIterator::into_iter(self.slots).enumerate())
+	}
+}
+
+impl<T> StorageMut for Generational<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		let index = GenerationalIndex::from(index);
+		match self.slots.get_mut(index.slot) {
+			Some(Slot::Occupied { generation, value }) if *generation == index.generation => {
+				Some(value)
+			}
+			_ => None,
+		}
+	}
+
+	/// Removes all the values of the storage.
+	///
+	/// Slots are kept so that indices of the removed values remain stale.
+	fn clear(&mut self) {
+		for slot in 0..self.slots.len() {
+			self.vacate(slot);
+		}
+	}
+}
+
+impl<T> StorageIterMut for Generational<T> {
+	type IterMut<'a>
+		= IterMut<'a, T>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		IterMut(self.slots.as_mut_slice().iter_mut().enumerate())
+	}
+}
+
+impl<T> StorageAllocate for Generational<T> {
+	/// Allocates an index for the given value.
+	///
+	/// # Panics
+	///
+	/// Panics if a new slot is needed and the storage already holds
+	/// [`GenerationalIndex::MAX_SLOT`]` + 1` slots.
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = match self.next_vacant {
+			Some(slot) => {
+				let generation = match self.slots[slot] {
+					Slot::Vacant { generation, next } => {
+						self.next_vacant = next;
+						generation
+					}
+					Slot::Occupied { .. } => unreachable!(),
+				};
+
+				self.slots[slot] = Slot::Occupied { generation, value };
+				GenerationalIndex { slot, generation }.into()
+			}
+			None => {
+				let slot = self.slots.len();
+				assert!(
+					slot <= SLOT_MASK,
+					"generational storage cannot hold more than {} slots",
+					SLOT_MASK as u128 + 1
+				);
+				self.slots.push(Slot::Occupied {
+					generation: 0,
+					value,
+				});
+				GenerationalIndex {
+					slot,
+					generation: 0,
+				}
+				.into()
+			}
+		};
+
+		self.len += 1;
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		let (slot, generation) = match self.next_vacant {
+			Some(slot) => match self.slots[slot] {
+				Slot::Vacant { generation, .. } => (slot, generation),
+				Slot::Occupied { .. } => unreachable!(),
+			},
+			None => (self.slots.len(), 0),
+		};
+
+		GenerationalIndex { slot, generation }.into()
+	}
+}
+
+impl<T> StorageSet for Generational<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T> StorageRemove for Generational<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		let index = GenerationalIndex::from(index);
+		match self.slots.get(index.slot) {
+			Some(Slot::Occupied { generation, .. }) if *generation == index.generation => {
+				self.vacate(index.slot)
+			}
+			_ => None,
+		}
+	}
+}
+
+impl<T> StorageCompact for Generational<T> {
+	/// Moves the stored values so that they occupy the first slots.
+	///
+	/// The generation of every slot left by a moved value is incremented so
+	/// that its old index becomes stale.
+	/// Slots are never deallocated, and retired slots are never reused, so
+	/// the values may not exactly occupy the indices `0..len()` afterward.
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		let mut hole = 0;
+		let mut mover = self.slots.len();
+
+		loop {
+			while hole < mover && !self.is_reusable(hole) {
+				hole += 1
+			}
+
+			while mover > hole && !matches!(self.slots[mover - 1], Slot::Occupied { .. }) {
+				mover -= 1
+			}
+
+			if hole + 1 >= mover {
+				break;
+			}
+
+			mover -= 1;
+			let hole_generation = match self.slots[hole] {
+				Slot::Vacant { generation, .. } => generation,
+				Slot::Occupied { .. } => unreachable!(),
+			};
+
+			let (generation, value) = match std::mem::replace(
+				&mut self.slots[mover],
+				Slot::Vacant {
+					generation: 0,
+					next: None,
+				},
+			) {
+				Slot::Occupied { generation, value } => (generation, value),
+				Slot::Vacant { .. } => unreachable!(),
+			};
+
+			self.slots[mover] = Slot::Vacant {
+				generation: generation + 1,
+				next: None,
+			};
+
+			self.slots[hole] = Slot::Occupied {
+				generation: hole_generation,
+				value,
+			};
+
+			moved(
+				GenerationalIndex {
+					slot: mover,
+					generation,
+				}
+				.into(),
+				GenerationalIndex {
+					slot: hole,
+					generation: hole_generation,
+				}
+				.into(),
+			)
+		}
+
+		self.link_vacant()
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for Generational<T> {
+	/// Serializes the storage as a sequence of `(generation, value)` slots,
+	/// where vacant slots have no value.
+	///
+	/// The generation of vacant slots is preserved so that stale references
+	/// remain stale after deserialization.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		use serde::ser::SerializeSeq;
+		let mut seq = serializer.serialize_seq(Some(self.slots.len()))?;
+		for slot in self.slots.as_slice().iter() {
+			match slot {
+				Slot::Occupied { generation, value } => {
+					seq.serialize_element(&(generation, Some(value)))?
+				}
+				Slot::Vacant { generation, .. } => {
+					seq.serialize_element(&(generation, None::<&T>))?
+				}
+			}
+		}
+
+		seq.end()
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Generational<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		use serde::de::Error;
+		let slots: Vec<(usize, Option<T>)> = serde::Deserialize::deserialize(deserializer)?;
+		if slots.len() > SLOT_MASK + 1 {
+			return Err(D::Error::custom("too many generational slots"));
+		}
+
+		let mut result = Self::with_capacity(slots.len());
+		for (generation, value) in slots {
+			let slot = match value {
+				Some(value) if generation <= MAX_GENERATION => {
+					result.len += 1;
+					Slot::Occupied { generation, value }
+				}
+				None if generation <= MAX_GENERATION + 1 => Slot::Vacant {
+					generation,
+					next: None,
+				},
+				_ => {
+					return Err(D::Error::custom(format_args!(
+						"invalid slot generation {generation}"
+					)))
+				}
+			};
+
+			result.slots.push(slot)
+		}
+
+		result.link_vacant();
+		Ok(result)
+	}
+}
+
+pub struct Iter<'a, T>(std::iter::Enumerate<std::slice::Iter<'a, Slot<T>>>);
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = (usize, &'a T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		for (slot, entry) in self.0.by_ref() {
+			if let Slot::Occupied { generation, value } = entry {
+				let index = GenerationalIndex {
+					slot,
+					generation: *generation,
+				};
+				return Some((index.into(), value));
+			}
+		}
+
+		None
+	}
+}
+
+pub struct IterMut<'a, T>(std::iter::Enumerate<std::slice::IterMut<'a, Slot<T>>>);
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+	type Item = (usize, &'a mut T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		for (slot, entry) in self.0.by_ref() {
+			if let Slot::Occupied { generation, value } = entry {
+				let index = GenerationalIndex {
+					slot,
+					generation: *generation,
+				};
+				return Some((index.into(), value));
+			}
+		}
+
+		None
+	}
+}
+
+pub struct IntoIter<T>(std::iter::Enumerate<std::vec::IntoIter<Slot<T>>>);
+
+impl<T> Iterator for IntoIter<T> {
+	type Item = (usize, T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		for (slot, entry) in self.0.by_ref() {
+			if let Slot::Occupied { generation, value } = entry {
+				let index = GenerationalIndex { slot, generation };
+				return Some((index.into(), value));
+			}
+		}
+
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Ref, Shelf};
+
+	#[test]
+	fn stale_reference() {
+		let mut shelf: Shelf<Generational<&str>> = Shelf::default();
+		let a = shelf.insert("a");
+		assert_eq!(shelf.remove(a), Some("a"));
+
+		let b = shelf.insert("b");
+		assert_eq!(
+			GenerationalIndex::from(a.index()).slot(),
+			GenerationalIndex::from(b.index()).slot()
+		);
+		assert_ne!(a, b);
+
+		assert_eq!(shelf.get(a), None);
+		assert_eq!(shelf.get_mut(a), None);
+		assert_eq!(shelf.set(a, "c"), Err("c"));
+		assert_eq!(shelf.remove(a), None);
+		assert_eq!(shelf.get(b), Some(&"b"));
+		assert_eq!(shelf.len(), 1);
+	}
+
+	#[test]
+	fn retire_slot() {
+		let mut storage = Generational::new();
+		storage.slots.push(Slot::Occupied {
+			generation: MAX_GENERATION,
+			value: "a",
+		});
+		storage.len = 1;
+
+		let a = GenerationalIndex::new(0, MAX_GENERATION).unwrap().into();
+		assert_eq!(storage.remove(a), Some("a"));
+		assert_eq!(storage.next_index(), 1);
+
+		let b = storage.allocate("b");
+		assert_eq!(b, 1);
+		assert_eq!(storage.get(a), None);
+
+		storage.remove(b);
+		storage.compact(|_, _| panic!("no value to move"));
+		assert_eq!(GenerationalIndex::from(storage.allocate("c")).slot(), 1)
+	}
+
+	#[test]
+	fn compact_remaps_live_references() {
+		let mut shelf: Shelf<Generational<usize>> = Shelf::default();
+		let refs: Vec<Ref<usize>> = (0..6).map(|i| shelf.insert(i)).collect();
+		shelf.remove(refs[0]);
+		shelf.remove(refs[2]);
+
+		let remap = shelf.compact();
+		assert_eq!(remap.len(), 2);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			if i == 0 || i == 2 {
+				continue;
+			}
+
+			let new_r = remap.apply(r);
+			assert_eq!(shelf.get(new_r), Some(&i));
+			assert!(GenerationalIndex::from(new_r.index()).slot() < 4);
+			if remap.get(r).is_some() {
+				assert_eq!(shelf.get(r), None)
+			}
+		}
+	}
+}
@@ -55,6 +55,37 @@ impl<T> StorageIterMut for HashMap<usize, T> {
 	}
 }
 
+impl<T> StorageAllocate for HashMap<usize, T> {
+	/// Allocates an index for the given value.
+	///
+	/// The length of the map is allocated if it is free, which is always the
+	/// case when the indices are contiguous, so that filling the map takes
+	/// constant time per value.
+	/// Otherwise, the smallest free index is allocated, which takes linear
+	/// time in this index.
+	///
+	/// This is deliberately not always the smallest free index: a hash map
+	/// cannot find it without scanning its keys, which would make filling
+	/// the map quadratic. For instance, the map `{5}` allocates `1`, while a
+	/// `BTreeMap<usize, T>` with the same key allocates `0`.
+	/// Use the [`Monotonic`] adapter to allocate indices using a counter instead.
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = self.next_index();
+		self.insert(index, value);
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		let len = self.len();
+		if self.contains_key(&len) {
+			// There are `len` keys, one of which is not lower than `len`.
+			(0..len).find(|i| !self.contains_key(i)).unwrap()
+		} else {
+			len
+		}
+	}
+}
+
 impl<T> StorageSet for HashMap<usize, T> {
 	fn set(&mut self, index: usize, value: Self::Value) -> Result<Self::Value, Self::Value> {
 		match self.entry(index) {
@@ -68,6 +99,22 @@ impl<T> StorageInsert for HashMap<usize, T> {
 	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
 		self.insert(index, value)
 	}
+
+	const DENSE: bool = false;
+}
+
+impl<T> StorageCompact for HashMap<usize, T> {
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		let len = self.len();
+		let holes: Vec<usize> = (0..len).filter(|i| !self.contains_key(i)).collect();
+		let movers: Vec<usize> = self.keys().copied().filter(|i| *i >= len).collect();
+
+		for (from, to) in std::iter::zip(movers, holes) {
+			let value = self.remove(&from).unwrap();
+			self.insert(to, value);
+			moved(from, to)
+		}
+	}
 }
 
 impl<T> StorageRemove for HashMap<usize, T> {
@@ -75,3 +122,27 @@ impl<T> StorageRemove for HashMap<usize, T> {
 		self.remove(&index)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn allocate() {
+		let mut storage: HashMap<usize, char> = HashMap::new();
+		for (i, c) in ('a'..='e').enumerate() {
+			assert_eq!(storage.allocate(c), i);
+		}
+
+		storage.remove(&1);
+		storage.remove(&3);
+		assert_eq!(storage.allocate('f'), 3);
+		assert_eq!(storage.allocate('g'), 1);
+		assert_eq!(storage.allocate('h'), 5);
+
+		let mut storage = HashMap::from([(5, 'a')]);
+		for i in [1, 2, 3, 4, 0, 6] {
+			assert_eq!(storage.allocate('b'), i);
+		}
+	}
+}
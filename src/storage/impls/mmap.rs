@@ -0,0 +1,218 @@
+use crate::storage::*;
+use bytemuck::Pod;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::path::Path;
+
+/// Magic number at the start of every file.
+const MAGIC: [u8; 8] = *b"SHELVES\0";
+
+/// Length of the file header: the magic number followed by the record size,
+/// the record alignment and the number of records, as little-endian `u64`.
+const HEADER_LEN: usize = 32;
+
+/// Offset of the number of records in the header.
+const COUNT_OFFSET: usize = 24;
+
+/// Offset of the first record, aligned for `T`.
+fn data_offset<T>() -> usize {
+	HEADER_LEN.next_multiple_of(align_of::<T>())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read-only storage of fixed-size records mapped in memory from a file.
+///
+/// The file is written by a [`MmapWriter`], and values are read in place
+/// without being deserialized.
+/// Records are stored in the byte representation of `T`, which must be plain
+/// old data as defined by [`bytemuck::Pod`]. Since this representation
+/// depends on the target, files should be read on a target with the same
+/// endianness as the one they were written on.
+/// The size and alignment of `T` are checked when the file is opened.
+///
+/// The value at index `i` is the `i`-th record of the file, so that the
+/// references to the values of a shelf stay valid once written.
+///
+/// Only fixed-size records are supported. Since the values of a storage must
+/// be sized, there is no offset table layout for unsized values such as `str`
+/// or `[u8]`: store them as fixed-size records, such as `[u8; N]` arrays, or
+/// as offset ranges into a separate payload file.
+pub struct MmapStorage<T> {
+	map: Mmap,
+	len: usize,
+	value: PhantomData<T>,
+}
+
+impl<T: Pod> MmapStorage<T> {
+	/// Maps the given file in memory.
+	///
+	/// # Safety
+	///
+	/// The file must not be modified, by this process or another, while it is
+	/// mapped. See [`Mmap::map`].
+	pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+		let file = File::open(path)?;
+		Self::from_mmap(Mmap::map(&file)?)
+	}
+
+	/// Creates a storage from a memory map of a file written by a
+	/// [`MmapWriter`].
+	///
+	/// Fails if the header of the file does not match `T`, or if the file is
+	/// truncated.
+	pub fn from_mmap(map: Mmap) -> io::Result<Self> {
+		if size_of::<T>() == 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"zero-sized records are not supported",
+			));
+		}
+
+		let header = map
+			.get(..HEADER_LEN)
+			.ok_or_else(|| invalid_data("missing header"))?;
+		if header[..8] != MAGIC {
+			return Err(invalid_data("invalid magic number"));
+		}
+
+		let field = |i: usize| u64::from_le_bytes(header[i..i + 8].try_into().unwrap());
+		if field(8) != size_of::<T>() as u64 {
+			return Err(invalid_data("record size mismatch"));
+		}
+
+		if field(16) != align_of::<T>() as u64 {
+			return Err(invalid_data("record alignment mismatch"));
+		}
+
+		let len =
+			usize::try_from(field(COUNT_OFFSET)).map_err(|_| invalid_data("too many records"))?;
+		let end = len
+			.checked_mul(size_of::<T>())
+			.and_then(|size| size.checked_add(data_offset::<T>()))
+			.ok_or_else(|| invalid_data("too many records"))?;
+		if map.len() < end {
+			return Err(invalid_data("truncated records"));
+		}
+
+		Ok(Self {
+			map,
+			len,
+			value: PhantomData,
+		})
+	}
+
+	/// Returns the records of the storage.
+	pub fn as_slice(&self) -> &[T] {
+		let start = data_offset::<T>();
+		bytemuck::cast_slice(&self.map[start..start + self.len * size_of::<T>()])
+	}
+}
+
+impl<T: Pod> Storage for MmapStorage<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.as_slice().get(index)
+	}
+
+	fn capacity(&self) -> usize {
+		self.len
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+}
+
+impl<T: Pod> StorageIter for MmapStorage<T> {
+	type Iter<'a>
+		= std::iter::Enumerate<std::slice::Iter<'a, T>>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.as_slice().iter().enumerate()
+	}
+}
+
+/// Writer of files to be read by a [`MmapStorage`].
+///
+/// Records are written in order, the `i`-th pushed value being at index `i`
+/// once the file is mapped. The number of records is written in the header
+/// by [`Self::finish`].
+pub struct MmapWriter<T, W> {
+	inner: W,
+	len: usize,
+	value: PhantomData<T>,
+}
+
+impl<T: Pod, W: Write + Seek> MmapWriter<T, W> {
+	/// Starts writing a file with the given writer, positioned at the
+	/// beginning of the file.
+	pub fn new(mut inner: W) -> io::Result<Self> {
+		let mut header = vec![0; data_offset::<T>()];
+		header[..8].copy_from_slice(&MAGIC);
+		header[8..16].copy_from_slice(&(size_of::<T>() as u64).to_le_bytes());
+		header[16..24].copy_from_slice(&(align_of::<T>() as u64).to_le_bytes());
+		inner.write_all(&header)?;
+
+		Ok(Self {
+			inner,
+			len: 0,
+			value: PhantomData,
+		})
+	}
+
+	/// Returns the number of records written so far.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Checks if no record has been written yet.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Writes a record, and returns its index.
+	pub fn push(&mut self, value: &T) -> io::Result<usize> {
+		self.inner.write_all(bytemuck::bytes_of(value))?;
+		let index = self.len;
+		self.len += 1;
+		Ok(index)
+	}
+
+	/// Writes the values of the given storage, in index order.
+	///
+	/// Fails if the indices of the storage do not follow the records written
+	/// so far without holes, since they would not be preserved.
+	pub fn write_storage<S: StorageIter<Value = T>>(&mut self, storage: &S) -> io::Result<()> {
+		for (index, value) in storage.iter() {
+			if index != self.len {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidInput,
+					"storage indices are not contiguous",
+				));
+			}
+
+			self.push(value)?;
+		}
+
+		Ok(())
+	}
+
+	/// Writes the number of records in the header, and returns the
+	/// underlying writer, positioned after the last record.
+	pub fn finish(mut self) -> io::Result<W> {
+		self.inner.seek(SeekFrom::Start(COUNT_OFFSET as u64))?;
+		self.inner.write_all(&(self.len as u64).to_le_bytes())?;
+		self.inner.seek(SeekFrom::End(0))?;
+		self.inner.flush()?;
+		Ok(self.inner)
+	}
+}
@@ -0,0 +1,236 @@
+use crate::storage::*;
+
+/// Storage adapter allocating indices using a monotonic counter.
+///
+/// Every allocated index is greater than any index previously allocated or
+/// inserted in the storage, so removed indices are never reused.
+/// This can be used on top of sparse storages such as `BTreeMap<usize, T>`
+/// or `HashMap<usize, T>`, which otherwise reuse the free indices.
+#[derive(Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Monotonic<S> {
+	storage: S,
+	next: usize,
+}
+
+impl<S: StorageIter> Monotonic<S> {
+	/// Wraps the given storage.
+	///
+	/// The counter starts after the greatest index of the storage.
+	pub fn new(storage: S) -> Self {
+		let next = storage.iter().map(|(i, _)| i + 1).max().unwrap_or(0);
+		Self { storage, next }
+	}
+}
+
+impl<S> Monotonic<S> {
+	/// Returns the next index that will be allocated.
+	pub fn next_index(&self) -> usize {
+		self.next
+	}
+
+	pub fn into_storage(self) -> S {
+		self.storage
+	}
+
+	pub fn as_storage(&self) -> &S {
+		&self.storage
+	}
+}
+
+impl<S: Storage> Storage for Monotonic<S> {
+	type Value = S::Value;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.storage.get(index)
+	}
+
+	fn capacity(&self) -> usize {
+		self.storage.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.storage.len()
+	}
+}
+
+impl<S: StorageIter> StorageIter for Monotonic<S> {
+	type Iter<'a>
+		= S::Iter<'a>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.storage.iter()
+	}
+}
+
+impl<S: StorageIntoIter> StorageIntoIter for Monotonic<S> {
+	type IntoIter = S::IntoIter;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.storage.into_iter()
+	}
+}
+
+impl<S: StorageMut> StorageMut for Monotonic<S> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		self.storage.get_mut(index)
+	}
+
+	/// Removes all the values of the storage.
+	///
+	/// The counter is not reset, so the indices of the removed values
+	/// are never reused.
+	fn clear(&mut self) {
+		self.storage.clear()
+	}
+}
+
+impl<S: StorageIterMut> StorageIterMut for Monotonic<S> {
+	type IterMut<'a>
+		= S::IterMut<'a>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		self.storage.iter_mut()
+	}
+}
+
+impl<S: StorageInsert> StorageAllocate for Monotonic<S> {
+	/// Allocates the next index of the counter for the given value.
+	///
+	/// # Panics
+	///
+	/// Panics if the counter overflows, or if the index of the counter is
+	/// already occupied in the underlying storage. The storage is left
+	/// unchanged in both cases.
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = self.next;
+		assert!(
+			self.storage.get(index).is_none(),
+			"monotonic index {index} is already occupied"
+		);
+		self.next = index.checked_add(1).expect("monotonic index overflow");
+		self.storage.insert(index, value);
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		self.next
+	}
+}
+
+impl<S: StorageSet> StorageSet for Monotonic<S> {
+	fn set(&mut self, index: usize, value: Self::Value) -> Result<Self::Value, Self::Value> {
+		self.storage.set(index, value)
+	}
+}
+
+impl<S: StorageInsert> StorageInsert for Monotonic<S> {
+	/// Inserts the given value at the given index.
+	///
+	/// The counter is moved after the given index if necessary.
+	///
+	/// # Panics
+	///
+	/// Panics if `index` is `usize::MAX`.
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		if index >= self.next {
+			self.next = index.checked_add(1).expect("monotonic index overflow")
+		}
+
+		self.storage.insert(index, value)
+	}
+
+	const DENSE: bool = S::DENSE;
+}
+
+impl<S: StorageCompact> StorageCompact for Monotonic<S> {
+	/// Compacts the underlying storage.
+	///
+	/// The counter is not reset.
+	fn compact(&mut self, moved: impl FnMut(usize, usize)) {
+		self.storage.compact(moved)
+	}
+}
+
+impl<S: StorageRemove> StorageRemove for Monotonic<S> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		self.storage.remove(index)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S: StorageIter + serde::Deserialize<'de>> serde::Deserialize<'de> for Monotonic<S> {
+	/// Deserializes the storage and its counter.
+	///
+	/// The counter is moved after the greatest index of the storage, as done
+	/// by [`Monotonic::new`], if the deserialized counter is lower.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		#[derive(serde::Deserialize)]
+		#[serde(rename = "Monotonic")]
+		struct Repr<S> {
+			storage: S,
+			next: usize,
+		}
+
+		let Repr { storage, next } = Repr::deserialize(deserializer)?;
+		let mut result = Self::new(storage);
+		result.next = result.next.max(next);
+		Ok(result)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::{BTreeMap, HashMap};
+
+	#[test]
+	fn allocate_after_removals() {
+		let mut storage: Monotonic<HashMap<usize, char>> = Monotonic::default();
+		assert_eq!(storage.allocate('a'), 0);
+		assert_eq!(storage.allocate('b'), 1);
+		assert_eq!(storage.allocate('c'), 2);
+
+		storage.remove(2);
+		storage.remove(0);
+		assert_eq!(storage.allocate('d'), 3);
+
+		storage.clear();
+		assert_eq!(storage.next_index(), 4);
+		assert_eq!(storage.allocate('e'), 4);
+
+		assert_eq!(StorageInsert::insert(&mut storage, 9, 'f'), None);
+		assert_eq!(storage.allocate('g'), 10);
+		assert_eq!(storage.len(), 3);
+	}
+
+	#[test]
+	fn new_starts_after_greatest_index() {
+		let storage = BTreeMap::from([(2, 'a'), (7, 'b')]);
+		let mut storage = Monotonic::new(storage);
+		assert_eq!(storage.next_index(), 8);
+		assert_eq!(storage.allocate('c'), 8);
+		assert_eq!(storage.get(2), Some(&'a'));
+		assert_eq!(storage.get(7), Some(&'b'));
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn deserialize_checks_counter() {
+		let mut storage: Monotonic<BTreeMap<usize, char>> =
+			serde_json::from_str(r#"{"storage":{"0":"a","4":"b"},"next":2}"#).unwrap();
+		assert_eq!(storage.next_index(), 5);
+		assert_eq!(storage.allocate('c'), 5);
+		assert_eq!(storage.get(4), Some(&'b'));
+
+		storage.remove(5);
+		let json = serde_json::to_string(&storage).unwrap();
+		assert_eq!(json, r#"{"storage":{"0":"a","4":"b"},"next":6}"#);
+		let storage: Monotonic<BTreeMap<usize, char>> = serde_json::from_str(&json).unwrap();
+		assert_eq!(storage.next_index(), 6);
+	}
+}
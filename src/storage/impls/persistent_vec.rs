@@ -0,0 +1,501 @@
+use crate::storage::*;
+use std::fmt;
+use std::sync::Arc;
+
+/// Number of index bits consumed by each level of the tree.
+const BITS: usize = 5;
+
+/// Maximum number of children of a node.
+const WIDTH: usize = 1 << BITS;
+
+const MASK: usize = WIDTH - 1;
+
+/// Persistent vector of optional slots.
+///
+/// Values are stored in the leaves of a tree whose nodes have up to 32
+/// children, shared between the clones of the storage.
+/// Cloning the storage is therefore `O(1)`, and modifying a clone only copies
+/// the nodes on the path to the modified slot, leaving the other clones
+/// untouched.
+/// This makes it suitable to keep many versions of a shelf or map, where old
+/// references keep resolving in old versions.
+///
+/// As with `FreeList<T>`, values can be inserted and removed at any index
+/// without moving the other values.
+/// New values are allocated after the last occupied slot.
+/// Modifying the storage requires `T: Clone`, to copy the shared slots.
+///
+/// Nodes are shared through atomic reference counters, so that versions of
+/// the storage can be sent to other threads when `T` is `Send` and `Sync`.
+pub struct PersistentVec<T> {
+	root: Arc<Node<T>>,
+	/// Index bits consumed by the levels below the root.
+	shift: usize,
+	/// Index following the last occupied slot.
+	end: usize,
+	len: usize,
+}
+
+#[derive(Clone)]
+enum Node<T> {
+	Branch(Vec<Arc<Node<T>>>),
+	Leaf(Vec<Option<T>>),
+}
+
+impl<T> Clone for PersistentVec<T> {
+	/// Clones the storage in constant time, sharing all its slots.
+	fn clone(&self) -> Self {
+		Self {
+			root: self.root.clone(),
+			shift: self.shift,
+			end: self.end,
+			len: self.len,
+		}
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentVec<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<T> Default for PersistentVec<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T> PersistentVec<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			root: Arc::new(Node::Leaf(Vec::new())),
+			shift: 0,
+			end: 0,
+			len: 0,
+		}
+	}
+
+	/// Checks if the two storages share the same tree, in which case they
+	/// hold the same values.
+	pub fn ptr_eq(&self, other: &Self) -> bool {
+		Arc::ptr_eq(&self.root, &other.root)
+	}
+
+	fn slot(&self, index: usize) -> Option<&Option<T>> {
+		if index >= self.end {
+			return None;
+		}
+
+		let mut node = &*self.root;
+		let mut shift = self.shift;
+		loop {
+			match node {
+				Node::Branch(children) => {
+					node = children.get((index >> shift) & MASK)?;
+					shift -= BITS
+				}
+				Node::Leaf(slots) => return slots.get(index & MASK),
+			}
+		}
+	}
+
+	/// Moves `end` back to the index following the last occupied slot.
+	fn trim(&mut self) {
+		while self.end > 0 && !matches!(self.slot(self.end - 1), Some(Some(_))) {
+			self.end -= 1
+		}
+	}
+}
+
+impl<T: Clone> PersistentVec<T> {
+	/// Returns the slot at the given index, growing the tree if necessary.
+	///
+	/// The nodes on the path to the slot are copied if they are shared.
+	fn slot_mut(&mut self, index: usize) -> &mut Option<T> {
+		while index >> self.shift >= WIDTH {
+			self.root = Arc::new(Node::Branch(vec![self.root.clone()]));
+			self.shift += BITS
+		}
+
+		if index >= self.end {
+			self.end = index + 1
+		}
+
+		let mut node = Arc::make_mut(&mut self.root);
+		let mut shift = self.shift;
+		loop {
+			match node {
+				Node::Branch(children) => {
+					let i = (index >> shift) & MASK;
+					while children.len() <= i {
+						children.push(Arc::new(if shift == BITS {
+							Node::Leaf(Vec::new())
+						} else {
+							Node::Branch(Vec::new())
+						}))
+					}
+
+					node = Arc::make_mut(&mut children[i]);
+					shift -= BITS
+				}
+				Node::Leaf(slots) => {
+					let i = index & MASK;
+					if slots.len() <= i {
+						slots.resize_with(i + 1, || None)
+					}
+
+					return &mut slots[i];
+				}
+			}
+		}
+	}
+}
+
+impl<T> Storage for PersistentVec<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.slot(index)?.as_ref()
+	}
+
+	fn capacity(&self) -> usize {
+		self.end
+	}
+
+	fn len(&self) -> usize {
+		self.len
+	}
+}
+
+impl<T> StorageIter for PersistentVec<T> {
+	type Iter<'a>
+		= Iter<'a, T>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		match &*self.root {
+			Node::Branch(children) => Iter {
+				branches: vec![(0, self.shift, children.as_slice().iter().enumerate())],
+				leaf: None,
+			},
+			Node::Leaf(slots) => Iter {
+				branches: Vec::new(),
+				leaf: Some((0, slots.as_slice().iter().enumerate())),
+			},
+		}
+	}
+}
+
+impl<T: Clone> StorageIntoIter for PersistentVec<T> {
+	type IntoIter = IntoIter<T>;
+
+	/// Moves the values out of the storage, cloning the ones shared with
+	/// other storages.
+	fn into_iter(self) -> Self::IntoIter {
+		let mut values = Vec::with_capacity(self.len);
+		into_values(self.root, 0, self.shift, &mut values);
+		IntoIter(std::iter::IntoIterator::into_iter(values))
+	}
+}
+
+fn into_values<T: Clone>(
+	node: Arc<Node<T>>,
+	base: usize,
+	shift: usize,
+	values: &mut Vec<(usize, T)>,
+) {
+	match Arc::unwrap_or_clone(node) {
+		Node::Branch(children) => {
+			for (i, child) in std::iter::IntoIterator::into_iter(children).enumerate() {
+				into_values(child, base + (i << shift), shift - BITS, values)
+			}
+		}
+		Node::Leaf(slots) => values.extend(
+			std::iter::IntoIterator::into_iter(slots)
+				.enumerate()
+				.filter_map(|(i, slot)| slot.map(|v| (base + i, v))),
+		),
+	}
+}
+
+impl<T: Clone> StorageMut for PersistentVec<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		if self.get(index).is_some() {
+			self.slot_mut(index).as_mut()
+		} else {
+			None
+		}
+	}
+
+	fn clear(&mut self) {
+		*self = Self::new()
+	}
+}
+
+impl<T: Clone> StorageIterMut for PersistentVec<T> {
+	type IterMut<'a>
+		= IterMut<'a, T>
+	where
+		Self: 'a;
+
+	/// Iterates over the values, copying the shared nodes of the tree.
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		match Arc::make_mut(&mut self.root) {
+			Node::Branch(children) => IterMut {
+				branches: vec![(
+					0,
+					self.shift,
+					children.as_mut_slice().iter_mut().enumerate(),
+				)],
+				leaf: None,
+			},
+			Node::Leaf(slots) => IterMut {
+				branches: Vec::new(),
+				leaf: Some((0, slots.as_mut_slice().iter_mut().enumerate())),
+			},
+		}
+	}
+}
+
+impl<T: Clone> StorageAllocate for PersistentVec<T> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = self.end;
+		*self.slot_mut(index) = Some(value);
+		self.len += 1;
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		self.end
+	}
+}
+
+impl<T: Clone> StorageSet for PersistentVec<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T: Clone> StorageInsert for PersistentVec<T> {
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		let previous = self.slot_mut(index).replace(value);
+		if previous.is_none() {
+			self.len += 1
+		}
+
+		previous
+	}
+
+	/// Inserting at some index only allocates the nodes on the path to this
+	/// index.
+	const DENSE: bool = false;
+}
+
+impl<T: Clone> StorageRemove for PersistentVec<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		self.get(index)?;
+		let value = self.slot_mut(index).take();
+		self.len -= 1;
+		self.trim();
+		value
+	}
+}
+
+impl<T: Clone> StorageCompact for PersistentVec<T> {
+	/// Rebuilds the tree with the values packed in index order.
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		if self.end > self.len {
+			for (index, value) in std::mem::take(self).into_iter() {
+				let new_index = self.allocate(value);
+				if new_index != index {
+					moved(index, new_index)
+				}
+			}
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for PersistentVec<T> {
+	/// Serializes the storage as a sequence of `(index, value)` pairs.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		crate::serialization::serialize_indexed(self, serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Clone + serde::Deserialize<'de>> serde::Deserialize<'de> for PersistentVec<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		crate::serialization::deserialize_indexed(deserializer)
+	}
+}
+
+type Branches<I> = Vec<(usize, usize, std::iter::Enumerate<I>)>;
+
+pub struct Iter<'a, T> {
+	/// Base index, shift and remaining children of the branches being
+	/// visited.
+	branches: Branches<std::slice::Iter<'a, Arc<Node<T>>>>,
+	/// Base index and remaining slots of the leaf being visited.
+	leaf: Option<(usize, std::iter::Enumerate<std::slice::Iter<'a, Option<T>>>)>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = (usize, &'a T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some((base, slots)) = &mut self.leaf {
+				let base = *base;
+				let item = slots
+					.by_ref()
+					.find_map(|(i, slot)| slot.as_ref().map(|v| (base + i, v)));
+				if item.is_some() {
+					return item;
+				}
+
+				self.leaf = None
+			}
+
+			let (base, shift, children) = self.branches.last_mut()?;
+			match children.next() {
+				Some((i, child)) => {
+					let base = *base + (i << *shift);
+					let shift = *shift - BITS;
+					match &**child {
+						Node::Branch(children) => self.branches.push((
+							base,
+							shift,
+							children.as_slice().iter().enumerate(),
+						)),
+						Node::Leaf(slots) => {
+							self.leaf = Some((base, slots.as_slice().iter().enumerate()))
+						}
+					}
+				}
+				None => {
+					self.branches.pop();
+				}
+			}
+		}
+	}
+}
+
+pub struct IterMut<'a, T> {
+	/// Base index, shift and remaining children of the branches being
+	/// visited.
+	branches: Branches<std::slice::IterMut<'a, Arc<Node<T>>>>,
+	/// Base index and remaining slots of the leaf being visited.
+	leaf: Option<(
+		usize,
+		std::iter::Enumerate<std::slice::IterMut<'a, Option<T>>>,
+	)>,
+}
+
+impl<'a, T: Clone> Iterator for IterMut<'a, T> {
+	type Item = (usize, &'a mut T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some((base, slots)) = &mut self.leaf {
+				let base = *base;
+				let item = slots
+					.by_ref()
+					.find_map(|(i, slot)| slot.as_mut().map(|v| (base + i, v)));
+				if item.is_some() {
+					return item;
+				}
+
+				self.leaf = None
+			}
+
+			let (base, shift, children) = self.branches.last_mut()?;
+			match children.next() {
+				Some((i, child)) => {
+					let base = *base + (i << *shift);
+					let shift = *shift - BITS;
+					match Arc::make_mut(child) {
+						Node::Branch(children) => self.branches.push((
+							base,
+							shift,
+							children.as_mut_slice().iter_mut().enumerate(),
+						)),
+						Node::Leaf(slots) => {
+							self.leaf = Some((base, slots.as_mut_slice().iter_mut().enumerate()))
+						}
+					}
+				}
+				None => {
+					self.branches.pop();
+				}
+			}
+		}
+	}
+}
+
+pub struct IntoIter<T>(std::vec::IntoIter<(usize, T)>);
+
+impl<T> Iterator for IntoIter<T> {
+	type Item = (usize, T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.0.next()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn clone_is_unaffected_by_modifications() {
+		let mut storage = PersistentVec::new();
+		for i in 0..100 {
+			storage.allocate(i);
+		}
+
+		let version = storage.clone();
+		assert!(storage.ptr_eq(&version));
+
+		storage.set(3, 300).unwrap();
+		*storage.get_mut(40).unwrap() = 4000;
+		storage.remove(50);
+		storage.remove(7);
+		let mut moved = Vec::new();
+		storage.compact(|from, to| moved.push((from, to)));
+
+		assert!(!storage.ptr_eq(&version));
+		assert_eq!(storage.len(), 98);
+		assert_eq!(storage.get(3), Some(&300));
+		assert_eq!(storage.get(39), Some(&4000));
+		assert!(!moved.is_empty());
+
+		assert_eq!(version.len(), 100);
+		for i in 0..100 {
+			assert_eq!(version.get(i), Some(&i));
+		}
+	}
+
+	#[test]
+	fn versions_are_send() {
+		fn assert_send_sync<T: Send + Sync>() {}
+		assert_send_sync::<PersistentVec<String>>()
+	}
+
+	#[test]
+	fn debug() {
+		let mut storage = PersistentVec::new();
+		StorageInsert::insert(&mut storage, 40, 'b');
+		StorageInsert::insert(&mut storage, 2, 'a');
+		assert_eq!(format!("{storage:?}"), "{2: 'a', 40: 'b'}");
+	}
+}
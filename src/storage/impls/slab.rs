@@ -19,9 +19,9 @@ impl<T> Storage for Slab<T> {
 
 impl<T> StorageIter for Slab<T> {
 	type Iter<'a>
+		= slab::Iter<'a, T>
 	where
-		Self: 'a,
-	= slab::Iter<'a, T>;
+		Self: 'a;
 
 	fn iter(&self) -> Self::Iter<'_> {
 		self.iter()
@@ -48,15 +48,25 @@ impl<T> StorageMut for Slab<T> {
 
 impl<T> StorageIterMut for Slab<T> {
 	type IterMut<'a>
+		= slab::IterMut<'a, T>
 	where
-		Self: 'a,
-	= slab::IterMut<'a, T>;
+		Self: 'a;
 
 	fn iter_mut(&mut self) -> Self::IterMut<'_> {
 		self.iter_mut()
 	}
 }
 
+impl<T> StorageAllocate for Slab<T> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		self.insert(value)
+	}
+
+	fn next_index(&self) -> usize {
+		self.vacant_key()
+	}
+}
+
 impl<T> StorageSet for Slab<T> {
 	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
 		if self.contains(index) {
@@ -68,6 +78,15 @@ impl<T> StorageSet for Slab<T> {
 	}
 }
 
+impl<T> StorageCompact for Slab<T> {
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		self.compact(|_, from, to| {
+			moved(from, to);
+			true
+		})
+	}
+}
+
 impl<T> StorageRemove for Slab<T> {
 	fn remove(&mut self, index: usize) -> Option<Self::Value> {
 		if self.contains(index) {
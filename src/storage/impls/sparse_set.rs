@@ -0,0 +1,307 @@
+use crate::storage::*;
+use std::fmt;
+
+/// Marker of a vacant index in the sparse array.
+const VACANT: usize = usize::MAX;
+
+/// Sparse set storage.
+///
+/// Values are packed in a dense array, while a sparse array maps each index
+/// to the position of its value in the dense array.
+/// Insertion and removal are performed in constant time,
+/// and iteration only visits the dense array regardless of the
+/// distribution of the indices.
+///
+/// This storage is well suited for side tables such as `Map<K, SparseSet<T>>`
+/// where only some of the keys are associated to a value.
+/// Removing a value moves the last value of the dense array in its place,
+/// so the iteration order is unspecified.
+#[derive(Clone)]
+pub struct SparseSet<T> {
+	sparse: Vec<usize>,
+	dense: Vec<T>,
+	indices: Vec<usize>,
+}
+
+impl<T> Default for SparseSet<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for SparseSet<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<T> SparseSet<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			sparse: Vec::new(),
+			dense: Vec::new(),
+			indices: Vec::new(),
+		}
+	}
+
+	/// Creates a new empty storage able to hold `capacity` values without
+	/// reallocating its dense array.
+	pub fn with_capacity(capacity: usize) -> Self {
+		Self {
+			sparse: Vec::new(),
+			dense: Vec::with_capacity(capacity),
+			indices: Vec::with_capacity(capacity),
+		}
+	}
+
+	/// Returns the packed values of the storage.
+	pub fn as_slice(&self) -> &[T] {
+		&self.dense
+	}
+
+	/// Returns the packed values of the storage, mutably.
+	pub fn as_mut_slice(&mut self) -> &mut [T] {
+		&mut self.dense
+	}
+
+	/// Returns the indices of the packed values, in the same order as
+	/// [`as_slice`](Self::as_slice).
+	pub fn indices(&self) -> &[usize] {
+		&self.indices
+	}
+
+	fn position(&self, index: usize) -> Option<usize> {
+		match self.sparse.as_slice().get(index) {
+			Some(&VACANT) | None => None,
+			Some(&position) => Some(position),
+		}
+	}
+}
+
+impl<T> Storage for SparseSet<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.position(index).map(|position| &self.dense[position])
+	}
+
+	fn capacity(&self) -> usize {
+		self.dense.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.dense.len()
+	}
+}
+
+impl<T> StorageIter for SparseSet<T> {
+	type Iter<'a>
+		= std::iter::Zip<std::iter::Copied<std::slice::Iter<'a, usize>>, std::slice::Iter<'a, T>>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.indices
+			.as_slice()
+			.iter()
+			.copied()
+			.zip(self.dense.as_slice().iter())
+	}
+}
+
+impl<T> StorageIntoIter for SparseSet<T> {
+	type IntoIter = std::iter::Zip<std::vec::IntoIter<usize>, std::vec::IntoIter<T>>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		std::iter::IntoIterator::into_iter(self.indices)
+			.zip(std::iter::IntoIterator::into_iter(self.dense))
+	}
+}
+
+impl<T> StorageMut for SparseSet<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		self.position(index)
+			.map(move |position| &mut self.dense[position])
+	}
+
+	fn clear(&mut self) {
+		self.sparse.clear();
+		self.dense.clear();
+		self.indices.clear()
+	}
+}
+
+impl<T> StorageIterMut for SparseSet<T> {
+	type IterMut<'a>
+		= std::iter::Zip<std::iter::Copied<std::slice::Iter<'a, usize>>, std::slice::IterMut<'a, T>>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		self.indices
+			.as_slice()
+			.iter()
+			.copied()
+			.zip(self.dense.as_mut_slice().iter_mut())
+	}
+}
+
+impl<T> StorageSet for SparseSet<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T> StorageInsert for SparseSet<T> {
+	/// Inserts the given value at the given index.
+	///
+	/// # Panics
+	///
+	/// Panics if `index` is `usize::MAX`.
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		assert!(index != VACANT, "sparse set index overflow");
+		match self.set(index, value) {
+			Ok(previous) => Some(previous),
+			Err(value) => {
+				if index >= self.sparse.len() {
+					self.sparse.resize(index + 1, VACANT)
+				}
+
+				self.sparse[index] = self.dense.len();
+				self.dense.push(value);
+				self.indices.push(index);
+				None
+			}
+		}
+	}
+}
+
+impl<T> StorageCompact for SparseSet<T> {
+	/// Changes the indices of the stored values so that they occupy the
+	/// indices `0..len()`.
+	///
+	/// The values are not moved in the dense array.
+	fn compact(&mut self, mut moved: impl FnMut(usize, usize)) {
+		let len = self.dense.len();
+		let mut hole = 0;
+
+		for position in 0..len {
+			let from = self.indices[position];
+			if from >= len {
+				while self.sparse[hole] != VACANT {
+					hole += 1
+				}
+
+				self.sparse[hole] = position;
+				self.indices[position] = hole;
+				moved(from, hole)
+			}
+		}
+
+		self.sparse.truncate(len)
+	}
+}
+
+impl<T> StorageRemove for SparseSet<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		let position = self.position(index)?;
+		self.sparse[index] = VACANT;
+		self.indices.swap_remove(position);
+		let value = self.dense.swap_remove(position);
+
+		if let Some(&moved) = self.indices.as_slice().get(position) {
+			self.sparse[moved] = position
+		}
+
+		Some(value)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for SparseSet<T> {
+	/// Serializes the storage as a sequence of `(index, value)` pairs.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		crate::serialization::serialize_indexed(self, serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for SparseSet<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		crate::serialization::deserialize_indexed(deserializer)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn remove_moves_last_value() {
+		let mut storage = SparseSet::new();
+		for i in [3, 7, 1, 9, 4] {
+			StorageInsert::insert(&mut storage, i, i * 10);
+		}
+
+		assert_eq!(storage.remove(1), Some(10));
+		assert_eq!(storage.remove(1), None);
+		assert_eq!(storage.len(), 4);
+		assert_eq!(storage.get(4), Some(&40));
+		assert_eq!(storage.get_mut(4), Some(&mut 40));
+		assert_eq!(storage.get(1), None);
+		assert_eq!(storage.indices(), [3, 7, 4, 9]);
+		assert_eq!(storage.as_slice(), [30, 70, 40, 90]);
+
+		assert_eq!(storage.remove(9), Some(90));
+		assert_eq!(storage.indices(), [3, 7, 4]);
+		assert_eq!(storage.get(4), Some(&40));
+	}
+
+	#[test]
+	fn iteration_order_after_removals() {
+		let mut storage = SparseSet::new();
+		for i in 0..6 {
+			StorageInsert::insert(&mut storage, i, i);
+		}
+
+		storage.remove(0);
+		storage.remove(3);
+		StorageInsert::insert(&mut storage, 8, 8);
+
+		let entries: Vec<_> = storage.iter().map(|(i, v)| (i, *v)).collect();
+		assert_eq!(entries, [(5, 5), (1, 1), (2, 2), (4, 4), (8, 8)]);
+		assert!(storage.iter().all(|(i, v)| storage.get(i) == Some(v)));
+		assert!(storage.iter_mut().map(|(i, v)| (i, *v)).eq(entries.clone()));
+		assert!(StorageIntoIter::into_iter(storage).eq(entries));
+	}
+
+	#[test]
+	fn compact() {
+		let mut storage = SparseSet::new();
+		for i in [0, 2, 5, 6, 9] {
+			StorageInsert::insert(&mut storage, i, i);
+		}
+
+		let mut moves = Vec::new();
+		storage.compact(|from, to| moves.push((from, to)));
+		assert_eq!(moves, [(5, 1), (6, 3), (9, 4)]);
+		assert_eq!(storage.len(), 5);
+		for (from, to) in [(0, 0), (2, 2), (5, 1), (6, 3), (9, 4)] {
+			assert_eq!(storage.get(to), Some(&from));
+		}
+
+		assert_eq!(storage.get(5), None);
+		assert_eq!(storage.get(9), None);
+		assert_eq!(storage.remove(1), Some(5));
+		assert_eq!(storage.get(4), Some(&9));
+		StorageInsert::insert(&mut storage, 7, 7);
+		assert_eq!(storage.get(7), Some(&7));
+	}
+}
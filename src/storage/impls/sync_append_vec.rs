@@ -0,0 +1,304 @@
+use super::chunks::{self, Chunks, Shared};
+use crate::storage::*;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Thread-safe append-only storage with stable addresses.
+///
+/// This is the `Sync` counterpart of [`AppendVec`](super::AppendVec):
+/// values are stored in chunks of increasing size that are never reallocated,
+/// and new values can be allocated concurrently from multiple threads
+/// through [`StorageAllocateConst`] and [`StorageInsertConst`] without locking,
+/// while other threads read the values already stored.
+///
+/// Values can only be replaced or removed through a mutable reference to the
+/// storage.
+pub struct SyncAppendVec<T> {
+	chunks: Chunks<T, Shared>,
+	len: AtomicUsize,
+	end: AtomicUsize,
+}
+
+impl<T> Default for SyncAppendVec<T> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl<T: Clone> Clone for SyncAppendVec<T> {
+	fn clone(&self) -> Self {
+		Self {
+			chunks: self.chunks.clone(),
+			len: AtomicUsize::new(self.len.load(Ordering::Relaxed)),
+			end: AtomicUsize::new(self.end.load(Ordering::Acquire)),
+		}
+	}
+}
+
+impl<T: fmt::Debug> fmt::Debug for SyncAppendVec<T> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_map().entries(self.iter()).finish()
+	}
+}
+
+impl<T> SyncAppendVec<T> {
+	/// Creates a new empty storage.
+	pub fn new() -> Self {
+		Self {
+			chunks: Chunks::default(),
+			len: AtomicUsize::new(0),
+			end: AtomicUsize::new(0),
+		}
+	}
+
+	/// Stores the given value at the given index if it is vacant.
+	///
+	/// Returns `Err(value)` if the index is already occupied.
+	fn init(&self, index: usize, value: T) -> Result<(), T> {
+		self.chunks.allocated_slot(index).set(value)?;
+		self.len.fetch_add(1, Ordering::Relaxed);
+		self.end.fetch_max(index + 1, Ordering::AcqRel);
+		Ok(())
+	}
+}
+
+impl<T> Storage for SyncAppendVec<T> {
+	type Value = T;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.chunks.get(index)
+	}
+
+	fn capacity(&self) -> usize {
+		self.chunks.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.len.load(Ordering::Relaxed)
+	}
+}
+
+impl<T> StorageIter for SyncAppendVec<T> {
+	type Iter<'a>
+		= Iter<'a, T>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.chunks.iter(self.end.load(Ordering::Acquire))
+	}
+}
+
+impl<T> StorageIntoIter for SyncAppendVec<T> {
+	type IntoIter = IntoIter<T>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.chunks.into_iter()
+	}
+}
+
+impl<T> StorageMut for SyncAppendVec<T> {
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		self.chunks.get_mut(index)
+	}
+
+	fn clear(&mut self) {
+		self.chunks.clear();
+		*self.len.get_mut() = 0;
+		*self.end.get_mut() = 0
+	}
+}
+
+impl<T> StorageIterMut for SyncAppendVec<T> {
+	type IterMut<'a>
+		= IterMut<'a, T>
+	where
+		Self: 'a;
+
+	fn iter_mut(&mut self) -> Self::IterMut<'_> {
+		self.chunks.iter_mut()
+	}
+}
+
+impl<T> StorageAllocate for SyncAppendVec<T> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		self.allocate_const(value)
+	}
+
+	/// Returns the index allocated by the next call to `allocate`.
+	///
+	/// Indices taken by `insert_const` are skipped, as `allocate` does.
+	fn next_index(&self) -> usize {
+		let end = self.end.load(Ordering::Acquire);
+		(end..).find(|index| self.get(*index).is_none()).unwrap()
+	}
+}
+
+impl<T> StorageAllocateConst for SyncAppendVec<T> {
+	fn allocate_const(&self, mut value: Self::Value) -> usize {
+		loop {
+			// The index may have been concurrently taken by `insert_const`.
+			let index = self.end.fetch_add(1, Ordering::AcqRel);
+			match self.chunks.allocated_slot(index).set(value) {
+				Ok(()) => {
+					self.len.fetch_add(1, Ordering::Relaxed);
+					break index;
+				}
+				Err(v) => value = v,
+			}
+		}
+	}
+
+	/// Allocates an index not greater than `max` for the given value.
+	///
+	/// The end of the storage is only moved past an index once this index is
+	/// known not to be greater than `max`, so that concurrent allocations
+	/// never exceed the limit.
+	fn try_allocate_const(&self, mut value: Self::Value, max: usize) -> Result<usize, Self::Value> {
+		let mut index = self.end.load(Ordering::Acquire);
+		loop {
+			if index > max {
+				break Err(value);
+			}
+
+			match self.end.compare_exchange_weak(
+				index,
+				index + 1,
+				Ordering::AcqRel,
+				Ordering::Acquire,
+			) {
+				Ok(_) => match self.chunks.allocated_slot(index).set(value) {
+					Ok(()) => {
+						self.len.fetch_add(1, Ordering::Relaxed);
+						break Ok(index);
+					}
+					// The index has been concurrently taken by `insert_const`.
+					Err(v) => {
+						value = v;
+						index = self.end.load(Ordering::Acquire)
+					}
+				},
+				Err(end) => index = end,
+			}
+		}
+	}
+}
+
+impl<T> StorageSet for SyncAppendVec<T> {
+	fn set(&mut self, index: usize, mut value: Self::Value) -> Result<Self::Value, Self::Value> {
+		match self.get_mut(index) {
+			Some(current) => {
+				std::mem::swap(current, &mut value);
+				Ok(value)
+			}
+			None => Err(value),
+		}
+	}
+}
+
+impl<T> StorageInsert for SyncAppendVec<T> {
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		match self.set(index, value) {
+			Ok(previous) => Some(previous),
+			Err(value) => {
+				if self.init(index, value).is_err() {
+					unreachable!()
+				}
+				None
+			}
+		}
+	}
+}
+
+impl<T> StorageInsertConst for SyncAppendVec<T> {
+	/// Inserts the given value at the given index using interior mutability only.
+	///
+	/// Since stored values can be borrowed through a shared reference to the
+	/// storage, an occupied index is never overwritten:
+	/// if the index is already occupied, the storage is left unchanged and
+	/// the given `value` is returned instead.
+	fn insert_const(&self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		self.init(index, value).err()
+	}
+}
+
+impl<T> StorageCompact for SyncAppendVec<T> {
+	fn compact(&mut self, moved: impl FnMut(usize, usize)) {
+		let len = self.len();
+		self.chunks.compact(len, *self.end.get_mut(), moved);
+		*self.end.get_mut() = len
+	}
+}
+
+impl<T> StorageRemove for SyncAppendVec<T> {
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		let value = self.chunks.take(index)?;
+		*self.len.get_mut() -= 1;
+		Some(value)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for SyncAppendVec<T> {
+	/// Serializes the storage as a sequence of `(index, value)` pairs.
+	fn serialize<E: serde::Serializer>(&self, serializer: E) -> Result<E::Ok, E::Error> {
+		crate::serialization::serialize_indexed(self, serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for SyncAppendVec<T> {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		crate::serialization::deserialize_indexed(deserializer)
+	}
+}
+
+pub type Iter<'a, T> = chunks::Iter<'a, T, Shared>;
+
+pub type IterMut<'a, T> = chunks::IterMut<'a, T, Shared>;
+
+pub type IntoIter<T> = chunks::IntoIter<T, Shared>;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn clone_and_debug() {
+		let mut storage = SyncAppendVec::new();
+		for i in 0..40 {
+			storage.allocate_const(i);
+		}
+
+		storage.remove(3);
+		let clone = storage.clone();
+		assert_eq!(clone.len(), 39);
+		assert_eq!(clone.next_index(), 40);
+		assert!(clone.iter().eq(storage.iter()));
+		assert_eq!(format!("{clone:?}"), format!("{storage:?}"));
+		assert!(format!("{clone:?}").starts_with("{0: 0, 1: 1, 2: 2, 4: 4,"));
+	}
+
+	#[test]
+	fn compact() {
+		let mut storage = SyncAppendVec::new();
+		for i in 0..40 {
+			storage.allocate_const(i);
+		}
+
+		for i in [0, 5, 36] {
+			storage.remove(i);
+		}
+
+		let mut moves = Vec::new();
+		storage.compact(|from, to| moves.push((from, to)));
+		assert_eq!(moves, [(39, 0), (38, 5), (37, 36)]);
+		assert_eq!(storage.next_index(), 37);
+		let mut expected: Vec<_> = (0..36).chain([37]).collect();
+		expected[0] = 39;
+		expected[5] = 38;
+		assert!(StorageIntoIter::into_iter(storage)
+			.map(|(_, value)| value)
+			.eq(expected));
+	}
+}
@@ -0,0 +1,418 @@
+use crate::storage::*;
+use derivative::Derivative;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Storage adapter recording the modifications of the underlying storage so
+/// that they can be undone.
+///
+/// Snapshots are only available on shelves of an `UndoLog` storage: to undo
+/// the modifications of a `Shelf<Vec<T>>`, use a `Shelf<UndoLog<Vec<T>>>`
+/// instead. The same goes for the dictionaries built on such a shelf.
+///
+/// ```
+/// use shelves::{Shelf, UndoLog};
+///
+/// let mut shelf: Shelf<UndoLog<Vec<&str>>> = Shelf::default();
+/// let a = shelf.insert("a");
+///
+/// let snapshot = shelf.snapshot();
+/// shelf.insert("b");
+/// *shelf.get_mut::<&str>(a).unwrap() = "c";
+/// shelf.rollback_to(snapshot);
+///
+/// assert_eq!(shelf.len(), 1);
+/// assert_eq!(shelf[a], "a");
+/// ```
+///
+/// Modifications are only recorded while a [`Snapshot`] is open.
+/// A snapshot is then either rolled back, undoing every modification since
+/// it was taken, or committed, keeping them.
+/// Snapshots can be nested, but must be rolled back or committed in the
+/// reverse order of their creation.
+///
+/// Recording the modification of a value requires cloning it, except for
+/// allocations and the whole storage cleared at once.
+/// Values modified through [`StorageMut::get_mut`] are recorded as well, but
+/// only the first time in a snapshot, and mutable iteration is not supported
+/// since it cannot be recorded.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "S: Clone, S::Value: Clone"))]
+pub struct UndoLog<S: Storage> {
+	storage: S,
+	log: Vec<Undo<S>>,
+
+	/// Length of the log when each open snapshot was taken.
+	snapshots: Vec<usize>,
+
+	/// Position of the last recorded modification of each index in the log.
+	recorded: HashMap<usize, usize>,
+}
+
+/// Recorded modification.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "S: Clone, S::Value: Clone"))]
+enum Undo<S: Storage> {
+	/// The value at `index` was `value`, and can be restored using `restore`.
+	Restore {
+		index: usize,
+		value: Option<S::Value>,
+		restore: fn(&mut S, usize, Option<S::Value>),
+	},
+
+	/// The storage was cleared, and was previously the given storage.
+	Clear(S),
+}
+
+/// Snapshot of an [`UndoLog`] storage.
+#[must_use = "snapshots must be rolled back or committed"]
+pub struct Snapshot {
+	log_len: usize,
+	depth: usize,
+}
+
+impl<S: Storage + Default> Default for UndoLog<S> {
+	fn default() -> Self {
+		Self::new(S::default())
+	}
+}
+
+impl<S: Storage + fmt::Debug> fmt::Debug for UndoLog<S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_struct("UndoLog")
+			.field("storage", &self.storage)
+			.field("snapshots", &self.snapshots.len())
+			.finish()
+	}
+}
+
+impl<S: Storage> UndoLog<S> {
+	pub fn new(storage: S) -> Self {
+		Self {
+			storage,
+			log: Vec::new(),
+			snapshots: Vec::new(),
+			recorded: HashMap::new(),
+		}
+	}
+
+	pub fn into_storage(self) -> S {
+		self.storage
+	}
+
+	pub fn as_storage(&self) -> &S {
+		&self.storage
+	}
+
+	/// Checks if a snapshot is open.
+	pub fn in_snapshot(&self) -> bool {
+		!self.snapshots.is_empty()
+	}
+
+	/// Starts recording the modifications of the storage.
+	pub fn snapshot(&mut self) -> Snapshot {
+		self.snapshots.push(self.log.len());
+		Snapshot {
+			log_len: self.log.len(),
+			depth: self.snapshots.len(),
+		}
+	}
+
+	/// Keeps the modifications made since the given snapshot was taken.
+	///
+	/// They can still be undone by rolling back an enclosing snapshot.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn commit(&mut self, snapshot: Snapshot) {
+		self.close(&snapshot);
+		if !self.in_snapshot() {
+			self.log.clear();
+			self.recorded.clear()
+		}
+	}
+
+	/// Undoes the modifications made since the given snapshot was taken.
+	///
+	/// # Panics
+	///
+	/// Panics if a snapshot taken after this one is still open.
+	pub fn rollback_to(&mut self, snapshot: Snapshot) {
+		self.close(&snapshot);
+		while self.log.len() > snapshot.log_len {
+			match self.log.pop().unwrap() {
+				Undo::Restore {
+					index,
+					value,
+					restore,
+				} => restore(&mut self.storage, index, value),
+				Undo::Clear(storage) => self.storage = storage,
+			}
+		}
+
+		self.recorded
+			.retain(|_, position| *position < snapshot.log_len)
+	}
+
+	/// Returns the indices of the values modified since the given snapshot
+	/// was taken, in index order.
+	pub fn modified_since(&self, snapshot: &Snapshot) -> Vec<usize>
+	where
+		S: StorageIter,
+	{
+		let mut indices = Vec::new();
+		for undo in &self.log[snapshot.log_len..] {
+			match undo {
+				Undo::Restore { index, .. } => indices.push(*index),
+				Undo::Clear(storage) => indices.extend(storage.iter().map(|(i, _)| i)),
+			}
+		}
+
+		indices.sort_unstable();
+		indices.dedup();
+		indices
+	}
+
+	fn close(&mut self, snapshot: &Snapshot) {
+		assert_eq!(
+			snapshot.depth,
+			self.snapshots.len(),
+			"snapshot closed out of order"
+		);
+		self.snapshots.pop();
+	}
+
+	/// Checks if a modification of the value at `index` has been recorded
+	/// since the innermost open snapshot was taken.
+	fn is_recorded(&self, index: usize) -> bool {
+		match (self.snapshots.last(), self.recorded.get(&index)) {
+			(Some(start), Some(position)) => position >= start,
+			_ => false,
+		}
+	}
+
+	/// Records a modification of the value at `index`, if a snapshot is open.
+	fn record(
+		&mut self,
+		index: usize,
+		value: impl FnOnce(&S) -> Option<S::Value>,
+		restore: fn(&mut S, usize, Option<S::Value>),
+	) {
+		if self.in_snapshot() {
+			let value = value(&self.storage);
+			self.recorded.insert(index, self.log.len());
+			self.log.push(Undo::Restore {
+				index,
+				value,
+				restore,
+			})
+		}
+	}
+}
+
+impl<S: Storage> Storage for UndoLog<S> {
+	type Value = S::Value;
+
+	fn get(&self, index: usize) -> Option<&Self::Value> {
+		self.storage.get(index)
+	}
+
+	fn capacity(&self) -> usize {
+		self.storage.capacity()
+	}
+
+	fn len(&self) -> usize {
+		self.storage.len()
+	}
+}
+
+impl<S: StorageIter> StorageIter for UndoLog<S> {
+	type Iter<'a>
+		= S::Iter<'a>
+	where
+		Self: 'a;
+
+	fn iter(&self) -> Self::Iter<'_> {
+		self.storage.iter()
+	}
+}
+
+impl<S: StorageIntoIter> StorageIntoIter for UndoLog<S> {
+	type IntoIter = S::IntoIter;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.storage.into_iter()
+	}
+}
+
+impl<S: StorageMut + StorageSet + Default> StorageMut for UndoLog<S>
+where
+	S::Value: Clone,
+{
+	/// Returns a mutable reference to the value at the given index.
+	///
+	/// The value is cloned to be restored on rollback, unless it has already
+	/// been recorded since the innermost open snapshot was taken.
+	fn get_mut(&mut self, index: usize) -> Option<&mut Self::Value> {
+		if self.storage.get(index).is_some() && !self.is_recorded(index) {
+			self.record(
+				index,
+				|s| s.get(index).cloned(),
+				|s, i, v| {
+					s.set(i, v.unwrap()).ok();
+				},
+			)
+		}
+
+		self.storage.get_mut(index)
+	}
+
+	fn clear(&mut self) {
+		if self.in_snapshot() {
+			let storage = std::mem::take(&mut self.storage);
+			self.log.push(Undo::Clear(storage))
+		} else {
+			self.storage.clear()
+		}
+	}
+}
+
+impl<S: StorageDeallocate> StorageAllocate for UndoLog<S> {
+	fn allocate(&mut self, value: Self::Value) -> usize {
+		let index = self.storage.allocate(value);
+		self.record(
+			index,
+			|_| None,
+			|s, i, _| {
+				s.deallocate(i);
+			},
+		);
+		index
+	}
+
+	fn next_index(&self) -> usize {
+		self.storage.next_index()
+	}
+}
+
+impl<S: StorageSet> StorageSet for UndoLog<S>
+where
+	S::Value: Clone,
+{
+	fn set(&mut self, index: usize, value: Self::Value) -> Result<Self::Value, Self::Value> {
+		let old = self.storage.set(index, value)?;
+		self.record(
+			index,
+			|_| Some(old.clone()),
+			|s, i, v| {
+				s.set(i, v.unwrap()).ok();
+			},
+		);
+		Ok(old)
+	}
+}
+
+impl<S: StorageInsert + StorageRemove> StorageInsert for UndoLog<S>
+where
+	S::Value: Clone,
+{
+	fn insert(&mut self, index: usize, value: Self::Value) -> Option<Self::Value> {
+		let previous = self.storage.insert(index, value);
+		self.record(
+			index,
+			|_| previous.clone(),
+			|s, i, v| match v {
+				Some(v) => {
+					s.insert(i, v);
+				}
+				None => {
+					s.remove(i);
+				}
+			},
+		);
+		previous
+	}
+
+	const DENSE: bool = S::DENSE;
+}
+
+impl<S: StorageInsert + StorageRemove> StorageRemove for UndoLog<S>
+where
+	S::Value: Clone,
+{
+	fn remove(&mut self, index: usize) -> Option<Self::Value> {
+		let value = self.storage.remove(index)?;
+		self.record(
+			index,
+			|_| Some(value.clone()),
+			|s, i, v| {
+				s.insert(i, v.unwrap());
+			},
+		);
+		Some(value)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{FreeList, Ref, Shelf, UndoLog};
+
+	#[test]
+	fn nested_rollback_vec() {
+		let mut shelf: Shelf<UndoLog<Vec<String>>> = Shelf::default();
+		let a = shelf.insert("a".to_string());
+		let b = shelf.insert("b".to_string());
+
+		let outer = shelf.snapshot();
+		let c = shelf.insert("c".to_string());
+		shelf.set(a, "a1".to_string()).unwrap();
+		shelf.get_mut::<String>(b).unwrap().push('1');
+
+		let inner = shelf.snapshot();
+		shelf.insert("d".to_string());
+		shelf.set(a, "a2".to_string()).unwrap();
+		shelf.get_mut::<String>(b).unwrap().push('2');
+		shelf.get_mut::<String>(c).unwrap().push('2');
+		assert_eq!(shelf.modified_since(&inner).len(), 4);
+
+		shelf.rollback_to(inner);
+		assert_eq!(shelf.len(), 3);
+		assert_eq!(shelf[a], "a1");
+		assert_eq!(shelf[b], "b1");
+		assert_eq!(shelf[c], "c");
+
+		shelf.rollback_to(outer);
+		assert_eq!(shelf.len(), 2);
+		assert_eq!(shelf[a], "a");
+		assert_eq!(shelf[b], "b");
+		assert!(!shelf.as_storage().in_snapshot());
+	}
+
+	#[test]
+	fn nested_rollback_free_list() {
+		let mut shelf: Shelf<UndoLog<FreeList<String>>> = Shelf::default();
+		let refs: Vec<Ref<String>> = (0..4).map(|i| shelf.insert(i.to_string())).collect();
+		let expected = shelf.clone();
+
+		let outer = shelf.snapshot();
+		shelf.remove(refs[0]);
+		let e = shelf.insert("e".to_string());
+		assert_eq!(e, refs[0]);
+		shelf.set(refs[1], "1a".to_string()).unwrap();
+
+		let inner = shelf.snapshot();
+		shelf.remove(refs[1]);
+		shelf.remove(e);
+		shelf.insert("f".to_string());
+		shelf.get_mut::<String>(refs[2]).unwrap().push('b');
+		shelf.rollback_to(inner);
+		assert_eq!(shelf.len(), 4);
+		assert_eq!(shelf[refs[1]], "1a");
+		assert_eq!(shelf[refs[2]], "2");
+		assert_eq!(shelf[e], "e");
+
+		shelf.rollback_to(outer);
+		assert_eq!(shelf, expected);
+	}
+}
@@ -60,6 +60,26 @@ impl<T> StorageAllocate for Vec<T> {
 		self.push(value);
 		index
 	}
+
+	fn next_index(&self) -> usize {
+		self.len()
+	}
+}
+
+impl<T> StorageDeallocate for Vec<T> {
+	/// Pops the last value of the vector if it is at the given index.
+	fn deallocate(&mut self, index: usize) -> Option<Self::Value> {
+		if index + 1 == self.len() {
+			self.pop()
+		} else {
+			None
+		}
+	}
+}
+
+impl<T> StorageCompact for Vec<T> {
+	/// Does nothing, since values in a vector always occupy the indices `0..len()`.
+	fn compact(&mut self, _moved: impl FnMut(usize, usize)) {}
 }
 
 impl<T> StorageSet for Vec<T> {
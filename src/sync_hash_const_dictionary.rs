@@ -0,0 +1,772 @@
+use crate::dictionary::{equivalent, RefTable, ValueHasher};
+use crate::shelf::{IntoIter, Iter};
+use crate::{
+	Collision, DefaultEquivalence, DictionaryReport, Equivalence, InsertError, Ref, RefIndex,
+	Remap, Shelf, Storage, StorageAllocateConst, StorageCompact, StorageIntoIter, StorageIter,
+	StorageMut, StorageRemove,
+};
+use std::borrow::{Borrow, BorrowMut};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::{PoisonError, RwLock};
+
+type Shard<T, I> = RwLock<RefTable<T, I>>;
+
+/// Thread-safe bidirectional mapping between `T` and `Ref<T, I>`.
+///
+/// This is the `Sync` counterpart of
+/// [`HashConstDictionary`](crate::HashConstDictionary).
+/// Each value is stored only once, in the shelf of the dictionary.
+/// The hash table of references is split into shards, each protected by its
+/// own lock, so that values can be inserted concurrently from multiple
+/// threads.
+/// Equal values are always inserted in the same shard, guaranteeing that they
+/// are given the same reference.
+/// Values are hashed before locking a shard, and compared once it is
+/// unlocked, so that the hash function and equivalence relation may
+/// themselves look up or insert values.
+///
+/// The underlying storage must support concurrent allocations,
+/// such as [`SyncAppendVec`](crate::SyncAppendVec).
+pub struct SyncHashConstDictionary<
+	S: Storage,
+	H = RandomState,
+	E = DefaultEquivalence,
+	I: RefIndex = usize,
+> {
+	shards: Box<[Shard<S::Value, I>]>,
+	hasher: ValueHasher<H, E>,
+	values: Shelf<S, I>,
+}
+
+/// Returns the default number of shards, based on the available parallelism.
+fn default_shard_count() -> usize {
+	let parallelism = std::thread::available_parallelism().map_or(1, usize::from);
+	(parallelism * 4).next_power_of_two()
+}
+
+impl<S: Storage + Default, H: Default, E: Default, I: RefIndex> Default
+	for SyncHashConstDictionary<S, H, E, I>
+{
+	fn default() -> Self {
+		Self::with_equivalence(S::default(), H::default(), E::default())
+	}
+}
+
+impl<S: Storage> SyncHashConstDictionary<S> {
+	/// Creates a new empty dictionary.
+	pub fn new(storage: S) -> Self {
+		Self::with_shards(storage, default_shard_count())
+	}
+
+	/// Creates a new empty dictionary with the given number of shards.
+	///
+	/// # Panics
+	///
+	/// Panics if `shards` is zero.
+	pub fn with_shards(storage: S, shards: usize) -> Self {
+		Self::with_shards_and_equivalence(storage, shards, RandomState::new(), DefaultEquivalence)
+	}
+}
+
+impl<S: Storage, H, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values.
+	pub fn with_hasher(storage: S, hasher: H) -> Self
+	where
+		E: Default,
+	{
+		Self::with_equivalence(storage, hasher, E::default())
+	}
+
+	/// Creates a new empty dictionary using the given hash builder to hash
+	/// the values, and the given equivalence relation to compare them.
+	pub fn with_equivalence(storage: S, hasher: H, equivalence: E) -> Self {
+		Self::with_shards_and_equivalence(storage, default_shard_count(), hasher, equivalence)
+	}
+
+	/// Creates a new empty dictionary with the given number of shards, using
+	/// the given hash builder to hash the values, and the given equivalence
+	/// relation to compare them.
+	///
+	/// # Panics
+	///
+	/// Panics if `shards` is zero.
+	pub fn with_shards_and_equivalence(
+		storage: S,
+		shards: usize,
+		hasher: H,
+		equivalence: E,
+	) -> Self {
+		assert!(shards > 0, "a dictionary needs at least one shard");
+		Self {
+			shards: (0..shards).map(|_| RwLock::default()).collect(),
+			hasher: ValueHasher::new(hasher, equivalence),
+			values: Shelf::new(storage),
+		}
+	}
+
+	/// Returns the hash builder of the dictionary.
+	pub fn hasher(&self) -> &H {
+		&self.hasher.builder
+	}
+
+	/// Returns the equivalence relation of the dictionary.
+	pub fn equivalence(&self) -> &E {
+		&self.hasher.equivalence
+	}
+
+	pub fn len(&self) -> usize {
+		self.shards
+			.iter()
+			.map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner).len())
+			.sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn into_shelf(self) -> Shelf<S, I> {
+		self.values
+	}
+
+	pub fn as_shelf(&self) -> &Shelf<S, I> {
+		&self.values
+	}
+
+	pub fn into_storage(self) -> S {
+		self.values.into_storage()
+	}
+
+	pub fn as_storage(&self) -> &S {
+		self.values.as_storage()
+	}
+
+	/// Borrows the definition (`T`) associated to the given term (`Ref<T, I>`).
+	pub fn borrow<T>(&self, r: Ref<T, I>) -> Option<&T>
+	where
+		S::Value: Borrow<T>,
+	{
+		self.values.borrow(r)
+	}
+
+	/// Returns a reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	pub fn get<T>(&self, r: Ref<T, I>) -> Option<&S::Value>
+	where
+		S::Value: Borrow<T>,
+	{
+		self.values.get(r)
+	}
+
+	/// Returns the reference (`Ref<T, I>`) associated to the given definition, if any.
+	///
+	/// The definition may be any borrowed form of the stored value type,
+	/// which allows querying the dictionary without inserting or even
+	/// allocating the value.
+	pub fn get_ref<Q>(&self, value: &Q) -> Option<Ref<S::Value, I>>
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		let hash = self.hasher.hash_one(value);
+		self.search(hash, value).ok()
+	}
+
+	/// Searches the value with the given hash, comparing the candidate
+	/// values once its shard is unlocked.
+	///
+	/// Returns the reference of the value if found, or the version of the
+	/// shard it is missing from otherwise.
+	fn search<Q>(&self, hash: u64, value: &Q) -> Result<Ref<S::Value, I>, usize>
+	where
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		let (first, others, version) = {
+			let table = self.shards[self.shard_index(hash)]
+				.read()
+				.unwrap_or_else(PoisonError::into_inner);
+			let mut candidates = table.candidates(hash);
+			let first = candidates.next();
+			(first, candidates.collect::<Vec<_>>(), table.version())
+		};
+
+		first
+			.into_iter()
+			.chain(others)
+			.find(|r| equivalent(&self.values, &self.hasher, *r, value))
+			.ok_or(version)
+	}
+
+	/// Checks if the given definition is registered in the dictionary.
+	pub fn contains<Q>(&self, value: &Q) -> bool
+	where
+		H: BuildHasher,
+		S::Value: Borrow<Q>,
+		E: Equivalence<Q>,
+		Q: ?Sized,
+	{
+		self.get_ref(value).is_some()
+	}
+
+	/// Returns the index of the shard in which a value with the given hash is
+	/// stored.
+	fn shard_index(&self, hash: u64) -> usize {
+		shard_index(hash, self.shards.len())
+	}
+
+	/// Returns a mutable reference to the shard in which a value with the
+	/// given hash is stored.
+	fn shard_mut(&mut self, hash: u64) -> &mut RefTable<S::Value, I> {
+		let index = self.shard_index(hash);
+		self.shards[index]
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+/// Returns the index of the shard in which a value with the given hash is
+/// stored, among `len` shards.
+///
+/// The shard is selected using the middle bits of the hash, since its low
+/// bits are used to select a bucket in the table of the shard.
+fn shard_index(hash: u64, len: usize) -> usize {
+	(hash >> 32) as usize % len
+}
+
+impl<S: StorageIter, H, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	/// Returns an iterator over the terms (`Ref<T, I>`) and definitions (`S::Value`)
+	/// of the dictionary, in storage order.
+	pub fn iter(&self) -> Iter<'_, S, I> {
+		self.values.iter()
+	}
+
+	/// Checks that the value-to-reference index of the dictionary is
+	/// consistent with its shelf.
+	pub fn validate(&self) -> DictionaryReport<S::Value, I>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		let refs: Vec<_> = self
+			.shards
+			.iter()
+			.flat_map(|shard| {
+				let table = shard.read().unwrap_or_else(PoisonError::into_inner);
+				table.iter().collect::<Vec<_>>()
+			})
+			.collect();
+
+		DictionaryReport::new(
+			&self.values,
+			refs,
+			|value| self.get_ref(value),
+			|a, b| self.hasher.equivalence.equivalent(a, b),
+		)
+	}
+
+	/// Rebuilds the value-to-reference index of the dictionary from its
+	/// shelf.
+	///
+	/// Only the first of several equal values, in storage order, is
+	/// registered. The others stay in the shelf, and are returned as
+	/// collisions.
+	pub fn rebuild_map(&mut self) -> Vec<Collision<S::Value, I>>
+	where
+		H: BuildHasher,
+		E: Equivalence<S::Value>,
+	{
+		for shard in self.shards.iter_mut() {
+			*shard.get_mut().unwrap_or_else(PoisonError::into_inner) = RefTable::new();
+		}
+
+		let mut collisions = Vec::new();
+		for (r, value) in self.values.iter() {
+			let hash = self.hasher.hash_one(value);
+			let index = shard_index(hash, self.shards.len());
+			let table = self.shards[index]
+				.get_mut()
+				.unwrap_or_else(PoisonError::into_inner);
+
+			match table.find(&self.values, &self.hasher, hash, value) {
+				Some(existing) => collisions.push(Collision::new(r, existing)),
+				None => table.insert(hash, r),
+			}
+		}
+
+		collisions
+	}
+}
+
+impl<S: StorageMut, H: BuildHasher, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	/// Borrows a mutable reference to the definition (`T`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn borrow_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut T>
+	where
+		S::Value: BorrowMut<T>,
+	{
+		self.values.borrow_mut(r)
+	}
+
+	/// Get a mutable reference to the definition (`S::Value`) associated to the given term (`Ref<T, I>`).
+	///
+	/// The definition must not be modified in a way that changes its
+	/// hash or equality, otherwise the dictionary will not find it anymore.
+	/// Use [`Self::modify`] or [`Self::replace`] instead.
+	pub fn get_mut_unchecked<T>(&mut self, r: Ref<T, I>) -> Option<&mut S::Value>
+	where
+		S::Value: BorrowMut<T>,
+	{
+		self.values.get_mut(r)
+	}
+
+	/// Replaces the definition associated to the given term, and returns the
+	/// old definition.
+	///
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the new definition is already associated to another term, the
+	/// dictionary is left unchanged and a [`Collision`] is returned.
+	#[allow(clippy::type_complexity)]
+	pub fn replace(
+		&mut self,
+		r: Ref<S::Value, I>,
+		value: S::Value,
+	) -> Option<Result<S::Value, Collision<S::Value, I>>>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let old_hash = self.hasher.hash_one(self.values.get::<S::Value>(r)?);
+		let hash = self.hasher.hash_one(&value);
+		let index = self.shard_index(hash);
+		let table = self.shards[index]
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner);
+		if let Some(existing) = table.find(&self.values, &self.hasher, hash, &value) {
+			if existing != r {
+				return Some(Err(Collision::new(r, existing)));
+			}
+		}
+
+		self.shard_mut(old_hash).remove(old_hash, r);
+		let old = std::mem::replace(self.values.get_mut::<S::Value>(r).unwrap(), value);
+		let table = self.shards[index]
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner);
+		table.insert(hash, r);
+		Some(Ok(old))
+	}
+
+	/// Modifies the definition associated to the given term, keeping the
+	/// dictionary consistent.
+	///
+	/// The given function is applied to a copy of the definition, which then
+	/// replaces it as with [`Self::replace`].
+	/// The definition cannot be modified in place since it must be left
+	/// unchanged on collision, hence the `S::Value: Clone` bound. Use
+	/// [`Self::replace`] for definitions that cannot be cloned.
+	/// Returns `None` if the term is not in the dictionary.
+	/// If the modified definition is equal to the definition of another term,
+	/// the dictionary is left unchanged and a [`Collision`] is returned.
+	pub fn modify(
+		&mut self,
+		r: Ref<S::Value, I>,
+		f: impl FnOnce(&mut S::Value),
+	) -> Option<Result<(), Collision<S::Value, I>>>
+	where
+		S::Value: Clone,
+		E: Equivalence<S::Value>,
+	{
+		let mut value = self.values.get::<S::Value>(r)?.clone();
+		f(&mut value);
+		self.replace(r, value).map(|result| result.map(|_| ()))
+	}
+}
+
+impl<S: StorageAllocateConst, H: BuildHasher, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	/// Inserts a new value and returns its unique reference.
+	///
+	/// If the value is already registered, its old reference is returned
+	/// without overriding the old value.
+	///
+	/// This function can be called concurrently from multiple threads.
+	/// The shard of the value is only locked for writing to register the new
+	/// reference, without hashing or comparing values. If the shard was
+	/// modified since the value was looked up, the lookup starts over.
+	///
+	/// # Panics
+	///
+	/// Panics if the reference of a new value does not fit in the index type
+	/// `I`, leaving the dictionary unchanged. See [`Self::try_insert`].
+	pub fn insert(&self, value: S::Value) -> Ref<S::Value, I>
+	where
+		E: Equivalence<S::Value>,
+	{
+		self.try_insert(value).unwrap_or_else(|e| panic!("{}", e))
+	}
+
+	/// Inserts a new value and returns its unique reference, or returns the
+	/// value back in an [`InsertError`] if the reference of the value would
+	/// not fit in the index type `I`.
+	///
+	/// The dictionary is left unchanged on failure.
+	pub fn try_insert(&self, value: S::Value) -> Result<Ref<S::Value, I>, InsertError<S::Value>>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let hash = self.hasher.hash_one(&value);
+		loop {
+			let version = match self.search(hash, &value) {
+				Ok(r) => return Ok(r),
+				Err(version) => version,
+			};
+
+			let mut table = self.shards[self.shard_index(hash)]
+				.write()
+				.unwrap_or_else(PoisonError::into_inner);
+			if table.version() == version {
+				let r = self
+					.values
+					.try_insert_const(value)
+					.map_err(InsertError::overflow)?;
+				table.insert(hash, r);
+				return Ok(r);
+			}
+		}
+	}
+
+	/// Inserts all the given values and returns their unique references.
+	///
+	/// The returned vector contains the reference of each input value,
+	/// in order. Equal values are given the same reference.
+	pub fn insert_all<J>(&self, values: J) -> Vec<Ref<S::Value, I>>
+	where
+		J: IntoIterator<Item = S::Value>,
+		E: Equivalence<S::Value>,
+	{
+		values.into_iter().map(|value| self.insert(value)).collect()
+	}
+}
+
+impl<S: StorageRemove, H: BuildHasher, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	pub fn remove<T>(&mut self, r: Ref<T, I>) -> Option<S::Value>
+	where
+		S::Value: Borrow<T>,
+		E: Equivalence<S::Value>,
+	{
+		let value = self.values.remove(r)?;
+		let hash = self.hasher.hash_one(&value);
+		self.shard_mut(hash).remove(hash, r.cast());
+		Some(value)
+	}
+
+	#[allow(clippy::type_complexity)]
+	pub fn remove_value<T>(&mut self, value: &S::Value) -> Option<(Ref<S::Value, I>, S::Value)>
+	where
+		E: Equivalence<S::Value>,
+	{
+		let hash = self.hasher.hash_one(value);
+		let index = self.shard_index(hash);
+		let table = self.shards[index]
+			.get_mut()
+			.unwrap_or_else(PoisonError::into_inner);
+		let r = table.find(&self.values, &self.hasher, hash, value)?;
+		table.remove(hash, r);
+		Some((r, self.values.remove(r).unwrap()))
+	}
+}
+
+impl<S: StorageCompact, H, E, I: RefIndex> SyncHashConstDictionary<S, H, E, I> {
+	/// Moves the values of the dictionary so that they are densely packed,
+	/// updating the references of the dictionary accordingly.
+	///
+	/// Returns the table mapping the old references of the moved values to
+	/// their new references, to be applied to the references held outside of
+	/// the dictionary.
+	pub fn compact(&mut self) -> Remap<S::Value, I> {
+		let remap = self.values.compact();
+		for shard in self.shards.iter_mut() {
+			shard
+				.get_mut()
+				.unwrap_or_else(PoisonError::into_inner)
+				.remap(&remap);
+		}
+
+		remap
+	}
+}
+
+impl<'a, S: 'a + StorageIter, H, E, I: RefIndex> IntoIterator
+	for &'a SyncHashConstDictionary<S, H, E, I>
+{
+	type Item = (Ref<S::Value, I>, &'a S::Value);
+	type IntoIter = Iter<'a, S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.iter()
+	}
+}
+
+impl<S: StorageIntoIter, H, E, I: RefIndex> IntoIterator for SyncHashConstDictionary<S, H, E, I> {
+	type Item = (Ref<S::Value, I>, S::Value);
+	type IntoIter = IntoIter<S, I>;
+
+	fn into_iter(self) -> Self::IntoIter {
+		self.values.into_iter()
+	}
+}
+
+impl<S: StorageAllocateConst, H: BuildHasher, E, I: RefIndex> Extend<S::Value>
+	for SyncHashConstDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn extend<J: IntoIterator<Item = S::Value>>(&mut self, iter: J) {
+		for value in iter {
+			self.insert(value);
+		}
+	}
+}
+
+impl<S: StorageAllocateConst + Default, H: BuildHasher + Default, E: Default, I: RefIndex>
+	FromIterator<S::Value> for SyncHashConstDictionary<S, H, E, I>
+where
+	E: Equivalence<S::Value>,
+{
+	fn from_iter<J: IntoIterator<Item = S::Value>>(iter: J) -> Self {
+		let mut result = Self::default();
+		result.extend(iter);
+		result
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<S: Storage + serde::Serialize, H, E, I: RefIndex> serde::Serialize
+	for SyncHashConstDictionary<S, H, E, I>
+{
+	/// Serializes the underlying storage only.
+	fn serialize<T: serde::Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+		self.values.as_storage().serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de, S, H, E, I> serde::Deserialize<'de> for SyncHashConstDictionary<S, H, E, I>
+where
+	S: StorageIter + serde::Deserialize<'de>,
+	H: BuildHasher + Default,
+	E: Default,
+	I: RefIndex,
+	E: Equivalence<S::Value>,
+{
+	/// Deserializes the underlying storage and rebuilds the dictionary from it.
+	///
+	/// Fails if the same value is stored at two different indices.
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let mut result =
+			Self::with_equivalence(S::deserialize(deserializer)?, H::default(), E::default());
+		if let Some(collision) = result.rebuild_map().first() {
+			return Err(serde::de::Error::custom(format_args!(
+				"duplicate dictionary value at index {}",
+				collision.modified().index()
+			)));
+		}
+
+		Ok(result)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{InsertErrorKind, SyncAppendVec};
+
+	#[test]
+	fn concurrent_insert() {
+		let dictionary: SyncHashConstDictionary<SyncAppendVec<String>> =
+			SyncHashConstDictionary::with_shards(SyncAppendVec::new(), 4);
+
+		let refs: Vec<Vec<_>> = std::thread::scope(|scope| {
+			let threads: Vec<_> = (0..8)
+				.map(|t| {
+					let dictionary = &dictionary;
+					scope.spawn(move || {
+						(t * 50..t * 50 + 200)
+							.map(|i| (i, dictionary.insert((i % 300).to_string())))
+							.collect::<Vec<_>>()
+					})
+				})
+				.collect();
+
+			IntoIterator::into_iter(threads)
+				.map(|t| t.join().unwrap())
+				.collect()
+		});
+
+		let mut expected = vec![None; 300];
+		for (i, r) in IntoIterator::into_iter(refs).flatten() {
+			assert_eq!(dictionary.get(r), Some(&(i % 300).to_string()));
+			assert_eq!(*expected[i % 300].get_or_insert(r), r);
+		}
+
+		assert_eq!(dictionary.len(), 300);
+		assert_eq!(dictionary.as_shelf().len(), 300);
+		assert!(dictionary.validate().is_valid())
+	}
+
+	#[test]
+	fn index_overflow() {
+		let dictionary: SyncHashConstDictionary<
+			SyncAppendVec<u32>,
+			RandomState,
+			DefaultEquivalence,
+			u16,
+		> = SyncHashConstDictionary::default();
+		for i in 0..=u16::MAX as u32 {
+			dictionary.insert(i);
+		}
+
+		let overflow = dictionary.try_insert(u16::MAX as u32 + 1).unwrap_err();
+		assert_eq!(overflow.kind(), InsertErrorKind::Overflow);
+		assert_eq!(overflow.into_value(), u16::MAX as u32 + 1);
+
+		assert_eq!(dictionary.len(), u16::MAX as usize + 1);
+		assert!(!dictionary.contains(&(u16::MAX as u32 + 1)));
+		assert_eq!(dictionary.try_insert(7).unwrap().index(), 7);
+		assert!(dictionary.validate().is_valid())
+	}
+
+	#[test]
+	fn insert_all_deduplicates() {
+		let mut dictionary: SyncHashConstDictionary<SyncAppendVec<String>> =
+			SyncHashConstDictionary::default();
+		let refs = dictionary.insert_all(["a", "b", "a", "c", "b"].map(String::from));
+		let indices: Vec<_> = refs.as_slice().iter().map(Ref::index).collect();
+		assert_eq!(indices, [0, 1, 0, 2, 1]);
+		assert_eq!(dictionary.len(), 3);
+
+		dictionary.extend(["c", "d", "a", "d"].map(String::from));
+		assert_eq!(dictionary.len(), 4);
+		assert_eq!(dictionary.get_ref("d").map(|r| r.index()), Some(3));
+
+		let values: Vec<_> = dictionary
+			.iter()
+			.map(|(r, value)| (r.index(), value.as_str()))
+			.collect();
+		assert_eq!(values, [(0, "a"), (1, "b"), (2, "c"), (3, "d")]);
+
+		let collected: SyncHashConstDictionary<SyncAppendVec<String>> =
+			["b", "a", "b"].map(String::from).into_iter().collect();
+		assert_eq!(collected.len(), 2);
+		assert_eq!(collected.get_ref("b").map(|r| r.index()), Some(0));
+		assert_eq!(collected.get_ref("a").map(|r| r.index()), Some(1));
+		assert!(collected.validate().is_valid());
+	}
+
+	#[test]
+	fn compact_keeps_lookups() {
+		let mut dictionary: SyncHashConstDictionary<SyncAppendVec<String>> =
+			SyncHashConstDictionary::default();
+		let refs: Vec<_> = (0..20).map(|i| dictionary.insert(i.to_string())).collect();
+		for &r in refs.as_slice().iter().step_by(3) {
+			dictionary.remove(r);
+		}
+
+		let remap = dictionary.compact();
+		assert!(!remap.is_empty());
+		assert_eq!(dictionary.len(), 13);
+		for (i, &r) in refs.as_slice().iter().enumerate() {
+			let value = i.to_string();
+			if i % 3 == 0 {
+				assert_eq!(dictionary.get_ref(value.as_str()), None);
+			} else {
+				let r = remap.apply(r);
+				assert!(r.index() < 13);
+				assert_eq!(dictionary.get_ref(value.as_str()), Some(r));
+				assert_eq!(dictionary.get(r), Some(&value));
+			}
+		}
+
+		assert!(dictionary.validate().is_valid());
+		assert_eq!(dictionary.insert("0".to_string()).index(), 13);
+	}
+
+	#[test]
+	fn replace_and_modify_collisions() {
+		let mut dictionary: SyncHashConstDictionary<SyncAppendVec<String>> =
+			SyncHashConstDictionary::default();
+		let a = dictionary.insert("a".to_string());
+		let b = dictionary.insert("b".to_string());
+
+		let collision = dictionary.replace(a, "b".to_string()).unwrap().unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (a, b));
+		let collision = dictionary
+			.modify(b, |value| *value = "a".to_string())
+			.unwrap()
+			.unwrap_err();
+		assert_eq!((collision.modified(), collision.existing()), (b, a));
+
+		assert_eq!(dictionary.get(a).map(String::as_str), Some("a"));
+		assert_eq!(dictionary.get(b).map(String::as_str), Some("b"));
+		assert_eq!(dictionary.get_ref("a"), Some(a));
+		assert_eq!(dictionary.get_ref("b"), Some(b));
+		assert!(dictionary.validate().is_valid());
+
+		assert_eq!(
+			dictionary.replace(a, "a".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(
+			dictionary.replace(a, "c".to_string()).unwrap().unwrap(),
+			"a"
+		);
+		assert_eq!(dictionary.modify(b, |value| value.push('d')), Some(Ok(())));
+		assert_eq!(dictionary.replace(Ref::new(2), "e".to_string()), None);
+		assert_eq!(dictionary.modify(Ref::new(2), |_| unreachable!()), None);
+
+		assert_eq!(dictionary.len(), 2);
+		assert_eq!(dictionary.get_ref("a"), None);
+		assert_eq!(dictionary.get_ref("b"), None);
+		assert_eq!(dictionary.get_ref("c"), Some(a));
+		assert_eq!(dictionary.get_ref("bd"), Some(b));
+		assert!(dictionary.validate().is_valid());
+	}
+
+	#[test]
+	fn rebuild_corrupted_ref_table() {
+		let mut dictionary: SyncHashConstDictionary<SyncAppendVec<u32>> =
+			SyncHashConstDictionary::default();
+		let refs = dictionary.insert_all((0..500).map(|i| (i * 7919 % 1000) as u32 * 2));
+		assert_eq!(dictionary.len(), refs.len());
+
+		for &r in refs.as_slice().iter().step_by(7) {
+			*dictionary.get_mut_unchecked(r).unwrap() += 1001;
+		}
+
+		let report = dictionary.validate();
+		assert!(!report.is_valid());
+		assert_eq!(report.mismatched().len(), refs.len().div_ceil(7));
+		assert!(dictionary.rebuild_map().is_empty());
+		assert!(dictionary.validate().is_valid());
+		for (r, value) in dictionary.iter() {
+			assert_eq!(dictionary.get_ref(value), Some(r));
+		}
+
+		let (a, b) = (refs[0], refs[1]);
+		let value = *dictionary.get(b).unwrap();
+		*dictionary.get_mut_unchecked(a).unwrap() = value;
+		let collisions = dictionary.rebuild_map();
+		assert_eq!(collisions.len(), 1);
+		assert_eq!(collisions[0].modified(), a.max(b));
+		assert_eq!(collisions[0].existing(), a.min(b));
+		assert_eq!(dictionary.len(), refs.len() - 1);
+		assert_eq!(dictionary.get_ref(&value), Some(a.min(b)));
+		assert_eq!(dictionary.validate().missing(), &[a.max(b)]);
+	}
+}
@@ -0,0 +1,229 @@
+//! Validation of deserialized references.
+//!
+//! A deserialized `Ref<T>` is a plain index that may not resolve in the shelf
+//! it is meant to reference, for instance if the data is corrupted or has
+//! been serialized along with another shelf. The [`check_refs`] function and
+//! the [`Validator`] type can be used to deserialize data containing
+//! references while checking that every reference resolves in a given shelf:
+//!
+//! ```
+//! use shelves::validation::{check_refs, ValidationError};
+//! use shelves::{Ref, Shelf};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let nodes: Shelf<Vec<String>> = serde_json::from_str(r#"["a", "b"]"#)?;
+//!
+//! let edges: Vec<(Ref<String>, Ref<String>)> =
+//!     check_refs(&nodes, || serde_json::from_str("[[[0], [1]]]"))?;
+//! assert_eq!(nodes[edges[0].1], "b");
+//!
+//! let dangling: Result<Vec<(Ref<String>, Ref<String>)>, _> =
+//!     check_refs(&nodes, || serde_json::from_str("[[[0], [2]]]"));
+//! assert!(matches!(dangling, Err(ValidationError::Dangling(r)) if r.index() == 2));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Thread affinity
+//!
+//! The references are collected in a thread-local validation frame while
+//! the deserialization function runs. References deserialized on another
+//! thread, for instance by a parallel deserializer, are not checked.
+use crate::{RefIndex, Shelf, Storage};
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+
+thread_local! {
+	/// Number of validation frames of the current thread.
+	static DEPTH: Cell<usize> = const { Cell::new(0) };
+
+	/// Stack of the validation frames of the current thread.
+	static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Validation frame, collecting the deserialized references of the checked
+/// types.
+struct Frame {
+	types: Vec<TypeId>,
+	refs: Vec<(TypeId, usize)>,
+}
+
+/// Records the index of a deserialized `Ref<T>` in the innermost validation
+/// frame checking `T`, if any.
+///
+/// Does nothing outside of a validation frame.
+pub(crate) fn record<T: ?Sized>(index: usize) {
+	if DEPTH.get() == 0 {
+		return;
+	}
+
+	let type_id = typeid::of::<T>();
+	FRAMES.with(|frames| {
+		let mut frames = frames.borrow_mut();
+		if let Some(frame) = frames
+			.iter_mut()
+			.rev()
+			.find(|frame| frame.types.contains(&type_id))
+		{
+			frame.refs.push((type_id, index))
+		}
+	})
+}
+
+/// Pops the validation frame it was created with when dropped, even if the
+/// deserialization panics.
+struct FrameGuard;
+
+impl FrameGuard {
+	fn push(types: Vec<TypeId>) -> Self {
+		FRAMES.with(|frames| {
+			frames.borrow_mut().push(Frame {
+				types,
+				refs: Vec::new(),
+			})
+		});
+		DEPTH.set(DEPTH.get() + 1);
+
+		Self
+	}
+
+	fn refs(&self) -> Vec<(TypeId, usize)> {
+		FRAMES.with(|frames| std::mem::take(&mut frames.borrow_mut().last_mut().unwrap().refs))
+	}
+}
+
+impl Drop for FrameGuard {
+	fn drop(&mut self) {
+		DEPTH.set(DEPTH.get() - 1);
+		FRAMES.with(|frames| frames.borrow_mut().pop());
+	}
+}
+
+/// Reference validator.
+///
+/// Checks the references deserialized into one or more shelves.
+/// References to types that are not checked by the validator are not
+/// validated. Only the references deserialized on the current thread are
+/// checked.
+#[derive(Default)]
+pub struct Validator<'a> {
+	shelves: Vec<Checker<'a>>,
+}
+
+/// Checks that an index resolves in a shelf.
+struct Checker<'a> {
+	type_id: TypeId,
+	type_name: &'static str,
+	contains: Box<dyn 'a + Fn(usize) -> bool>,
+}
+
+impl<'a> Validator<'a> {
+	/// Creates a new validator, checking no reference.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Checks that every deserialized `Ref<S::Value>` resolves in the given
+	/// shelf.
+	pub fn with<S: Storage, I: RefIndex>(mut self, shelf: &'a Shelf<S, I>) -> Self {
+		self.shelves.push(Checker {
+			type_id: typeid::of::<S::Value>(),
+			type_name: std::any::type_name::<S::Value>(),
+			contains: Box::new(move |index| shelf.as_storage().get(index).is_some()),
+		});
+		self
+	}
+
+	/// Runs the given deserialization function, and checks that every
+	/// reference it deserialized resolves in its shelf.
+	///
+	/// Returns the first dangling reference, in deserialization order, if any.
+	/// The deserialization must happen on the current thread.
+	pub fn deserialize<R, E>(
+		&self,
+		f: impl FnOnce() -> Result<R, E>,
+	) -> Result<R, ValidationError<E>> {
+		let guard = FrameGuard::push(self.shelves.iter().map(|c| c.type_id).collect());
+		let result = f().map_err(ValidationError::Deserialization)?;
+
+		for (type_id, index) in guard.refs() {
+			let mut checkers = self.shelves.iter().filter(|c| c.type_id == type_id);
+			let type_name = checkers.clone().next().unwrap().type_name;
+			if !checkers.any(|c| (c.contains)(index)) {
+				return Err(ValidationError::Dangling(DanglingRef { index, type_name }));
+			}
+		}
+
+		Ok(result)
+	}
+}
+
+/// Runs the given deserialization function, and checks that every
+/// `Ref<S::Value>` it deserialized on the current thread resolves in the
+/// given shelf.
+pub fn check_refs<S: Storage, I: RefIndex, R, E>(
+	shelf: &Shelf<S, I>,
+	f: impl FnOnce() -> Result<R, E>,
+) -> Result<R, ValidationError<E>> {
+	Validator::new().with(shelf).deserialize(f)
+}
+
+/// Deserialized reference that does not resolve in its shelf.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DanglingRef {
+	index: usize,
+	type_name: &'static str,
+}
+
+impl DanglingRef {
+	/// Returns the index of the dangling reference.
+	pub fn index(&self) -> usize {
+		self.index
+	}
+
+	/// Returns the name of the referenced type.
+	pub fn type_name(&self) -> &'static str {
+		self.type_name
+	}
+}
+
+impl fmt::Display for DanglingRef {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"dangling reference to `{}` at index {}",
+			self.type_name, self.index
+		)
+	}
+}
+
+impl std::error::Error for DanglingRef {}
+
+/// Validating deserialization error.
+#[derive(Debug)]
+pub enum ValidationError<E> {
+	/// A deserialized reference does not resolve in its shelf.
+	Dangling(DanglingRef),
+
+	/// The deserialization itself failed.
+	Deserialization(E),
+}
+
+impl<E: fmt::Display> fmt::Display for ValidationError<E> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Dangling(e) => e.fmt(f),
+			Self::Deserialization(e) => e.fmt(f),
+		}
+	}
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for ValidationError<E> {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Dangling(_) => None,
+			Self::Deserialization(e) => e.source(),
+		}
+	}
+}
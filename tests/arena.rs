@@ -0,0 +1,90 @@
+#![cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use shelves::{FreeList, Ref, RefIndex, Shelf, StorageIter, VisitShelf};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Type(String);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Function {
+	name: String,
+	result: Ref<Type>,
+}
+
+shelves::arena! {
+	#[derive(Default, Serialize, Deserialize)]
+	struct Program {
+		types: Shelf<Vec<Type>> => Type,
+		#[serde(default)]
+		functions: Shelf<FreeList<Function>, u32> => Function,
+	}
+}
+
+/// Collects the JSON representation of every value of the visited shelves.
+struct Collect(Vec<(&'static str, String)>);
+
+impl<S: StorageIter, I: RefIndex> VisitShelf<S, I> for Collect
+where
+	S::Value: Serialize,
+{
+	fn visit(&mut self, name: &'static str, shelf: &Shelf<S, I>) {
+		for value in shelf.values() {
+			self.0.push((name, serde_json::to_string(value).unwrap()))
+		}
+	}
+}
+
+#[test]
+fn round_trip() {
+	let mut program = Program::default();
+	let unit = program.insert(Type("unit".to_string()));
+	let int = program.insert(Type("int".to_string()));
+	let main = program.insert(Function {
+		name: "main".to_string(),
+		result: unit,
+	});
+	let removed = program.insert(Function {
+		name: "removed".to_string(),
+		result: int,
+	});
+	let len = program.insert(Function {
+		name: "len".to_string(),
+		result: int,
+	});
+	program.remove(removed);
+
+	let json = serde_json::to_string(&program).unwrap();
+	let other: Program = serde_json::from_str(&json).unwrap();
+
+	assert_eq!(other.len(), 4);
+	assert_eq!(other.types, program.types);
+	assert_eq!(other.functions, program.functions);
+	assert_eq!(other.get(removed), None);
+	assert_eq!(other.get(main).unwrap().name, "main");
+
+	let result = other.get(len).unwrap().result;
+	assert_eq!(other.get(result), Some(&Type("int".to_string())));
+}
+
+#[test]
+fn visit_every_shelf() {
+	let mut program = Program::default();
+	let unit = program.insert(Type("unit".to_string()));
+	program.insert(Function {
+		name: "main".to_string(),
+		result: unit,
+	});
+
+	let mut collect = Collect(Vec::new());
+	program.for_each_shelf(&mut collect);
+	assert_eq!(
+		collect.0,
+		[
+			("types", "\"unit\"".to_string()),
+			(
+				"functions",
+				"{\"name\":\"main\",\"result\":[0]}".to_string()
+			)
+		]
+	)
+}
@@ -0,0 +1,87 @@
+#![cfg(feature = "mmap-storage")]
+use shelves::{MmapStorage, MmapWriter, Ref, Shelf};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+
+/// Temporary file, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+	fn new(name: &str) -> Self {
+		Self(std::env::temp_dir().join(format!("shelves-{}-{name}", std::process::id())))
+	}
+
+	fn write(&self, values: &[u32]) {
+		let mut writer = MmapWriter::new(File::create(&self.0).unwrap()).unwrap();
+		writer.write_storage(&values.to_vec()).unwrap();
+		writer.finish().unwrap();
+	}
+}
+
+impl Drop for TempFile {
+	fn drop(&mut self) {
+		fs::remove_file(&self.0).ok();
+	}
+}
+
+fn error_kind<T>(result: io::Result<T>) -> io::ErrorKind {
+	match result {
+		Ok(_) => panic!("expected an error"),
+		Err(e) => e.kind(),
+	}
+}
+
+#[test]
+fn write_and_map() {
+	let file = TempFile::new("write_and_map");
+	let mut values: Shelf<Vec<u32>> = Shelf::default();
+	let refs: Vec<Ref<u32>> = (0..100).map(|i| values.insert(i * 3)).collect();
+	file.write(values.as_storage());
+
+	let mapped: Shelf<MmapStorage<u32>> =
+		Shelf::new(unsafe { MmapStorage::open(&file.0) }.unwrap());
+	assert_eq!(mapped.len(), 100);
+	for r in refs {
+		assert_eq!(mapped.get::<u32>(r), values.get::<u32>(r))
+	}
+}
+
+#[test]
+fn header_mismatch() {
+	let file = TempFile::new("header_mismatch");
+	file.write(&[1, 2, 3]);
+
+	let result = unsafe { MmapStorage::<u64>::open(&file.0) };
+	assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+
+	fs::write(&file.0, b"NOT A SHELF FILE, BUT LONG ENOUGH").unwrap();
+	let result = unsafe { MmapStorage::<u32>::open(&file.0) };
+	assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn truncated() {
+	let file = TempFile::new("truncated");
+	file.write(&[1, 2, 3]);
+
+	let len = fs::metadata(&file.0).unwrap().len();
+	OpenOptions::new()
+		.write(true)
+		.open(&file.0)
+		.unwrap()
+		.set_len(len - 1)
+		.unwrap();
+
+	let result = unsafe { MmapStorage::<u32>::open(&file.0) };
+	assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+
+	OpenOptions::new()
+		.write(true)
+		.open(&file.0)
+		.unwrap()
+		.set_len(8)
+		.unwrap();
+	let result = unsafe { MmapStorage::<u32>::open(&file.0) };
+	assert_eq!(error_kind(result), io::ErrorKind::InvalidData);
+}
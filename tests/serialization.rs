@@ -0,0 +1,181 @@
+#![cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shelves::{
+	AppendVec, BTreeConstDictionary, BTreeDictionary, FreeList, HashConstDictionary,
+	HashDictionary, Interner, PersistentVec, Ref, Shelf, SparseSet, Storage, StorageIter,
+	SyncAppendVec, SyncHashConstDictionary,
+};
+
+/// Serializes the shelf along with its references, deserializes both back
+/// and checks that every reference resolves to the same value.
+fn round_trip<S>(shelf: &Shelf<S>, refs: &[Ref<S::Value>])
+where
+	S: StorageIter + Serialize + DeserializeOwned,
+	S::Value: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+	let json = serde_json::to_string(&(shelf, refs)).unwrap();
+	let (other, other_refs): (Shelf<S>, Vec<Ref<S::Value>>) = serde_json::from_str(&json).unwrap();
+
+	assert_eq!(other.len(), shelf.len());
+	assert_eq!(other_refs, refs);
+	for r in refs {
+		assert_eq!(other.get::<S::Value>(*r), shelf.get::<S::Value>(*r))
+	}
+}
+
+#[test]
+fn vec_round_trip() {
+	let mut shelf: Shelf<Vec<String>> = Shelf::default();
+	let refs: Vec<_> = ["a", "b", "c"]
+		.into_iter()
+		.map(|s| shelf.insert(s.to_string()))
+		.collect();
+
+	round_trip(&shelf, &refs)
+}
+
+#[cfg(feature = "slab-storage")]
+#[test]
+fn slab_round_trip() {
+	let mut shelf: Shelf<slab::Slab<String>> = Shelf::default();
+	let mut refs: Vec<_> = (0..10).map(|i| shelf.insert(i.to_string())).collect();
+	for r in refs.drain(2..5) {
+		shelf.remove(r);
+	}
+
+	round_trip(&shelf, &refs)
+}
+
+#[test]
+fn free_list_round_trip() {
+	let mut shelf: Shelf<FreeList<String>> = Shelf::default();
+	let mut refs: Vec<_> = (0..10).map(|i| shelf.insert(i.to_string())).collect();
+	for r in refs.drain(2..5) {
+		shelf.remove(r);
+	}
+	shelf.remove(refs.pop().unwrap());
+
+	round_trip(&shelf, &refs)
+}
+
+#[test]
+fn indexed_round_trip() {
+	#[derive(Serialize, Deserialize)]
+	struct Document {
+		#[serde(with = "shelves::serialization::indexed")]
+		nodes: Shelf<FreeList<String>>,
+	}
+
+	let mut nodes: Shelf<FreeList<String>> = Shelf::default();
+	let mut refs: Vec<_> = (0..10).map(|i| nodes.insert(i.to_string())).collect();
+	nodes.remove(refs.remove(0));
+	nodes.remove(refs.remove(4));
+	let expected: Vec<_> = refs.as_slice().iter().map(|r| nodes[*r].clone()).collect();
+
+	let json = serde_json::to_string(&Document { nodes }).unwrap();
+	let document: Document = serde_json::from_str(&json).unwrap();
+	assert_eq!(document.nodes.len(), refs.len());
+	for (r, value) in refs.into_iter().zip(expected) {
+		assert_eq!(document.nodes[r], value)
+	}
+}
+
+#[test]
+fn dense_index_bound() {
+	let far = format!("[[{}, 0]]", shelves::serialization::MAX_DENSE_HOLES * 4);
+	assert!(serde_json::from_str::<SparseSet<u32>>(&far).is_err());
+	assert!(serde_json::from_str::<FreeList<u32>>(&far).is_err());
+
+	let near = format!("[[{}, 0]]", shelves::serialization::MAX_DENSE_HOLES);
+	let list: FreeList<u32> = serde_json::from_str(&near).unwrap();
+	assert_eq!(list.get(shelves::serialization::MAX_DENSE_HOLES), Some(&0));
+
+	let sparse: PersistentVec<u32> = serde_json::from_str(&far).unwrap();
+	assert_eq!(sparse.len(), 1);
+	assert_eq!(
+		sparse.get(shelves::serialization::MAX_DENSE_HOLES * 4),
+		Some(&0)
+	)
+}
+
+/// Defines, for each dictionary type, a test serializing a dictionary with a
+/// removed value and deserializing it back, and a test deserializing a
+/// storage holding the same value twice.
+macro_rules! dictionary_round_trips {
+	($($round_trip:ident, $duplicates:ident: $ty:ident<$storage:ty>),*) => {
+		$(
+			#[test]
+			fn $round_trip() {
+				#[allow(unused_mut)]
+				let mut dictionary: $ty<$storage> = $ty::default();
+				let refs: Vec<_> = ["b", "a", "c", "d"]
+					.into_iter()
+					.map(|s| dictionary.insert(s.to_string()))
+					.collect();
+				dictionary.remove(refs[2]);
+
+				let json = serde_json::to_string(&dictionary).unwrap();
+				let other: $ty<$storage> = serde_json::from_str(&json).unwrap();
+				assert_eq!(other.len(), 3);
+				for (r, value) in dictionary.iter() {
+					assert_eq!(other.get(r), Some(value));
+					assert_eq!(other.get_ref(value.as_str()), Some(r));
+				}
+
+				assert!(!other.contains("c"));
+				assert!(other.validate().is_valid());
+			}
+
+			#[test]
+			fn $duplicates() {
+				let mut shelf: Shelf<$storage> = Shelf::default();
+				for value in ["a", "b", "a"] {
+					shelf.insert(value.to_string());
+				}
+
+				let json = serde_json::to_string(&shelf).unwrap();
+				let error = serde_json::from_str::<$ty<$storage>>(&json)
+					.err()
+					.unwrap();
+				assert!(error
+					.to_string()
+					.starts_with("duplicate dictionary value at index 2"));
+			}
+		)*
+	};
+}
+
+dictionary_round_trips! {
+	hash_dictionary_round_trip, hash_dictionary_duplicates: HashDictionary<FreeList<String>>,
+	btree_dictionary_round_trip, btree_dictionary_duplicates: BTreeDictionary<FreeList<String>>,
+	hash_const_dictionary_round_trip, hash_const_dictionary_duplicates:
+		HashConstDictionary<AppendVec<String>>,
+	btree_const_dictionary_round_trip, btree_const_dictionary_duplicates:
+		BTreeConstDictionary<AppendVec<String>>,
+	sync_hash_const_dictionary_round_trip, sync_hash_const_dictionary_duplicates:
+		SyncHashConstDictionary<SyncAppendVec<String>>
+}
+
+#[test]
+fn interner_round_trip() {
+	let interner: Interner = ["b", "a", "", "ab"].into_iter().collect();
+	let json = serde_json::to_string(&interner).unwrap();
+	assert_eq!(json, r#"["b","a","","ab"]"#);
+
+	let other: Interner = serde_json::from_str(&json).unwrap();
+	assert_eq!(other.len(), 4);
+	for (r, value) in &interner {
+		assert_eq!(other.get(value), Some(r));
+		assert_eq!(other.resolve(r), value);
+	}
+}
+
+#[test]
+fn interner_duplicates() {
+	let error = serde_json::from_str::<Interner>(r#"["a","b","a"]"#)
+		.err()
+		.unwrap();
+	assert!(error
+		.to_string()
+		.starts_with("duplicate interned string at index 2"));
+}